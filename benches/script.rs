@@ -0,0 +1,46 @@
+// Timings for running scripts whose arithmetic may go through the operator
+// rewrite: `cargo bench --bench script`.
+
+use std::time::{Duration, Instant};
+
+use truescad::luascad::eval;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "numeric loop",
+        "local n, x = 0, 1.5
+         for i = 1, 200000 do n = n + i * x - i end",
+    ),
+    (
+        "numeric function",
+        "local function f(a, b) return a * b + a - b end
+         local n = 0
+         for i = 1, 50000 do n = f(n, 0.5) + i end",
+    ),
+    (
+        "vector loop",
+        "local v = vec3(0, 0, 0)
+         for i = 1, 20000 do v = v + vec3(1, 2, 3) * 0.5 end",
+    ),
+];
+
+/// Best of `runs` timings of `f`.
+fn best(runs: usize, mut f: impl FnMut()) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for (name, script) in SCRIPTS {
+        let time = best(5, || {
+            eval(script).expect("script failed");
+        });
+        println!("{name:>16}: {:>8.2?}", time);
+    }
+}
@@ -0,0 +1,83 @@
+// Timings for tessellation and CPU evaluation of 65536 points:
+// `cargo bench --bench tessellate`.
+
+use std::time::{Duration, Instant};
+
+use truescad::luascad::{eval, merge_bodies};
+use truescad::tape::Tape;
+use truescad::tessellate_stl;
+
+const SCENES: &[(&str, &str)] = &[
+    ("sphere", "build(Sphere(2))"),
+    (
+        "36 boxes",
+        "local boxes = {}
+         for i = 0, 35 do
+             boxes[#boxes + 1] = Box(1, 1, 1):translate(i % 4 * 3, i // 4 % 3 * 3, i // 12 * 3)
+         end
+         build(Union(boxes))",
+    ),
+    (
+        "smooth blend",
+        "build(Difference({Union({Sphere(2), Box(3, 3, 3):translate(1.5, 0, 0)}, 0.5), Sphere(1)}, 0.2))",
+    ),
+];
+
+/// Best of `runs` timings of `f`.
+fn best(runs: usize, mut f: impl FnMut()) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for (name, script) in SCENES {
+        let (_, bodies, _) = eval(script).expect("scene failed");
+        let obj = merge_bodies(&bodies).unwrap();
+        let tess = best(5, || {
+            tessellate_stl(obj.as_ref()).unwrap();
+        });
+
+        let points: Vec<[f32; 3]> = (0..1 << 16)
+            .map(|i| {
+                [
+                    (i % 64) as f32 * 0.2 - 6.4,
+                    (i / 64 % 32) as f32 * 0.4 - 6.4,
+                    (i / 2048) as f32 * 0.4 - 6.4,
+                ]
+            })
+            .collect();
+        let mut out = vec![0.0; points.len()];
+        let tree = best(5, || {
+            for (o, &p) in out.iter_mut().zip(&points) {
+                *o = obj.eval(p);
+            }
+        });
+        let tape = Tape::compile(obj.as_ref());
+        let batch = best(5, || tape.eval_many(&points, &mut out));
+        // Brick-sized batches, as voxelization evaluates them.
+        let mut rows = Vec::new();
+        let bricks = best(5, || {
+            for (p, o) in points.chunks(512).zip(out.chunks_mut(512)) {
+                tape.eval_many_with(p, o, &mut rows);
+            }
+        });
+        println!(
+            "{name:>14}: tessellate {:>7.1} ms   eval {:>6.2} ms   \
+             tape batch {:>6.2} ms   512-point batches {:>6.2} ms",
+            ms(tess),
+            ms(tree),
+            ms(batch),
+            ms(bricks),
+        );
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1e3
+}
@@ -0,0 +1,165 @@
+use std::f32::consts::{PI, TAU};
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Closed interval `[lo, hi]` used for conservative range evaluation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+    pub lo: f32,
+    pub hi: f32,
+}
+
+impl Interval {
+    pub fn new(lo: f32, hi: f32) -> Self {
+        Interval { lo, hi }
+    }
+
+    pub fn point(v: f32) -> Self {
+        Interval { lo: v, hi: v }
+    }
+
+    pub fn width(self) -> f32 {
+        self.hi - self.lo
+    }
+
+    pub fn contains(self, v: f32) -> bool {
+        self.lo <= v && v <= self.hi
+    }
+
+    pub fn abs(self) -> Self {
+        if self.lo >= 0.0 {
+            self
+        } else if self.hi <= 0.0 {
+            -self
+        } else {
+            Interval::new(0.0, (-self.lo).max(self.hi))
+        }
+    }
+
+    pub fn sqr(self) -> Self {
+        let a = self.abs();
+        Interval::new(a.lo * a.lo, a.hi * a.hi)
+    }
+
+    pub fn sqrt(self) -> Self {
+        Interval::new(self.lo.max(0.0).sqrt(), self.hi.max(0.0).sqrt())
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        Interval::new(self.lo.min(other.lo), self.hi.min(other.hi))
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Interval::new(self.lo.max(other.lo), self.hi.max(other.hi))
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Interval::new(self.lo.min(other.lo), self.hi.max(other.hi))
+    }
+
+    pub fn cos(self) -> Self {
+        // Past ~1e6 an f32 cannot resolve multiples of π any more.
+        if self.width() >= TAU || self.lo.abs().max(self.hi.abs()) > 1e6 {
+            return Interval::new(-1.0, 1.0);
+        }
+        let (a, b) = (self.lo.cos(), self.hi.cos());
+        let mut r = Interval::new(a.min(b), a.max(b));
+        // Extrema at multiples of π inside the interval.
+        let first = (self.lo / PI).ceil();
+        let mut k = first;
+        while k * PI <= self.hi {
+            if (k as i64).rem_euclid(2) == 0 {
+                r.hi = 1.0;
+            } else {
+                r.lo = -1.0;
+            }
+            k += 1.0;
+        }
+        r
+    }
+
+    pub fn sin(self) -> Self {
+        (self - PI / 2.0).cos()
+    }
+}
+
+impl Add for Interval {
+    type Output = Interval;
+    fn add(self, o: Interval) -> Interval {
+        Interval::new(self.lo + o.lo, self.hi + o.hi)
+    }
+}
+
+impl Sub for Interval {
+    type Output = Interval;
+    fn sub(self, o: Interval) -> Interval {
+        Interval::new(self.lo - o.hi, self.hi - o.lo)
+    }
+}
+
+impl Mul for Interval {
+    type Output = Interval;
+    fn mul(self, o: Interval) -> Interval {
+        let p = [
+            self.lo * o.lo,
+            self.lo * o.hi,
+            self.hi * o.lo,
+            self.hi * o.hi,
+        ];
+        Interval::new(
+            p.iter().copied().fold(f32::INFINITY, f32::min),
+            p.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        )
+    }
+}
+
+impl Neg for Interval {
+    type Output = Interval;
+    fn neg(self) -> Interval {
+        Interval::new(-self.hi, -self.lo)
+    }
+}
+
+impl Add<f32> for Interval {
+    type Output = Interval;
+    fn add(self, v: f32) -> Interval {
+        Interval::new(self.lo + v, self.hi + v)
+    }
+}
+
+impl Sub<f32> for Interval {
+    type Output = Interval;
+    fn sub(self, v: f32) -> Interval {
+        Interval::new(self.lo - v, self.hi - v)
+    }
+}
+
+impl Mul<f32> for Interval {
+    type Output = Interval;
+    fn mul(self, v: f32) -> Interval {
+        if v >= 0.0 {
+            Interval::new(self.lo * v, self.hi * v)
+        } else {
+            Interval::new(self.hi * v, self.lo * v)
+        }
+    }
+}
+
+impl From<Interval> for (f32, f32) {
+    fn from(i: Interval) -> (f32, f32) {
+        (i.lo, i.hi)
+    }
+}
+
+impl From<(f32, f32)> for Interval {
+    fn from((lo, hi): (f32, f32)) -> Interval {
+        Interval::new(lo, hi)
+    }
+}
+
+/// Euclidean length of a vector whose components lie in the given intervals.
+pub fn length(v: &[Interval]) -> Interval {
+    v.iter()
+        .map(|c| c.sqr())
+        .fold(Interval::point(0.0), |a, b| a + b)
+        .sqrt()
+}
@@ -0,0 +1,136 @@
+use crate::primitive::{Bbox, Primitive, UNBOUNDED};
+use crate::primitives::Translate;
+
+// ── Layout ────────────────────────────────────────────────────────────────────
+// Placing objects relative to each other by their bounding boxes, for
+// `obj:align_to`, `obj:place_on`, `Stack` and `Distribute`. The boxes are the
+// conservative ones `Primitive::bbox` reports, so a rotated or smoothed object
+// is placed by a box that can be somewhat larger than its surface.
+
+const AXES: [&str; 3] = ["X", "Y", "Z"];
+
+/// A side of a box along one axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Anchor {
+    Min,
+    Center,
+    Max,
+}
+
+impl Anchor {
+    /// The anchor `name` means along `axis`: "min", "center" or "max", or
+    /// left/right along X, front/back along Y and bottom/top along Z.
+    pub fn parse(axis: usize, name: &str) -> Option<Anchor> {
+        match (axis, name) {
+            (_, "min") | (0, "left") | (1, "front") | (2, "bottom") => Some(Anchor::Min),
+            (_, "center") => Some(Anchor::Center),
+            (_, "max") | (0, "right") | (1, "back") | (2, "top") => Some(Anchor::Max),
+            _ => None,
+        }
+    }
+
+    /// Where this side of `b` lies along `axis`, unless it is at infinity.
+    fn of(self, b: &Bbox, axis: usize) -> Option<f32> {
+        let (lo, hi) = (b.min[axis], b.max[axis]);
+        match self {
+            Anchor::Min => (lo > -UNBOUNDED).then_some(lo),
+            Anchor::Center => (lo > -UNBOUNDED && hi < UNBOUNDED).then_some(0.5 * (lo + hi)),
+            Anchor::Max => (hi < UNBOUNDED).then_some(hi),
+        }
+    }
+}
+
+/// The axis `name` names: "x", "y" or "z".
+pub fn parse_axis(name: &str) -> Option<usize> {
+    ["x", "y", "z"].iter().position(|a| *a == name)
+}
+
+fn side(anchor: Anchor, b: &Bbox, axis: usize, what: &str) -> Result<f32, String> {
+    anchor
+        .of(b, axis)
+        .ok_or_else(|| format!("{what} is unbounded along {}", AXES[axis]))
+}
+
+fn translated(obj: Box<dyn Primitive>, t: [f32; 3]) -> Box<dyn Primitive> {
+    Box::new(Translate::new(obj, t))
+}
+
+/// `obj` moved so that its `anchors` meet the same anchors of `target`. Axes
+/// without an anchor are left as they are.
+pub fn align(
+    obj: Box<dyn Primitive>,
+    target: &Bbox,
+    anchors: [Option<Anchor>; 3],
+) -> Result<Box<dyn Primitive>, String> {
+    let b = obj.bbox();
+    let mut t = [0.0; 3];
+    for (axis, anchor) in anchors.into_iter().enumerate() {
+        if let Some(anchor) = anchor {
+            t[axis] = side(anchor, target, axis, "target")? - side(anchor, &b, axis, "object")?;
+        }
+    }
+    Ok(translated(obj, t))
+}
+
+/// `obj` moved to sit `gap` beyond the max side of `base` along `axis`,
+/// centred on it along the other two where both are bounded. Along those
+/// where one is not, such as a floor plane, `obj` stays where it is.
+pub fn place_on(
+    obj: Box<dyn Primitive>,
+    base: &Bbox,
+    axis: usize,
+    gap: f32,
+) -> Result<Box<dyn Primitive>, String> {
+    let b = obj.bbox();
+    let mut t = [0.0; 3];
+    for (i, t) in t.iter_mut().enumerate() {
+        *t = if i == axis {
+            side(Anchor::Max, base, i, "base")? + gap - side(Anchor::Min, &b, i, "object")?
+        } else {
+            match (Anchor::Center.of(base, i), Anchor::Center.of(&b, i)) {
+                (Some(to), Some(from)) => to - from,
+                _ => 0.0,
+            }
+        };
+    }
+    Ok(translated(obj, t))
+}
+
+/// `objects` one after the other along `axis`, `gap` apart and centred on the
+/// first one. The first stays where it is.
+pub fn stack(
+    objects: Vec<Box<dyn Primitive>>,
+    axis: usize,
+    gap: f32,
+) -> Result<Vec<Box<dyn Primitive>>, String> {
+    let mut placed: Vec<Box<dyn Primitive>> = Vec::with_capacity(objects.len());
+    for obj in objects {
+        let obj = match placed.last() {
+            Some(prev) => place_on(obj, &prev.bbox(), axis, gap)?,
+            None => obj,
+        };
+        placed.push(obj);
+    }
+    Ok(placed)
+}
+
+/// `objects` moved along `axis` so that their centres are `spacing` apart,
+/// starting from the centre of the first. The other axes are left as they are.
+pub fn distribute(
+    objects: Vec<Box<dyn Primitive>>,
+    axis: usize,
+    spacing: f32,
+) -> Result<Vec<Box<dyn Primitive>>, String> {
+    let Some(first) = objects.first() else {
+        return Ok(objects);
+    };
+    let start = side(Anchor::Center, &first.bbox(), axis, "object")?;
+    let mut placed = Vec::with_capacity(objects.len());
+    for (i, obj) in objects.into_iter().enumerate() {
+        let center = side(Anchor::Center, &obj.bbox(), axis, "object")?;
+        let mut t = [0.0; 3];
+        t[axis] = start + i as f32 * spacing - center;
+        placed.push(translated(obj, t));
+    }
+    Ok(placed)
+}
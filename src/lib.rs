@@ -1,76 +1,347 @@
+pub mod interval;
+pub mod layout;
 pub mod luascad;
+pub mod param;
 pub mod primitive;
 pub mod primitives;
 pub mod shader;
+pub mod tape;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::task::Poll;
 
 use js_sys::Uint8Array;
 use nalgebra as na;
 use tessellation::{ImplicitFunction, ManifoldDualContouring};
 use wasm_bindgen::prelude::*;
 
-use primitive::Primitive;
+use param::{Param, ParamKind, ParamValue};
+use primitive::{Bbox, Primitive, ShaderData};
 
 const TESSELLATION_RESOLUTION: f64 = 0.12;
 const TESSELLATION_ERROR: f64 = 2.0;
+/// View width used when the object extends to infinity.
+const UNBOUNDED_VIEW_WIDTH: f32 = 10.0;
 
 struct AppState {
+    /// The bodies the script built, and all of them merged into one object.
+    bodies: Vec<luascad::Body>,
     object: Option<Box<dyn Primitive>>,
+    /// The preview shader for `object`.
+    shader: Option<shader::Shader>,
     world_transform: na::Matrix4<f32>,
     object_width: f32,
+    /// The last script run, with its parameters and the values set by the host.
+    script: String,
+    params: Vec<Param>,
+    overrides: HashMap<String, ParamValue>,
+    /// Modules scripts can `require`, set by the host.
+    modules: luascad::Modules,
+    /// A live parameter moved since the script last ran, so `object` still has
+    /// the old value.
+    stale: bool,
+    /// A script started by `start_script` and not finished yet.
+    pending: Option<(String, luascad::Evaluation)>,
 }
 
 impl AppState {
     fn new() -> Self {
         AppState {
+            bodies: Vec::new(),
             object: None,
+            shader: None,
             world_transform: na::Matrix4::identity(),
             object_width: 1.0,
+            script: String::new(),
+            params: Vec::new(),
+            overrides: HashMap::new(),
+            modules: luascad::Modules::new(),
+            stale: false,
+            pending: None,
         }
     }
+
+    /// Re-run the script if a live parameter changed since it last ran. If the
+    /// run fails `object` stays stale, and the next refresh tries again.
+    fn refresh(&mut self) -> Result<(), luascad::EvalError> {
+        if !self.stale {
+            return Ok(());
+        }
+        let limits = luascad::Limits::default();
+        let evaluation =
+            luascad::Evaluation::new(&self.script, &self.modules, &self.overrides, limits);
+        let (_, bodies, params) = evaluation.finish()?;
+        self.set_bodies(bodies);
+        self.params = params;
+        self.stale = false;
+        Ok(())
+    }
+
+    fn set_bodies(&mut self, bodies: Vec<luascad::Body>) {
+        self.object = luascad::merge_bodies(&bodies);
+        self.shader = self.object.as_deref().map(shader::build_shader);
+        self.bodies = bodies;
+    }
 }
 
 thread_local! {
     static STATE: RefCell<AppState> = RefCell::new(AppState::new());
 }
 
-/// Evaluate a Lua script. Returns a JS object `{output: string, error: string|null}`.
+/// Evaluate a Lua script. Returns a JS object `{output: string, error: string|null,
+/// diagnostics: array}`; on errors `diagnostics` holds one entry locating it.
+/// Parameters keep the values set through `set_param` across runs.
 #[wasm_bindgen]
 pub fn run_script(code: &str) -> JsValue {
-    match luascad::eval(code) {
-        Ok((output, maybe_obj)) => {
+    start_script(code);
+    step_pending(u64::MAX)
+}
+
+/// Start evaluating a Lua script; `step_script` runs it.
+#[wasm_bindgen]
+pub fn start_script(code: &str) {
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let limits = luascad::Limits::default();
+        let evaluation = luascad::Evaluation::new(code, &state.modules, &state.overrides, limits);
+        state.pending = Some((code.to_string(), evaluation));
+    });
+}
+
+/// Run the script from `start_script` for about `fuel` Lua instructions, so the
+/// page stays responsive in between. Returns null while it is still running,
+/// then the same object as `run_script`. Throws unless `fuel` is a finite
+/// positive number.
+#[wasm_bindgen]
+pub fn step_script(fuel: f64) -> Result<JsValue, JsValue> {
+    if !fuel.is_finite() || fuel <= 0.0 {
+        return Err("fuel must be a finite positive number".into());
+    }
+    Ok(step_pending(fuel as u64))
+}
+
+fn step_pending(fuel: u64) -> JsValue {
+    let finished = STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let (code, evaluation) = state.pending.as_mut()?;
+        let Poll::Ready(result) = evaluation.step(fuel) else {
+            return None;
+        };
+        let code = std::mem::take(code);
+        state.pending = None;
+        Some((code, result))
+    });
+    let Some((code, result)) = finished else {
+        return JsValue::NULL;
+    };
+    match result {
+        Ok((mut output, bodies, params)) => {
+            let width = match luascad::merge_bodies(&bodies).map(|o| o.bbox().finite()) {
+                Some(Ok(b)) => b.width(),
+                Some(Err(e)) => {
+                    output.push_str(&format!("warning: {e}\n"));
+                    UNBOUNDED_VIEW_WIDTH
+                }
+                None => 1.0,
+            };
             STATE.with(|s| {
                 let mut state = s.borrow_mut();
-                state.object_width = maybe_obj
-                    .as_ref()
-                    .map(|o| o.bbox().width())
-                    .unwrap_or(1.0)
-                    .max(0.001);
+                state.object_width = width.max(0.001);
                 state.world_transform = na::Matrix4::identity();
-                state.object = maybe_obj;
+                state.set_bodies(bodies);
+                state
+                    .overrides
+                    .retain(|name, _| params.iter().any(|p| &p.name == name));
+                state.params = params;
+                state.script = code;
+                state.stale = false;
             });
             let result = js_sys::Object::new();
             js_sys::Reflect::set(&result, &"output".into(), &output.into()).unwrap();
             js_sys::Reflect::set(&result, &"error".into(), &JsValue::NULL).unwrap();
+            js_sys::Reflect::set(&result, &"diagnostics".into(), &js_sys::Array::new()).unwrap();
             result.into()
         }
         Err(e) => {
+            let diagnostics = js_sys::Array::of1(&diagnostic_to_js(&e.diagnostic()));
             let result = js_sys::Object::new();
             js_sys::Reflect::set(&result, &"output".into(), &"".into()).unwrap();
             js_sys::Reflect::set(&result, &"error".into(), &e.to_string().into()).unwrap();
+            js_sys::Reflect::set(&result, &"diagnostics".into(), &diagnostics).unwrap();
             result.into()
         }
     }
 }
 
+/// `{kind: "script", message, diagnostics}` for a script that failed when a
+/// live parameter made it run again.
+fn script_error_to_js(e: &luascad::EvalError) -> JsValue {
+    let diagnostics = js_sys::Array::of1(&diagnostic_to_js(&e.diagnostic()));
+    let err = js_sys::Object::new();
+    js_sys::Reflect::set(&err, &"kind".into(), &"script".into()).unwrap();
+    js_sys::Reflect::set(&err, &"message".into(), &e.to_string().into()).unwrap();
+    js_sys::Reflect::set(&err, &"diagnostics".into(), &diagnostics).unwrap();
+    err.into()
+}
+
+/// `{message, chunk, line, column, traceback, function, argument}`, with null
+/// for what is not known.
+fn diagnostic_to_js(d: &luascad::Diagnostic) -> JsValue {
+    let opt = |v: Option<u32>| v.map_or(JsValue::NULL, |v| v.into());
+    let text = |v: &Option<String>| v.as_deref().map_or(JsValue::NULL, |v| v.into());
+    let traceback: js_sys::Array = d.traceback.iter().map(|l| JsValue::from_str(l)).collect();
+    let o = js_sys::Object::new();
+    let set = |k: &str, v: JsValue| js_sys::Reflect::set(&o, &k.into(), &v).unwrap();
+    set("message", d.message.as_str().into());
+    set("chunk", text(&d.chunk));
+    set("line", opt(d.line));
+    set("column", opt(d.column));
+    set("traceback", traceback.into());
+    set("function", text(&d.function));
+    set("argument", opt(d.argument));
+    o.into()
+}
+
+/// Set the modules scripts can `require`, from an object mapping module names
+/// to Lua source. They apply from the next run.
+#[wasm_bindgen]
+pub fn set_modules(modules: JsValue) -> Result<(), JsValue> {
+    let modules: &js_sys::Object = modules.dyn_ref().ok_or("modules must be an object")?;
+    let mut map = luascad::Modules::new();
+    for entry in js_sys::Object::entries(modules).iter() {
+        let entry: js_sys::Array = entry.into();
+        let name = entry.get(0).as_string().unwrap_or_default();
+        let Some(source) = entry.get(1).as_string() else {
+            return Err(JsValue::from_str(&format!(
+                "module '{name}' must be a string"
+            )));
+        };
+        map.insert(name, source);
+    }
+    STATE.with(|s| s.borrow_mut().modules = map);
+    Ok(())
+}
+
 /// Returns the GLSL fragment shader source for the current scene, or null if no object is loaded.
 #[wasm_bindgen]
 pub fn get_shader_source() -> Option<String> {
     STATE.with(|s| {
-        let state = s.borrow();
-        let obj = state.object.as_ref()?;
-        Some(shader::build_fragment_shader(obj.as_ref()))
+        s.borrow()
+            .shader
+            .as_ref()
+            .map(|shader| shader.source.clone())
+    })
+}
+
+/// The data uniforms of the current shader, as an array of `{name, values,
+/// dims}`. `values` is a Float32Array; `dims` is null for a `vec4` array and
+/// `[x, y, z]` for a 3D texture with one float per texel.
+#[wasm_bindgen]
+pub fn get_shader_data() -> js_sys::Array {
+    STATE.with(|s| {
+        let list = js_sys::Array::new();
+        for (name, data) in s.borrow().shader.iter().flat_map(|shader| &shader.data) {
+            let (values, dims) = match data {
+                ShaderData::Vec4Array(values) => (values.as_flattened(), JsValue::NULL),
+                ShaderData::Texture3d { dims, values } => {
+                    let dims: js_sys::Array =
+                        dims.iter().map(|&n| JsValue::from(n as u32)).collect();
+                    (values.as_slice(), dims.into())
+                }
+            };
+            let o = js_sys::Object::new();
+            let set = |k: &str, v: JsValue| js_sys::Reflect::set(&o, &k.into(), &v).unwrap();
+            set("name", name.as_str().into());
+            set("values", js_sys::Float32Array::from(values).into());
+            set("dims", dims);
+            list.push(&o);
+        }
+        list
+    })
+}
+
+fn param_value_to_js(v: &ParamValue) -> JsValue {
+    match v {
+        ParamValue::Number(n) => (*n).into(),
+        ParamValue::Boolean(b) => (*b).into(),
+        ParamValue::Text(s) => s.as_str().into(),
+    }
+}
+
+fn param_value_from_js(v: &JsValue) -> Option<ParamValue> {
+    v.as_bool()
+        .map(ParamValue::Boolean)
+        .or_else(|| v.as_f64().map(ParamValue::Number))
+        .or_else(|| v.as_string().map(ParamValue::Text))
+}
+
+/// The parameters declared by the current script, as an array of
+/// `{name, type, uniform, value, default, min, max, step, options, description,
+/// group, live}`. `type` is number, integer, boolean, enum or string; fields a
+/// parameter does not have are null.
+#[wasm_bindgen]
+pub fn get_params() -> JsValue {
+    let opt = |v: Option<f64>| v.map_or(JsValue::NULL, |v| v.into());
+    let text = |v: &Option<String>| v.as_deref().map_or(JsValue::NULL, |v| v.into());
+    STATE.with(|s| {
+        let list = js_sys::Array::new();
+        for p in &s.borrow().params {
+            let o = js_sys::Object::new();
+            let set = |k: &str, v: JsValue| js_sys::Reflect::set(&o, &k.into(), &v).unwrap();
+            let (min, max, step) = match p.kind {
+                ParamKind::Number { min, max, step } => (min, max, step),
+                ParamKind::Integer { min, max } => {
+                    (min.map(|v| v as f64), max.map(|v| v as f64), Some(1.0))
+                }
+                _ => (None, None, None),
+            };
+            let options = match &p.kind {
+                ParamKind::Enum(options) => options
+                    .iter()
+                    .map(param_value_to_js)
+                    .collect::<js_sys::Array>()
+                    .into(),
+                _ => JsValue::NULL,
+            };
+            set("name", p.name.as_str().into());
+            set("type", p.kind.name().into());
+            set("uniform", p.uniform().into());
+            set("value", param_value_to_js(&p.value));
+            set("default", param_value_to_js(&p.default));
+            set("min", opt(min));
+            set("max", opt(max));
+            set("step", opt(step));
+            set("options", options);
+            set("description", text(&p.description));
+            set("group", text(&p.group));
+            set("live", p.live.into());
+            list.push(&o);
+        }
+        list.into()
+    })
+}
+
+/// Set parameter `name` for this and later runs. Numbers are clamped to the
+/// range and integers rounded; a value of the wrong type is an error.
+/// Returns true if the parameter is live: the shader stays valid and only its
+/// uniform needs the new value. Otherwise the script must be run again.
+#[wasm_bindgen]
+pub fn set_param(name: &str, value: JsValue) -> Result<bool, JsValue> {
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let state = &mut *state;
+        let Some(p) = state.params.iter_mut().find(|p| p.name == name) else {
+            return Err(JsValue::from_str(&format!("unknown parameter '{name}'")));
+        };
+        let Some(v) = param_value_from_js(&value).and_then(|v| p.accept(&v)) else {
+            let msg = format!("parameter '{name}' does not accept this value");
+            return Err(JsValue::from_str(&msg));
+        };
+        p.value = v.clone();
+        state.overrides.insert(p.name.clone(), v);
+        state.stale = true;
+        Ok(p.live)
     })
 }
 
@@ -94,8 +365,7 @@ pub fn get_object_width() -> f32 {
 pub fn rotate(dx: f64, dy: f64) {
     STATE.with(|s| {
         let mut state = s.borrow_mut();
-        let euler =
-            na::Rotation3::from_euler_angles(dy as f32, dx as f32, 0.).to_homogeneous();
+        let euler = na::Rotation3::from_euler_angles(dy as f32, dx as f32, 0.).to_homogeneous();
         state.world_transform = euler * state.world_transform;
     });
 }
@@ -111,69 +381,349 @@ pub fn pan(dx: f64, dy: f64) {
     });
 }
 
+/// Bake the current object into a voxel grid with `resolution` voxels along its
+/// longest axis and return the binary encoding, or `null` if no object is loaded,
+/// it is unbounded or the resolution is out of range. Throws `{kind: "script",
+/// message, diagnostics}` if a live parameter moved and the script then failed.
+#[wasm_bindgen]
+pub fn export_voxels(resolution: u32, sparse: bool) -> Result<Option<Uint8Array>, JsValue> {
+    STATE.with(|s| {
+        s.borrow_mut()
+            .refresh()
+            .map_err(|e| script_error_to_js(&e))?;
+        let state = s.borrow();
+        let Some(obj) = state.object.as_ref() else {
+            return Ok(None);
+        };
+        let field =
+            primitives::VoxelField::from_primitive(obj.as_ref(), resolution as usize, sparse);
+        Ok(field
+            .ok()
+            .map(|f| Uint8Array::from(f.to_bytes().as_slice())))
+    })
+}
+
 // ── Tessellation ──────────────────────────────────────────────────────────────
 
+/// Edge of the cubic regions the tape is pruned to, in tessellation cells.
+const PRUNE_REGION: f64 = 16.0;
+/// Tapes shorter than this are evaluated whole; pruning them costs more than
+/// it saves.
+const PRUNE_MIN_OPS: usize = 64;
+
+/// Dual contouring samples its value grid as an octree on the lattice
+/// `anchor + k·res`, anchored at its first query. Each cube it splits is
+/// sampled at its 8 corners `idx + {0,s}³`, and `s` is the lowest set bit of
+/// any of them but `idx`, whose value it already holds. When such a corner is
+/// first asked for, the adaptor evaluates the other seven as one batch on the
+/// tape and answers the remaining ones from `pending`. Queries off the lattice
+/// are answered one at a time and move the anchor. Long tapes are pruned to
+/// the PRUNE_REGION³ cube around the points, once per cube.
 struct TessAdaptor {
     object: Box<dyn Primitive>,
+    tape: tape::Tape,
+    res: f64,
+    /// Edge length of a pruning region.
+    region: f64,
+    anchor: Cell<Option<na::Point3<f64>>>,
+    /// Batched corners not yet asked for, by lattice index.
+    pending: RefCell<HashMap<[i64; 3], f32>>,
+    regions: RefCell<HashMap<[i64; 3], tape::Tape>>,
+    /// Scratch space for single-point and batch tape evaluation.
+    regs: RefCell<Vec<f32>>,
+    rows: RefCell<Vec<Vec<f32>>>,
+}
+
+impl TessAdaptor {
+    fn new(object: Box<dyn Primitive>, res: f64) -> Self {
+        TessAdaptor {
+            tape: tape::Tape::compile(object.as_ref()),
+            object,
+            res,
+            region: PRUNE_REGION * res,
+            anchor: Cell::new(None),
+            pending: RefCell::new(HashMap::new()),
+            regions: RefCell::new(HashMap::new()),
+            regs: RefCell::new(Vec::new()),
+            rows: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Lattice index of `p` relative to the current anchor, if it is on it.
+    fn lattice_key(&self, p: &na::Point3<f64>) -> Option<(na::Point3<f64>, [i64; 3])> {
+        let anchor = self.anchor.get()?;
+        let rel = (p - anchor) / self.res;
+        let key = [0, 1, 2].map(|a| rel[a].round() as i64);
+        (0..3)
+            .all(|a| (rel[a] - key[a] as f64).abs() < 1e-6)
+            .then_some((anchor, key))
+    }
+
+    /// The tape to evaluate `points` on: pruned to their region when they
+    /// share one and the tape is long enough to gain from it.
+    fn tape_for(&self, points: &[[f32; 3]], f: impl FnOnce(&tape::Tape)) {
+        let region = |p: &[f32; 3]| p.map(|c| (c as f64 / self.region).floor() as i64);
+        let key = region(&points[0]);
+        if self.tape.len() < PRUNE_MIN_OPS || points.iter().any(|p| region(p) != key) {
+            return f(&self.tape);
+        }
+        let mut regions = self.regions.borrow_mut();
+        let tape = regions.entry(key).or_insert_with(|| {
+            let corner = |offset: i64| key.map(|k| ((k + offset) as f64 * self.region) as f32);
+            let bounds = Bbox {
+                min: corner(0),
+                max: corner(1),
+            };
+            self.tape.prune(&bounds.expand(1e-3 * self.region as f32)).0
+        });
+        f(tape)
+    }
+
+    /// Evaluate the corners of the octree cube `key` belongs to, other than
+    /// its base corner, and return the value at `key`.
+    fn eval_corners(&self, anchor: na::Point3<f64>, key: [i64; 3]) -> f32 {
+        let s = 1 << (key[0] | key[1] | key[2]).trailing_zeros();
+        let base = key.map(|k| k & !s);
+        let keys: Vec<[i64; 3]> = (1..8)
+            .map(|i| [0, 1, 2].map(|a| base[a] + if i >> a & 1 == 1 { s } else { 0 }))
+            .collect();
+        let points: Vec<[f32; 3]> = keys
+            .iter()
+            .map(|k| [0, 1, 2].map(|a| (anchor[a] + k[a] as f64 * self.res) as f32))
+            .collect();
+        let mut values = vec![0.0; points.len()];
+        self.tape_for(&points, |tape| {
+            tape.eval_many_with(&points, &mut values, &mut self.rows.borrow_mut())
+        });
+        let mut pending = self.pending.borrow_mut();
+        let mut value = 0.0;
+        for (k, v) in keys.into_iter().zip(values) {
+            if k == key {
+                value = v;
+            } else {
+                pending.insert(k, v);
+            }
+        }
+        value
+    }
 }
 
 impl ImplicitFunction<f64> for TessAdaptor {
     fn value(&self, p: &na::Point3<f64>) -> f64 {
-        self.object.eval([p.x as f32, p.y as f32, p.z as f32]) as f64
+        match self.lattice_key(p) {
+            Some((anchor, key)) if key != [0; 3] => {
+                if let Some(v) = self.pending.borrow_mut().remove(&key) {
+                    return v as f64;
+                }
+                self.eval_corners(anchor, key) as f64
+            }
+            on_lattice => {
+                if on_lattice.is_none() {
+                    self.anchor.set(Some(*p));
+                    self.pending.borrow_mut().clear();
+                }
+                let q = [p.x as f32, p.y as f32, p.z as f32];
+                let mut value = 0.0;
+                self.tape_for(&[q], |tape| {
+                    value = tape.eval_with(q, &mut self.regs.borrow_mut())
+                });
+                value as f64
+            }
+        }
     }
     fn normal(&self, p: &na::Point3<f64>) -> na::Vector3<f64> {
-        let e = 0.001f32;
-        let [x, y, z] = [p.x as f32, p.y as f32, p.z as f32];
-        let dx = self.object.eval([x + e, y, z]) - self.object.eval([x - e, y, z]);
-        let dy = self.object.eval([x, y + e, z]) - self.object.eval([x, y - e, z]);
-        let dz = self.object.eval([x, y, z + e]) - self.object.eval([x, y, z - e]);
-        na::Vector3::new(dx as f64, dy as f64, dz as f64).normalize()
+        let g = self.object.gradient([p.x as f32, p.y as f32, p.z as f32]);
+        let [x, y, z] = primitive::normalize(g);
+        na::Vector3::new(x as f64, y as f64, z as f64)
     }
 }
 
-/// Tessellate the current object and return binary STL bytes.
-/// Returns `null` if no object is loaded.
+/// Cells along the longest axis beyond which tessellation is refused.
+const MAX_TESSELLATION_CELLS: f32 = 8192.0;
+
+/// Why an object could not be tessellated.
+#[derive(Debug, PartialEq)]
+pub enum TessellationError {
+    NoObject,
+    /// The script built no body with this name.
+    NoBody(String),
+    /// The object extends to infinity along the named axes.
+    Unbounded(String),
+    /// The bounding box is too wide to mesh at the tessellation resolution.
+    TooLarge {
+        width: f32,
+    },
+    /// Dual contouring found no surface.
+    Empty,
+}
+
+impl TessellationError {
+    /// Short machine-readable tag for the JS side.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TessellationError::NoObject => "no_object",
+            TessellationError::NoBody(_) => "no_body",
+            TessellationError::Unbounded(_) => "unbounded",
+            TessellationError::TooLarge { .. } => "too_large",
+            TessellationError::Empty => "empty",
+        }
+    }
+}
+
+impl std::fmt::Display for TessellationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TessellationError::NoObject => write!(f, "no object — run the script first"),
+            TessellationError::NoBody(name) => write!(f, "no body named '{name}'"),
+            TessellationError::Unbounded(e) => {
+                write!(
+                    f,
+                    "{e}; clip it with build(obj, {{bounds={{min={{..}}, max={{..}}}}}})"
+                )
+            }
+            TessellationError::TooLarge { width } => write!(
+                f,
+                "object is {width} units wide, more than {} cells at resolution {}",
+                MAX_TESSELLATION_CELLS, TESSELLATION_RESOLUTION
+            ),
+            TessellationError::Empty => write!(f, "object has no surface to tessellate"),
+        }
+    }
+}
+
+/// Check that `obj` has a finite bounding box small enough to mesh.
+pub fn tessellation_bounds(obj: &dyn Primitive) -> Result<primitive::Bbox, TessellationError> {
+    let b = obj.bbox().finite().map_err(TessellationError::Unbounded)?;
+    let width = b.width();
+    if width / TESSELLATION_RESOLUTION as f32 > MAX_TESSELLATION_CELLS {
+        return Err(TessellationError::TooLarge { width });
+    }
+    Ok(b)
+}
+
+/// Tessellate `obj` and encode the mesh as binary STL.
+pub fn tessellate_stl(obj: &dyn Primitive) -> Result<Vec<u8>, TessellationError> {
+    tessellation_bounds(obj)?;
+    let adaptor = TessAdaptor::new(obj.clone_box(), TESSELLATION_RESOLUTION);
+    let mut mdc =
+        ManifoldDualContouring::new(&adaptor, TESSELLATION_RESOLUTION, TESSELLATION_ERROR);
+    let mesh = mdc.tessellate().ok_or(TessellationError::Empty)?;
+
+    let triangles: Vec<stl_io::Triangle> = mesh
+        .faces
+        .iter()
+        .map(|face| {
+            let v: [[f32; 3]; 3] = std::array::from_fn(|i| {
+                let vi = face[i];
+                [
+                    mesh.vertices[vi][0] as f32,
+                    mesh.vertices[vi][1] as f32,
+                    mesh.vertices[vi][2] as f32,
+                ]
+            });
+            let a = na::Vector3::new(v[1][0] - v[0][0], v[1][1] - v[0][1], v[1][2] - v[0][2]);
+            let b = na::Vector3::new(v[2][0] - v[0][0], v[2][1] - v[0][1], v[2][2] - v[0][2]);
+            let n = a.cross(&b).normalize();
+            stl_io::Triangle {
+                normal: stl_io::Normal::new([n.x, n.y, n.z]),
+                vertices: [
+                    stl_io::Vertex::new(v[0]),
+                    stl_io::Vertex::new(v[1]),
+                    stl_io::Vertex::new(v[2]),
+                ],
+            }
+        })
+        .collect();
+
+    let mut buf = Vec::new();
+    stl_io::write_stl(&mut buf, triangles.iter()).map_err(|_| TessellationError::Empty)?;
+    Ok(buf)
+}
+
+fn tessellation_error_to_js(e: &TessellationError) -> JsValue {
+    let err = js_sys::Object::new();
+    js_sys::Reflect::set(&err, &"kind".into(), &e.kind().into()).unwrap();
+    js_sys::Reflect::set(&err, &"message".into(), &e.to_string().into()).unwrap();
+    err.into()
+}
+
+/// Tessellate the body named `body`, or all bodies merged into one if it is
+/// omitted, and return binary STL bytes.
+/// Throws `{kind, message}` if there is no such object or it cannot be meshed,
+/// and a script error as `export_voxels` does.
 #[wasm_bindgen]
-pub fn tessellate() -> Option<Uint8Array> {
+pub fn tessellate(body: Option<String>) -> Result<Uint8Array, JsValue> {
     STATE.with(|s| {
+        s.borrow_mut()
+            .refresh()
+            .map_err(|e| script_error_to_js(&e))?;
         let state = s.borrow();
-        let obj = state.object.as_ref()?.clone_box();
-        let adaptor = TessAdaptor { object: obj };
-        let mut mdc = ManifoldDualContouring::new(
-            &adaptor,
-            TESSELLATION_RESOLUTION,
-            TESSELLATION_ERROR,
-        );
-        let mesh = mdc.tessellate()?;
-
-        let triangles: Vec<stl_io::Triangle> = mesh
-            .faces
+        let obj = match &body {
+            Some(name) => state
+                .bodies
+                .iter()
+                .find(|b| &b.name == name)
+                .map(|b| b.object.as_ref())
+                .ok_or_else(|| TessellationError::NoBody(name.clone())),
+            None => state.object.as_deref().ok_or(TessellationError::NoObject),
+        };
+        match obj.and_then(tessellate_stl) {
+            Ok(buf) => Ok(Uint8Array::from(buf.as_slice())),
+            Err(e) => Err(tessellation_error_to_js(&e)),
+        }
+    })
+}
+
+/// Tessellate each body separately. Returns an array of `{name, stl}` in build
+/// order; throws `{kind, message, body}` for the first body that cannot be
+/// meshed, and a script error as `export_voxels` does.
+#[wasm_bindgen]
+pub fn tessellate_bodies() -> Result<js_sys::Array, JsValue> {
+    STATE.with(|s| {
+        s.borrow_mut()
+            .refresh()
+            .map_err(|e| script_error_to_js(&e))?;
+        let state = s.borrow();
+        if state.bodies.is_empty() {
+            return Err(tessellation_error_to_js(&TessellationError::NoObject));
+        }
+        let meshes = js_sys::Array::new();
+        for body in &state.bodies {
+            let stl = tessellate_stl(body.object.as_ref()).map_err(|e| {
+                let err = tessellation_error_to_js(&e);
+                js_sys::Reflect::set(&err, &"body".into(), &body.name.as_str().into()).unwrap();
+                err
+            })?;
+            let mesh = js_sys::Object::new();
+            js_sys::Reflect::set(&mesh, &"name".into(), &body.name.as_str().into()).unwrap();
+            js_sys::Reflect::set(&mesh, &"stl".into(), &Uint8Array::from(stl.as_slice())).unwrap();
+            meshes.push(&mesh);
+        }
+        Ok(meshes)
+    })
+}
+
+/// The bodies of the current scene as an array of `{name, color}` in build
+/// order, `color` being `[r, g, b]` or null.
+#[wasm_bindgen]
+pub fn get_bodies() -> js_sys::Array {
+    STATE.with(|s| {
+        let state = s.borrow();
+        state
+            .bodies
             .iter()
-            .map(|face| {
-                let v: [[f32; 3]; 3] = std::array::from_fn(|i| {
-                    let vi = face[i];
-                    [
-                        mesh.vertices[vi][0] as f32,
-                        mesh.vertices[vi][1] as f32,
-                        mesh.vertices[vi][2] as f32,
-                    ]
+            .map(|b| {
+                let color = b.color.map_or(JsValue::NULL, |c| {
+                    c.iter()
+                        .map(|&v| JsValue::from_f64(v as f64))
+                        .collect::<js_sys::Array>()
+                        .into()
                 });
-                let a = na::Vector3::new(v[1][0] - v[0][0], v[1][1] - v[0][1], v[1][2] - v[0][2]);
-                let b = na::Vector3::new(v[2][0] - v[0][0], v[2][1] - v[0][1], v[2][2] - v[0][2]);
-                let n = a.cross(&b).normalize();
-                stl_io::Triangle {
-                    normal: stl_io::Normal::new([n.x, n.y, n.z]),
-                    vertices: [
-                        stl_io::Vertex::new(v[0]),
-                        stl_io::Vertex::new(v[1]),
-                        stl_io::Vertex::new(v[2]),
-                    ],
-                }
+                let o = js_sys::Object::new();
+                js_sys::Reflect::set(&o, &"name".into(), &b.name.as_str().into()).unwrap();
+                js_sys::Reflect::set(&o, &"color".into(), &color).unwrap();
+                JsValue::from(o)
             })
-            .collect();
-
-        let mut buf = Vec::new();
-        stl_io::write_stl(&mut buf, triangles.iter()).ok()?;
-        Some(Uint8Array::from(buf.as_slice()))
+            .collect()
     })
 }
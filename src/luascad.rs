@@ -1,19 +1,86 @@
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::sync::{Arc, Mutex};
+use std::task::Poll;
 
+use gc_arena::{Collect, Rootable};
+use nalgebra as na;
+use piccolo::compiler::parser::{
+    AssignmentStatement, AssignmentTarget, BinaryOperator, Block, CallSuffix, Chunk,
+    ConstructorField, Expression, FieldSuffix, ForStatement, FunctionDefinition, HeadExpression,
+    LineAnnotated, LocalStatement, PrimaryExpression, RecordKey, SimpleExpression, Statement,
+    SuffixPart, SuffixedExpression, TableConstructor, UnaryOperator,
+};
+use piccolo::compiler::{FunctionRef, LineNumber, StringInterner, compile_chunk, parse_chunk};
 use piccolo::{
-    Callback, CallbackReturn, Closure, Context, Executor, IntoValue, Lua, MetaMethod, Table,
-    UserData, Value,
+    BoxSequence, Callback, CallbackReturn, Closure, Context, Error, Execution, Executor, FromValue,
+    Fuel, Function, FunctionPrototype, IntoValue, Lua, MetaMethod, PrototypeError, Sequence,
+    SequencePoll, Stack, StashedExecutor, StaticError, Table, TypeError, UserData, Value, raw_ops,
 };
 
-use crate::primitive::Primitive;
+use crate::layout::{self, Anchor};
+use crate::param::{Param, ParamKind, ParamValue, Scalar};
+use crate::primitive::{GlslCtx, Mark, Primitive, UNBOUNDED};
 use crate::primitives::{
-    Bender, Difference, InfCone, InfCylinder, Intersection, NormalPlane, PlaneNegX, PlaneNegY,
-    PlaneNegZ, PlaneX, PlaneY, PlaneZ, Rotate, Scale, Sphere, Translate, Twister, Union,
+    Affine, Bender, Blobs, Complement, Difference, Gear, InfCone, InfCylinder, Intersection,
+    Marker, Material, Minkowski, MinkowskiKernel, NormalPlane, PlaneNegX, PlaneNegY, PlaneNegZ,
+    PlaneX, PlaneY, PlaneZ, Rotate, Scale, Sphere, Translate, Twister, Union, VoxelField,
 };
 
 const EPSILON: f64 = f64::EPSILON;
+const DEFAULT_VOXEL_RESOLUTION: i64 = 32;
+
+/// Module sources by the name `require` takes.
+pub type Modules = HashMap<String, String>;
+
+/// The printed output, the bodies built in the order they were first built,
+/// and the declared parameters.
+pub type EvalResult = Result<(String, Vec<Body>, Vec<Param>), EvalError>;
+
+/// Name of the body `build` makes when it is given no name.
+pub const DEFAULT_BODY: &str = "model";
+
+/// An object passed to `build`, under the name the script gave it. Building
+/// under a name again replaces that body.
+pub struct Body {
+    pub name: String,
+    pub object: Box<dyn Primitive>,
+    /// RGB in [0, 1], from the `color` option of `build`.
+    pub color: Option<[f32; 3]>,
+}
+
+impl Clone for Body {
+    fn clone(&self) -> Self {
+        Body {
+            name: self.name.clone(),
+            object: self.object.clone_box(),
+            color: self.color,
+        }
+    }
+}
+
+/// All of `bodies` as one object, None if there are none.
+pub fn merge_bodies(bodies: &[Body]) -> Option<Box<dyn Primitive>> {
+    match bodies {
+        [] => None,
+        [body] => Some(body.shaded()),
+        _ => Some(Box::new(Union::new(
+            bodies.iter().map(Body::shaded).collect(),
+            0.0,
+        ))),
+    }
+}
 
-pub type EvalResult = Result<(String, Option<Box<dyn Primitive>>), piccolo::StaticError>;
+impl Body {
+    /// The object, in its colour if it has one.
+    fn shaded(&self) -> Box<dyn Primitive> {
+        match self.color {
+            Some(color) => Box::new(Material::new(self.object.clone_box(), color, 0.0)),
+            None => self.object.clone_box(),
+        }
+    }
+}
 
 /// Lua-visible wrapper around a Primitive.
 pub struct LObject(pub Option<Box<dyn Primitive>>);
@@ -24,8 +91,109 @@ impl LObject {
     }
 }
 
+/// Parameters declared so far in a run, with the values the host asked for.
+#[derive(Default)]
+struct ParamRegistry {
+    overrides: HashMap<String, ParamValue>,
+    declared: Vec<Param>,
+}
+
+type SharedParams = Arc<Mutex<ParamRegistry>>;
+
+/// State of the run the callbacks reach: the parameters, for those that bake
+/// some of them, and the call tracker, for those that blame an argument.
+#[derive(Default, Collect)]
+#[collect(require_static)]
+struct RunShared {
+    params: OnceCell<SharedParams>,
+    tracker: OnceCell<SharedTracker>,
+}
+
+/// Note that the values of `params` went somewhere other than a Scalar field,
+/// so the shader no longer follows them: they are not live.
+fn bake(ctx: Context<'_>, params: &[String]) {
+    let Some(registry) = ctx.singleton::<Rootable![RunShared]>().params.get() else {
+        return;
+    };
+    for p in registry.lock().unwrap().declared.iter_mut() {
+        if params.contains(&p.name) {
+            p.live = false;
+        }
+    }
+}
+
+/// Note that the script measured `obj`: the parameters its shader reads are
+/// baked into the result.
+fn bake_object(ctx: Context<'_>, obj: &dyn Primitive) {
+    let Some(registry) = ctx.singleton::<Rootable![RunShared]>().params.get() else {
+        return;
+    };
+    if !registry.lock().unwrap().declared.iter().any(|p| p.live) {
+        return;
+    }
+    let mut glsl = GlslCtx::new();
+    glsl.child(obj, "p");
+    bake(ctx, &glsl.params);
+}
+
+/// A live number parameter, or arithmetic on live parameters, as the script
+/// holds it: the Scalar reading the parameter uniforms.
+#[derive(Clone)]
+struct LScalar(Scalar);
+
+/// The Scalar held by `v`, if it is a live number. Its parameters stay live.
+fn live_scalar<'gc>(v: Value<'gc>) -> Option<&'gc Scalar> {
+    match v {
+        Value::UserData(ud) => ud.downcast_static::<LScalar>().ok().map(|s| &s.0),
+        _ => None,
+    }
+}
+
+/// The value of `v` if it is a live number, which bakes its parameters.
+fn live_value(ctx: Context<'_>, v: Value<'_>) -> Option<f64> {
+    let s = live_scalar(v)?;
+    bake(ctx, s.params());
+    Some(s.value as f64)
+}
+
+fn scalar_value(ctx: Context<'_>, s: Scalar) -> Value<'_> {
+    let ud = UserData::new_static(&ctx, LScalar(s));
+    if let Value::Table(mt) = ctx.get_global("__lscalar_mt") {
+        ud.set_metatable(&ctx, Some(mt));
+    }
+    ud.into()
+}
+
+/// Factories whose primitives keep Scalar fields take their numbers as Scalar:
+/// a live number keeps the parameters it reads, a plain number is a constant.
+impl<'gc> FromValue<'gc> for Scalar {
+    fn from_value(ctx: Context<'gc>, value: Value<'gc>) -> Result<Self, TypeError> {
+        match live_scalar(value) {
+            Some(s) => Ok(s.clone()),
+            None => Ok(Scalar::constant(f32::from_value(ctx, value)?)),
+        }
+    }
+}
+
 // ── internal helpers ──────────────────────────────────────────────────────────
 
+/// Intersect `obj` with the box `[min, max]` so that infinite geometry gets a
+/// finite extent.
+fn clip_to_bounds(obj: Box<dyn Primitive>, min: [f32; 3], max: [f32; 3]) -> Box<dyn Primitive> {
+    Box::new(Intersection::new(
+        vec![
+            obj,
+            Box::new(PlaneX::new(max[0])),
+            Box::new(PlaneNegX::new(-min[0])),
+            Box::new(PlaneY::new(max[1])),
+            Box::new(PlaneNegY::new(-min[1])),
+            Box::new(PlaneZ::new(max[2])),
+            Box::new(PlaneNegZ::new(-min[2])),
+        ],
+        0.0,
+    ))
+}
+
 fn wrap_object(ctx: Context<'_>, obj: LObject) -> Value<'_> {
     let ud = UserData::new_static(&ctx, obj);
     if let Value::Table(mt) = ctx.get_global("__lobj_mt") {
@@ -34,6 +202,17 @@ fn wrap_object(ctx: Context<'_>, obj: LObject) -> Value<'_> {
     ud.into()
 }
 
+/// An error blaming argument `n` of the traced call in progress, like
+/// `__argerror`.
+fn argument_error<'gc>(ctx: Context<'gc>, n: u32, message: &str) -> piccolo::Error<'gc> {
+    if let Some(tracker) = ctx.singleton::<Rootable![RunShared]>().tracker.get() {
+        let mut tracker = tracker.lock().unwrap();
+        tracker.argument = Some((tracker.calls.len(), n));
+    }
+    ctx.intern(message.as_bytes()).into_value(ctx).into()
+}
+
+/// The objects in `table`, the first argument of the call in progress.
 fn objects_from_table<'gc>(
     ctx: Context<'gc>,
     table: Table<'gc>,
@@ -41,18 +220,26 @@ fn objects_from_table<'gc>(
     let mut objects = Vec::new();
     let len = table.length() as usize;
     for i in 1..=len {
-        let val = table.get(ctx, i as i64);
-        match val {
-            Value::UserData(ud) => {
-                let obj = ud
-                    .downcast_static::<LObject>()
-                    .map_err(|_| "expected LObject in list".into_value(ctx))?;
-                match &obj.0 {
-                    Some(o) => objects.push(o.clone_box()),
-                    None => return Err("nil object in list".into_value(ctx).into()),
-                }
+        let object = match table.get(ctx, i as i64) {
+            Value::UserData(ud) => ud.downcast_static::<LObject>().ok(),
+            _ => None,
+        };
+        match object.map(|o| &o.0) {
+            Some(Some(o)) => objects.push(o.clone_box()),
+            Some(None) => {
+                return Err(argument_error(
+                    ctx,
+                    1,
+                    &format!("nil object at position {i} in list"),
+                ));
+            }
+            None => {
+                return Err(argument_error(
+                    ctx,
+                    1,
+                    &format!("expected an object at position {i} in list"),
+                ));
             }
-            _ => return Err("expected LObject in list".into_value(ctx).into()),
         }
     }
     Ok(objects)
@@ -69,12 +256,13 @@ fn setup_methods_metatable(ctx: Context<'_>) {
             "translate",
             Callback::from_fn(&ctx, |ctx, _, mut stack| {
                 let ud: UserData = stack.from_front(ctx)?;
-                let (x, y, z): (f64, f64, f64) = stack.consume(ctx)?;
+                let t: [Scalar; 3] = xyz_args(ctx, &mut stack, "translate")?;
                 let obj = ud.downcast_static::<LObject>()?;
-                let new_obj = LObject(obj.0.as_ref().map(|o| {
-                    Box::new(Translate::new(o.clone_box(), [x as f32, y as f32, z as f32]))
-                        as Box<dyn Primitive>
-                }));
+                let new_obj = LObject(
+                    obj.0
+                        .as_ref()
+                        .map(|o| Box::new(Translate::new(o.clone_box(), t)) as Box<dyn Primitive>),
+                );
                 stack.replace(ctx, wrap_object(ctx, new_obj));
                 Ok(CallbackReturn::Return)
             }),
@@ -87,7 +275,7 @@ fn setup_methods_metatable(ctx: Context<'_>) {
             "rotate",
             Callback::from_fn(&ctx, |ctx, _, mut stack| {
                 let ud: UserData = stack.from_front(ctx)?;
-                let (x, y, z): (f64, f64, f64) = stack.consume(ctx)?;
+                let [x, y, z]: [f64; 3] = xyz_args(ctx, &mut stack, "rotate")?;
                 let obj = ud.downcast_static::<LObject>()?;
                 let new_obj = LObject(obj.0.as_ref().map(|o| {
                     Box::new(Rotate::new(o.clone_box(), [x as f32, y as f32, z as f32]))
@@ -105,7 +293,7 @@ fn setup_methods_metatable(ctx: Context<'_>) {
             "scale",
             Callback::from_fn(&ctx, |ctx, _, mut stack| {
                 let ud: UserData = stack.from_front(ctx)?;
-                let (x, y, z): (f64, f64, f64) = stack.consume(ctx)?;
+                let [x, y, z]: [f64; 3] = xyz_args(ctx, &mut stack, "scale")?;
                 let obj = ud.downcast_static::<LObject>()?;
                 let new_obj = LObject(obj.0.as_ref().map(|o| {
                     Box::new(Scale::new(o.clone_box(), [x as f32, y as f32, z as f32]))
@@ -131,9 +319,413 @@ fn setup_methods_metatable(ctx: Context<'_>) {
         )
         .unwrap();
 
+    // obj:bbox(): {min=vec3, max=vec3}, at infinity where obj is unbounded
+    methods
+        .set(
+            ctx,
+            "bbox",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let ud: UserData = stack.consume(ctx)?;
+                let (min, max) = bounds(primitive_of(ctx, ud)?);
+                let bbox = Table::new(&ctx);
+                bbox.set(ctx, "min", Operand::Vec3(min).into_value(ctx))
+                    .unwrap();
+                bbox.set(ctx, "max", Operand::Vec3(max).into_value(ctx))
+                    .unwrap();
+                stack.replace(ctx, bbox);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "size",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let ud: UserData = stack.consume(ctx)?;
+                let (min, max) = bounds(primitive_of(ctx, ud)?);
+                stack.replace(ctx, Operand::Vec3(max - min).into_value(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "center",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let ud: UserData = stack.consume(ctx)?;
+                let bbox = primitive_of(ctx, ud)?
+                    .bbox()
+                    .finite()
+                    .map_err(|e| e.into_value(ctx))?;
+                let center = bbox.center().map(f64::from);
+                stack.replace(ctx, Operand::Vec3(center.into()).into_value(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // obj:distance(x, y, z): the signed distance at a point, negative inside
+    methods
+        .set(
+            ctx,
+            "distance",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let ud: UserData = stack.from_front(ctx)?;
+                let p: [f32; 3] = xyz_args(ctx, &mut stack, "distance")?;
+                stack.replace(ctx, primitive_of(ctx, ud)?.eval(p) as f64);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // obj:inside(x, y, z): whether a point is inside or on the surface
+    methods
+        .set(
+            ctx,
+            "inside",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let ud: UserData = stack.from_front(ctx)?;
+                let p: [f32; 3] = xyz_args(ctx, &mut stack, "inside")?;
+                stack.replace(ctx, primitive_of(ctx, ud)?.eval(p) <= 0.0);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // obj:align_to(other, {x="center", z="top"}): obj moved so that the named
+    // sides of its bbox meet those of other's; without anchors it is centred
+    methods
+        .set(
+            ctx,
+            "align_to",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (ud, other, anchors): (UserData, UserData, Option<Table>) =
+                    stack.consume(ctx)?;
+                let anchors = match anchors {
+                    Some(t) => anchors_from_table(ctx, t)?,
+                    None => [Some(Anchor::Center); 3],
+                };
+                let target = primitive_of(ctx, other)?.bbox();
+                let obj = primitive_of(ctx, ud)?.clone_box();
+                let obj = layout::align(obj, &target, anchors).map_err(|e| e.into_value(ctx))?;
+                stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // obj:place_on(other, gap?): obj standing on top of other, centred on it
+    methods
+        .set(
+            ctx,
+            "place_on",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (ud, other, gap): (UserData, UserData, Option<f64>) = stack.consume(ctx)?;
+                let base = primitive_of(ctx, other)?.bbox();
+                let obj = primitive_of(ctx, ud)?.clone_box();
+                let obj = layout::place_on(obj, &base, 2, gap.unwrap_or(0.0) as f32)
+                    .map_err(|e| e.into_value(ctx))?;
+                stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // obj:transform(m): obj mapped by the mat4 m
+    methods
+        .set(
+            ctx,
+            "transform",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (ud, m): (UserData, Value) = stack.consume(ctx)?;
+                let m = mat4_arg(ctx, m, "transform")?;
+                let obj = ud.downcast_static::<LObject>()?;
+                let new_obj = match obj.as_primitive() {
+                    Some(o) => match Affine::new(o, m) {
+                        Some(a) => Some(Box::new(a) as Box<dyn Primitive>),
+                        None => {
+                            let msg = "matrix must be an invertible affine transform";
+                            return Err(msg.into_value(ctx).into());
+                        }
+                    },
+                    None => None,
+                };
+                stack.replace(ctx, wrap_object(ctx, LObject(new_obj)));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // obj:color(r, g, b): obj shaded in an sRGB colour in the preview
+    methods
+        .set(
+            ctx,
+            "color",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let ud: UserData = stack.from_front(ctx)?;
+                let rgb: [f64; 3] = xyz_args(ctx, &mut stack, "color")?;
+                let color = check_color(ctx, rgb)?;
+                let obj = ud.downcast_static::<LObject>()?;
+                let new_obj = LObject(obj.0.as_ref().map(|o| {
+                    Box::new(Material::new(o.clone_box(), color, 0.0)) as Box<dyn Primitive>
+                }));
+                stack.replace(ctx, wrap_object(ctx, new_obj));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // obj:material{color={r, g, b}, specular=s}: obj shaded with a colour and
+    // a specular highlight of strength s in the preview
+    methods
+        .set(
+            ctx,
+            "material",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (ud, table): (UserData, Table) = stack.consume(ctx)?;
+                let (mut color, mut specular) = ([0.7; 3], 0.0);
+                for (key, value) in table.iter() {
+                    match key {
+                        Value::String(k) if k == "color" => {
+                            color = color_arg(ctx, value, "material")?
+                        }
+                        Value::String(k) if k == "specular" => {
+                            specular = match f64::from_value(ctx, value) {
+                                Ok(s) if (0.0..=1.0).contains(&s) => s as f32,
+                                _ => {
+                                    let msg = "specular must be a number between 0 and 1";
+                                    return Err(msg.into_value(ctx).into());
+                                }
+                            }
+                        }
+                        _ => {
+                            return Err("material keys must be color or specular"
+                                .into_value(ctx)
+                                .into());
+                        }
+                    }
+                }
+                let obj = ud.downcast_static::<LObject>()?;
+                let new_obj = LObject(obj.0.as_ref().map(|o| {
+                    Box::new(Material::new(o.clone_box(), color, specular)) as Box<dyn Primitive>
+                }));
+                stack.replace(ctx, wrap_object(ctx, new_obj));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // obj:debug(), obj:ghost(), obj:only(): obj unchanged, marked to be drawn
+    // highlighted, faintly, or alone in the preview
+    methods.set(ctx, "debug", marker(ctx, Mark::Debug)).unwrap();
+    methods.set(ctx, "ghost", marker(ctx, Mark::Ghost)).unwrap();
+    methods.set(ctx, "only", marker(ctx, Mark::Only)).unwrap();
+
+    methods.set(ctx, "invert", complement(ctx)).unwrap();
+
+    // obj:smooth(k): obj for the next operator to blend with radius k
+    methods
+        .set(
+            ctx,
+            "smooth",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let ud: UserData = stack.from_front(ctx)?;
+                let k: f64 = stack.consume(ctx)?;
+                if k.is_nan() || k <= 0.0 {
+                    return Err("smoothing must be a positive number".into_value(ctx).into());
+                }
+                let obj = ud.downcast_static::<LObject>()?;
+                let Some(o) = obj.as_primitive() else {
+                    return Err("cannot smooth a nil object".into_value(ctx).into());
+                };
+                let ud = UserData::new_static(&ctx, Smoothed(o, k as f32));
+                if let Value::Table(mt) = ctx.get_global("__lsmooth_mt") {
+                    ud.set_metatable(&ctx, Some(mt));
+                }
+                stack.replace(ctx, ud);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
     let metatable = Table::new(&ctx);
     metatable.set(ctx, MetaMethod::Index, methods).unwrap();
+    let smooth_metatable = Table::new(&ctx);
+    for mt in [metatable, smooth_metatable] {
+        mt.set(
+            ctx,
+            "__add",
+            csg_operator(ctx, |c, k| Box::new(Union::new(c, k))),
+        )
+        .unwrap();
+        mt.set(
+            ctx,
+            "__sub",
+            csg_operator(ctx, |c, k| Box::new(Difference::new(c, k))),
+        )
+        .unwrap();
+        mt.set(
+            ctx,
+            "__mul",
+            csg_operator(ctx, |c, k| Box::new(Intersection::new(c, k))),
+        )
+        .unwrap();
+    }
+    metatable.set(ctx, "__unm", complement(ctx)).unwrap();
     ctx.set_global("__lobj_mt", metatable).unwrap();
+    ctx.set_global("__lsmooth_mt", smooth_metatable).unwrap();
+}
+
+/// Take `cost` from the run's fuel for work a callback did in Rust.
+fn charge(exec: &mut Execution<'_, '_>, cost: usize) {
+    exec.fuel().consume(i32::try_from(cost).unwrap_or(i32::MAX));
+}
+
+/// The primitive behind `ud`, for the methods that measure an object. The
+/// parameters it reads are baked.
+fn primitive_of<'gc>(
+    ctx: Context<'gc>,
+    ud: UserData<'gc>,
+) -> Result<&'gc dyn Primitive, Error<'gc>> {
+    match &ud.downcast_static::<LObject>()?.0 {
+        Some(o) => {
+            bake_object(ctx, o.as_ref());
+            Ok(o.as_ref())
+        }
+        None => Err("cannot measure a nil object".into_value(ctx).into()),
+    }
+}
+
+/// The anchors of `align_to`'s third argument, by axis.
+fn anchors_from_table<'gc>(
+    ctx: Context<'gc>,
+    table: Table<'gc>,
+) -> Result<[Option<Anchor>; 3], Error<'gc>> {
+    let mut anchors = [None; 3];
+    for (key, value) in table.iter() {
+        let axis = match key {
+            Value::String(k) => layout::parse_axis(&lua_string(k)),
+            _ => None,
+        };
+        let Some(axis) = axis else {
+            return Err("anchors must be keyed by x, y or z".into_value(ctx).into());
+        };
+        let anchor = match value {
+            Value::String(v) => Anchor::parse(axis, &lua_string(v)),
+            _ => None,
+        };
+        let Some(anchor) = anchor else {
+            let sides = [
+                "\"left\" or \"right\"",
+                "\"front\" or \"back\"",
+                "\"bottom\" or \"top\"",
+            ];
+            let msg = format!(
+                "anchor {} must be \"min\", \"center\", \"max\", {}",
+                ["x", "y", "z"][axis],
+                sides[axis]
+            );
+            return Err(msg.into_value(ctx).into());
+        };
+        anchors[axis] = Some(anchor);
+    }
+    Ok(anchors)
+}
+
+/// The axis argument `v` of `name`, "z" when it is nil.
+fn axis_arg<'gc>(ctx: Context<'gc>, v: Value<'gc>, name: &str) -> Result<usize, Error<'gc>> {
+    let axis = match v {
+        Value::Nil => Some(2),
+        Value::String(s) => layout::parse_axis(&lua_string(s)),
+        _ => None,
+    };
+    axis.ok_or_else(|| {
+        format!("{name} axis must be \"x\", \"y\" or \"z\"")
+            .into_value(ctx)
+            .into()
+    })
+}
+
+/// The corners of `obj`'s bounds, at infinity on the sides it is unbounded.
+fn bounds(obj: &dyn Primitive) -> (na::Vector3<f64>, na::Vector3<f64>) {
+    let b = obj.bbox();
+    let side = |v: f32| {
+        if v.abs() >= UNBOUNDED {
+            f64::INFINITY.copysign(v.into())
+        } else {
+            v.into()
+        }
+    };
+    (b.min.map(side).into(), b.max.map(side).into())
+}
+
+/// `Complement(obj)`, `obj:invert()` and `-obj`: everything outside obj.
+fn complement(ctx: Context<'_>) -> Callback<'_> {
+    Callback::from_fn(&ctx, |ctx, _, mut stack| {
+        let ud: UserData = stack.from_front(ctx)?;
+        let obj = ud.downcast_static::<LObject>()?;
+        let new_obj = LObject(
+            obj.as_primitive()
+                .map(|o| Box::new(Complement::new(o)) as Box<dyn Primitive>),
+        );
+        stack.replace(ctx, wrap_object(ctx, new_obj));
+        Ok(CallbackReturn::Return)
+    })
+}
+
+fn marker(ctx: Context<'_>, mark: Mark) -> Callback<'_> {
+    Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+        let ud: UserData = stack.consume(ctx)?;
+        let obj = ud.downcast_static::<LObject>()?;
+        let new_obj = LObject(
+            obj.as_primitive()
+                .map(|o| Box::new(Marker::new(o, mark)) as Box<dyn Primitive>),
+        );
+        stack.replace(ctx, wrap_object(ctx, new_obj));
+        Ok(CallbackReturn::Return)
+    })
+}
+
+/// An object marked by `obj:smooth(k)`: an operator it takes part in blends
+/// its operands with radius k.
+struct Smoothed(Box<dyn Primitive>, f32);
+
+/// An operand of `+`, `-` or `*`, with the smoothing it asks for.
+fn csg_operand<'gc>(
+    ctx: Context<'gc>,
+    v: Value<'gc>,
+) -> Result<(Box<dyn Primitive>, f32), Error<'gc>> {
+    if let Value::UserData(ud) = v {
+        if let Ok(obj) = ud.downcast_static::<LObject>()
+            && let Some(o) = obj.as_primitive()
+        {
+            return Ok((o, 0.0));
+        }
+        if let Ok(Smoothed(o, k)) = ud.downcast_static::<Smoothed>() {
+            return Ok((o.clone_box(), *k));
+        }
+    }
+    let msg = format!("cannot combine an object with a {} value", type_of(v));
+    Err(msg.into_value(ctx).into())
+}
+
+/// Builds a CSG node from its children and smoothing.
+type Combine = fn(Vec<Box<dyn Primitive>>, f32) -> Box<dyn Primitive>;
+
+/// `a op b` as `combine({a, b}, k)`, k being the larger smoothing of the two.
+fn csg_operator(ctx: Context<'_>, combine: Combine) -> Callback<'_> {
+    Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+        let (a, b): (Value, Value) = stack.consume(ctx)?;
+        let (a, ka) = csg_operand(ctx, a)?;
+        let (b, kb) = csg_operand(ctx, b)?;
+        let obj = combine(vec![a, b], ka.max(kb));
+        stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
+        Ok(CallbackReturn::Return)
+    })
 }
 
 fn setup_print(ctx: Context<'_>, buffer: Arc<Mutex<String>>) {
@@ -143,12 +735,14 @@ fn setup_print(ctx: Context<'_>, buffer: Arc<Mutex<String>>) {
             let mut parts = Vec::new();
             for i in 0..stack.len() {
                 let s = match stack.get(i) {
-                    Value::String(s) => std::str::from_utf8(s.as_bytes()).unwrap_or("?").to_string(),
+                    Value::String(s) => {
+                        std::str::from_utf8(s.as_bytes()).unwrap_or("?").to_string()
+                    }
                     Value::Number(n) => n.to_string(),
                     Value::Integer(n) => n.to_string(),
                     Value::Boolean(b) => b.to_string(),
                     Value::Nil => "nil".to_string(),
-                    other => other.type_name().to_string(),
+                    other => describe(other).unwrap_or_else(|| other.type_name().to_string()),
                 };
                 parts.push(s);
             }
@@ -160,50 +754,263 @@ fn setup_print(ctx: Context<'_>, buffer: Arc<Mutex<String>>) {
     .unwrap();
 }
 
-fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
-    macro_rules! plane_factory {
-        ($name:literal, $T:ident) => {
-            ctx.set_global(
-                $name,
-                Callback::from_fn(&ctx, |ctx, _, mut stack| {
-                    let d: f64 = stack.consume(ctx)?;
-                    stack.replace(
-                        ctx,
-                        wrap_object(ctx, LObject(Some(Box::new($T::new(d as f32))))),
-                    );
-                    Ok(CallbackReturn::Return)
-                }),
-            )
-            .unwrap();
-        };
+/// A number, boolean or string as a parameter value.
+fn to_param_value(value: Value<'_>) -> Option<ParamValue> {
+    match value {
+        Value::Integer(i) => Some(ParamValue::Number(i as f64)),
+        Value::Number(n) => Some(ParamValue::Number(n)),
+        Value::Boolean(b) => Some(ParamValue::Boolean(b)),
+        Value::String(s) => Some(ParamValue::Text(lua_string(s))),
+        _ => None,
     }
+}
 
-    plane_factory!("PlaneX", PlaneX);
-    plane_factory!("PlaneY", PlaneY);
-    plane_factory!("PlaneZ", PlaneZ);
-    plane_factory!("PlaneNegX", PlaneNegX);
-    plane_factory!("PlaneNegY", PlaneNegY);
-    plane_factory!("PlaneNegZ", PlaneNegZ);
-
-    ctx.set_global(
-        "Sphere",
-        Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            let r: f64 = stack.consume(ctx)?;
-            stack.replace(ctx, wrap_object(ctx, LObject(Some(Box::new(Sphere::new(r as f32))))));
-            Ok(CallbackReturn::Return)
-        }),
-    )
-    .unwrap();
+fn lua_string(s: piccolo::String<'_>) -> String {
+    String::from_utf8_lossy(s.as_bytes()).into_owned()
+}
 
+fn setup_params(ctx: Context<'_>, params: SharedParams) {
+    setup_live_numbers(ctx);
+    let _ = ctx
+        .singleton::<Rootable![RunShared]>()
+        .params
+        .set(params.clone());
+    // __param(name, type, default, min, max, step, description, group, options,
+    // live) — the value for this run
     ctx.set_global(
-        "iCylinder",
-        Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            let r: f64 = stack.consume(ctx)?;
-            stack.replace(
-                ctx,
-                wrap_object(ctx, LObject(Some(Box::new(InfCylinder::new(r as f32))))),
-            );
-            Ok(CallbackReturn::Return)
+        "__param",
+        Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+            let name: piccolo::String = stack.from_front(ctx)?;
+            let kind: piccolo::String = stack.from_front(ctx)?;
+            let default: Value = stack.from_front(ctx)?;
+            let min: Option<f64> = stack.from_front(ctx)?;
+            let max: Option<f64> = stack.from_front(ctx)?;
+            let step: Option<f64> = stack.from_front(ctx)?;
+            let (description, group, options, live): (
+                Option<piccolo::String>,
+                Option<piccolo::String>,
+                Option<Table>,
+                Option<bool>,
+            ) = stack.consume(ctx)?;
+            let name = lua_string(name);
+            // The name becomes part of a GLSL identifier, where "__" is reserved.
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !name.contains("__");
+            if !valid {
+                let msg = "parameter name must be a letter followed by letters, digits or single underscores";
+                return Err(msg.into_value(ctx).into());
+            }
+            let mut registry = params.lock().unwrap();
+            if registry.declared.iter().any(|p| p.name == name) {
+                let msg = format!("parameter '{name}' is declared twice");
+                return Err(msg.into_value(ctx).into());
+            }
+            let kind = match kind.as_bytes() {
+                b"number" => ParamKind::Number { min, max, step },
+                b"integer" => ParamKind::Integer {
+                    min: min.map(|v| v as i64),
+                    max: max.map(|v| v as i64),
+                },
+                b"boolean" => ParamKind::Boolean,
+                b"string" => ParamKind::Text,
+                b"enum" => {
+                    let options = options.filter(|t| t.length() > 0).ok_or_else(|| {
+                        "options must be a non-empty list".into_value(ctx)
+                    })?;
+                    let mut values = Vec::new();
+                    for i in 1..=options.length() {
+                        match to_param_value(options.get(ctx, i)) {
+                            Some(v @ (ParamValue::Number(_) | ParamValue::Text(_))) => {
+                                values.push(v)
+                            }
+                            _ => {
+                                let msg = format!("option {i} must be a number or string");
+                                return Err(msg.into_value(ctx).into());
+                            }
+                        }
+                    }
+                    ParamKind::Enum(values)
+                }
+                _ => {
+                    let msg = "type must be number, integer, boolean, enum or string";
+                    return Err(msg.into_value(ctx).into());
+                }
+            };
+            let Some(default) = to_param_value(default) else {
+                return Err("default must be a number, boolean or string".into_value(ctx).into());
+            };
+            if let ParamKind::Enum(options) = &kind
+                && !options.contains(&default)
+            {
+                return Err("default must be one of the options".into_value(ctx).into());
+            }
+            let mut param = Param {
+                name,
+                kind,
+                value: default.clone(),
+                default,
+                description: description.map(lua_string),
+                group: group.map(lua_string),
+                live: live.unwrap_or(false),
+            };
+            // Overrides that do not fit the parameter leave it at its default.
+            if let Some(v) = registry.overrides.get(&param.name).and_then(|v| param.accept(v)) {
+                param.value = v;
+            }
+            let value: Value = match (&param.kind, &param.value) {
+                (_, &ParamValue::Number(v)) if param.live => {
+                    scalar_value(ctx, Scalar::param(&param.name, v as f32))
+                }
+                (ParamKind::Integer { .. }, &ParamValue::Number(v)) => Value::Integer(v as i64),
+                (_, ParamValue::Number(v)) => Value::Number(*v),
+                (_, ParamValue::Boolean(b)) => Value::Boolean(*b),
+                (_, ParamValue::Text(s)) => piccolo::String::from_slice(&ctx, s.as_bytes()).into(),
+            };
+            stack.replace(ctx, value);
+            registry.declared.push(param);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+}
+
+/// The metatable of live numbers. Arithmetic between live and plain numbers
+/// stays live, `tonumber` gives the plain value, and vectors see the value.
+fn setup_live_numbers(ctx: Context<'_>) {
+    let mt = Table::new(&ctx);
+    mt.set(ctx, "__add", live_operator(ctx, "__add", |a, b| a + b))
+        .unwrap();
+    mt.set(ctx, "__sub", live_operator(ctx, "__sub", |a, b| a - b))
+        .unwrap();
+    mt.set(ctx, "__mul", live_operator(ctx, "__mul", |a, b| a * b))
+        .unwrap();
+    mt.set(ctx, "__div", live_operator(ctx, "__div", |a, b| a / b))
+        .unwrap();
+    // The unary minus helper passes the operand twice.
+    mt.set(ctx, "__unm", live_operator(ctx, "__unm", |a, _| -a))
+        .unwrap();
+    mt.set(
+        ctx,
+        MetaMethod::ToString,
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let value = live_scalar(stack.get(0)).map_or(0.0, |s| s.value);
+            stack.replace(
+                ctx,
+                piccolo::String::from_slice(&ctx, value.to_string().as_bytes()),
+            );
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+    ctx.set_global("__lscalar_mt", mt).unwrap();
+
+    ctx.set_global(
+        "__islive",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let live = live_scalar(stack.get(0)).is_some();
+            stack.replace(ctx, live);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    // piccolo has no tonumber; this one also takes a live number to its value.
+    ctx.set_global(
+        "tonumber",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let v = stack.get(0);
+            let n = match v {
+                Value::Integer(_) | Value::Number(_) => v,
+                Value::String(_) => {
+                    let n = v
+                        .to_integer()
+                        .map(Value::Integer)
+                        .or(v.to_number().map(Value::Number));
+                    n.unwrap_or(Value::Nil)
+                }
+                _ => live_value(ctx, v).map_or(Value::Nil, Value::Number),
+            };
+            stack.replace(ctx, n);
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+}
+
+/// The metamethod `event` of live numbers: `op` if both operands are numbers,
+/// live or plain, otherwise that of the other operand.
+fn live_operator<'gc>(
+    ctx: Context<'gc>,
+    event: &'static str,
+    op: fn(Scalar, Scalar) -> Scalar,
+) -> Callback<'gc> {
+    Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+        let (a, b): (Value, Value) = stack.consume(ctx)?;
+        if let (Ok(x), Ok(y)) = (Scalar::from_value(ctx, a), Scalar::from_value(ctx, b)) {
+            stack.replace(ctx, scalar_value(ctx, op(x, y)));
+            return Ok(CallbackReturn::Return);
+        }
+        let other = if live_scalar(a).is_some() { b } else { a };
+        let Some(function) = metamethod(ctx, other, event) else {
+            let msg = format!(
+                "attempt to perform arithmetic on a {} value",
+                type_of(other)
+            );
+            return Err(msg.into_value(ctx).into());
+        };
+        stack.replace(ctx, (a, b));
+        Ok(CallbackReturn::Call {
+            function,
+            then: None,
+        })
+    })
+}
+
+fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
+    macro_rules! plane_factory {
+        ($name:literal, $T:ident) => {
+            ctx.set_global(
+                $name,
+                Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                    let d: Scalar = stack.consume(ctx)?;
+                    stack.replace(ctx, wrap_object(ctx, LObject(Some(Box::new($T::new(d))))));
+                    Ok(CallbackReturn::Return)
+                }),
+            )
+            .unwrap();
+        };
+    }
+
+    plane_factory!("PlaneX", PlaneX);
+    plane_factory!("PlaneY", PlaneY);
+    plane_factory!("PlaneZ", PlaneZ);
+    plane_factory!("PlaneNegX", PlaneNegX);
+    plane_factory!("PlaneNegY", PlaneNegY);
+    plane_factory!("PlaneNegZ", PlaneNegZ);
+
+    ctx.set_global(
+        "Sphere",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let r: Scalar = stack.consume(ctx)?;
+            stack.replace(
+                ctx,
+                wrap_object(ctx, LObject(Some(Box::new(Sphere::new(r))))),
+            );
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    ctx.set_global(
+        "iCylinder",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let r: Scalar = stack.consume(ctx)?;
+            stack.replace(
+                ctx,
+                wrap_object(ctx, LObject(Some(Box::new(InfCylinder::new(r))))),
+            );
+            Ok(CallbackReturn::Return)
         }),
     )
     .unwrap();
@@ -214,7 +1021,10 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
             let slope: f64 = stack.consume(ctx)?;
             stack.replace(
                 ctx,
-                wrap_object(ctx, LObject(Some(Box::new(InfCone::new(slope as f32, 0.0))))),
+                wrap_object(
+                    ctx,
+                    LObject(Some(Box::new(InfCone::new(slope as f32, 0.0)))),
+                ),
             );
             Ok(CallbackReturn::Return)
         }),
@@ -225,17 +1035,17 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
     ctx.set_global(
         "__Box",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            let (x, y, z, smooth): (f64, f64, f64, f64) = stack.consume(ctx)?;
-            let (x, y, z, smooth) = (x as f32, y as f32, z as f32, smooth as f32);
+            let (x, y, z, smooth): (Scalar, Scalar, Scalar, f64) = stack.consume(ctx)?;
+            let (x, y, z) = (x / 2.0, y / 2.0, z / 2.0);
             let children: Vec<Box<dyn Primitive>> = vec![
-                Box::new(PlaneX::new(x / 2.0)),
-                Box::new(PlaneY::new(y / 2.0)),
-                Box::new(PlaneZ::new(z / 2.0)),
-                Box::new(PlaneNegX::new(x / 2.0)),
-                Box::new(PlaneNegY::new(y / 2.0)),
-                Box::new(PlaneNegZ::new(z / 2.0)),
+                Box::new(PlaneX::new(x.clone())),
+                Box::new(PlaneY::new(y.clone())),
+                Box::new(PlaneZ::new(z.clone())),
+                Box::new(PlaneNegX::new(x)),
+                Box::new(PlaneNegY::new(y)),
+                Box::new(PlaneNegZ::new(z)),
             ];
-            let obj: Box<dyn Primitive> = Box::new(Intersection::new(children, smooth));
+            let obj: Box<dyn Primitive> = Box::new(Intersection::new(children, smooth as f32));
             stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
             Ok(CallbackReturn::Return)
         }),
@@ -246,26 +1056,29 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
     ctx.set_global(
         "__Cylinder",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
-            let (length, radius1, radius2, smooth): (f64, f64, f64, f64) = stack.consume(ctx)?;
-            let (length, radius1, radius2, smooth) =
-                (length as f32, radius1 as f32, radius2 as f32, smooth as f32);
+            let (length, radius1, radius2, smooth): (Scalar, Scalar, Scalar, f64) =
+                stack.consume(ctx)?;
+            let half = length.clone() / 2.0;
+            let smooth = smooth as f32;
 
-            let shaft: Box<dyn Primitive> = if (radius1 - radius2).abs() < EPSILON as f32 {
-                Box::new(InfCylinder::new(radius1))
-            } else {
-                let slope = (radius2 - radius1).abs() / length;
-                let offset = if radius1 < radius2 {
-                    -radius1 / slope - length * 0.5
+            let shaft: Box<dyn Primitive> =
+                if (radius1.value - radius2.value).abs() < EPSILON as f32 {
+                    Box::new(InfCylinder::new(radius1))
                 } else {
-                    radius2 / slope + length * 0.5
+                    let (length, radius1, radius2) = (length.value, radius1.value, radius2.value);
+                    let slope = (radius2 - radius1).abs() / length;
+                    let offset = if radius1 < radius2 {
+                        -radius1 / slope - length * 0.5
+                    } else {
+                        radius2 / slope + length * 0.5
+                    };
+                    Box::new(InfCone::new(slope, offset))
                 };
-                Box::new(InfCone::new(slope, offset))
-            };
 
             let children: Vec<Box<dyn Primitive>> = vec![
                 shaft,
-                Box::new(PlaneZ::new(length / 2.0)),
-                Box::new(PlaneNegZ::new(length / 2.0)),
+                Box::new(PlaneZ::new(half.clone())),
+                Box::new(PlaneNegZ::new(half)),
             ];
             let obj: Box<dyn Primitive> = Box::new(Intersection::new(children, smooth));
             stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
@@ -279,10 +1092,7 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
         "__PlaneHessian",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
             let (nx, ny, nz, p): (f64, f64, f64, f64) = stack.consume(ctx)?;
-            let plane = NormalPlane::from_normal_and_p(
-                [nx as f32, ny as f32, nz as f32],
-                p as f32,
-            );
+            let plane = NormalPlane::from_normal_and_p([nx as f32, ny as f32, nz as f32], p as f32);
             stack.replace(ctx, wrap_object(ctx, LObject(Some(Box::new(plane)))));
             Ok(CallbackReturn::Return)
         }),
@@ -294,9 +1104,15 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
         "__Plane3Points",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
             let (ax, ay, az, bx, by, bz, cx, cy, cz): (
-                f64, f64, f64,
-                f64, f64, f64,
-                f64, f64, f64,
+                f64,
+                f64,
+                f64,
+                f64,
+                f64,
+                f64,
+                f64,
+                f64,
+                f64,
             ) = stack.consume(ctx)?;
             let plane = NormalPlane::from_3_points(
                 [ax as f32, ay as f32, az as f32],
@@ -309,15 +1125,70 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
     )
     .unwrap();
 
+    // __Gear(module, teeth, pressure_angle, thickness, helix_angle, bore)
+    ctx.set_global(
+        "__Gear",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (module, teeth, pressure_angle, thickness, helix_angle, bore): (
+                f64,
+                i64,
+                f64,
+                f64,
+                f64,
+                f64,
+            ) = stack.consume(ctx)?;
+            let gear = Gear::new(
+                module as f32,
+                teeth as u32,
+                pressure_angle as f32,
+                thickness as f32,
+                helix_angle as f32,
+                bore as f32,
+            );
+            stack.replace(ctx, wrap_object(ctx, LObject(Some(Box::new(gear)))));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    // __Blobs({{x,y,z,r}, ...}, threshold)
+    ctx.set_global(
+        "__Blobs",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (table, threshold): (Table, f64) = stack.consume(ctx)?;
+            let mut balls = Vec::new();
+            for i in 1..=table.length() {
+                let Value::Table(ball) = table.get(ctx, i) else {
+                    return Err("expected {x,y,z,r} table in list".into_value(ctx).into());
+                };
+                let mut b = [0f32; 4];
+                for (j, v) in b.iter_mut().enumerate() {
+                    *v = match ball.get(ctx, j as i64 + 1) {
+                        Value::Number(n) => n as f32,
+                        Value::Integer(n) => n as f32,
+                        _ => return Err("ball entries must be numbers".into_value(ctx).into()),
+                    };
+                }
+                balls.push(b);
+            }
+            let blobs = Blobs::new(balls, threshold as f32).map_err(|e| e.into_value(ctx))?;
+            let obj: Box<dyn Primitive> = Box::new(blobs);
+            stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
     ctx.set_global(
         "Bend",
         Callback::from_fn(&ctx, |ctx, _, mut stack| {
             let ud: UserData = stack.from_front(ctx)?;
             let width: f64 = stack.consume(ctx)?;
             let obj = ud.downcast_static::<LObject>()?;
-            let new_obj = LObject(obj.0.as_ref().map(|o| {
-                Box::new(Bender::new(o.clone_box(), width as f32)) as Box<dyn Primitive>
-            }));
+            let new_obj =
+                LObject(obj.0.as_ref().map(|o| {
+                    Box::new(Bender::new(o.clone_box(), width as f32)) as Box<dyn Primitive>
+                }));
             stack.replace(ctx, wrap_object(ctx, new_obj));
             Ok(CallbackReturn::Return)
         }),
@@ -339,6 +1210,98 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
     )
     .unwrap();
 
+    // __Minkowski(obj, kind, a, b, c) — kind is "ball", "box" or "cylinder"
+    ctx.set_global(
+        "__Minkowski",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let ud: UserData = stack.from_front(ctx)?;
+            let (kind, a, b, c): (piccolo::String, f64, f64, f64) = stack.consume(ctx)?;
+            let kernel = match kind.as_bytes() {
+                b"ball" => MinkowskiKernel::Ball { radius: a as f32 },
+                b"box" => MinkowskiKernel::Box {
+                    half: [a as f32, b as f32, c as f32],
+                },
+                b"cylinder" => MinkowskiKernel::Cylinder {
+                    radius: a as f32,
+                    half_height: b as f32,
+                },
+                _ => return Err("unknown Minkowski kernel".into_value(ctx).into()),
+            };
+            let obj = ud.downcast_static::<LObject>()?;
+            // Box and cylinder kernels centre on the bbox of obj.
+            if let (Some(o), false) = (&obj.0, matches!(kernel, MinkowskiKernel::Ball { .. })) {
+                bake_object(ctx, o.as_ref());
+            }
+            let new_obj =
+                LObject(obj.0.as_ref().map(|o| {
+                    Box::new(Minkowski::new(o.clone_box(), kernel)) as Box<dyn Primitive>
+                }));
+            stack.replace(ctx, wrap_object(ctx, new_obj));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    // Voxelize(obj, resolution?, sparse?) — bake obj into a VoxelField
+    ctx.set_global(
+        "Voxelize",
+        Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+            let ud: UserData = stack.from_front(ctx)?;
+            let (resolution, sparse): (Option<i64>, Option<bool>) = stack.consume(ctx)?;
+            let obj = ud.downcast_static::<LObject>()?;
+            let Some(o) = obj.0.as_ref() else {
+                return Err("cannot voxelize a nil object".into_value(ctx).into());
+            };
+            bake_object(ctx, o.as_ref());
+            let field = VoxelField::from_primitive(
+                o.as_ref(),
+                usize::try_from(resolution.unwrap_or(DEFAULT_VOXEL_RESOLUTION)).unwrap_or(0),
+                sparse.unwrap_or(true),
+            )
+            .map_err(|e| e.into_value(ctx))?;
+            charge(&mut exec, field.dims.iter().product());
+            stack.replace(ctx, wrap_object(ctx, LObject(Some(Box::new(field)))));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    // VoxelBytes(obj, resolution?, sparse?) — bake obj and return the binary encoding
+    ctx.set_global(
+        "VoxelBytes",
+        Callback::from_fn(&ctx, |ctx, mut exec, mut stack| {
+            let ud: UserData = stack.from_front(ctx)?;
+            let (resolution, sparse): (Option<i64>, Option<bool>) = stack.consume(ctx)?;
+            let obj = ud.downcast_static::<LObject>()?;
+            let Some(o) = obj.0.as_ref() else {
+                return Err("cannot voxelize a nil object".into_value(ctx).into());
+            };
+            bake_object(ctx, o.as_ref());
+            let field = VoxelField::from_primitive(
+                o.as_ref(),
+                usize::try_from(resolution.unwrap_or(DEFAULT_VOXEL_RESOLUTION)).unwrap_or(0),
+                sparse.unwrap_or(true),
+            )
+            .map_err(|e| e.into_value(ctx))?;
+            charge(&mut exec, field.dims.iter().product());
+            stack.replace(ctx, piccolo::String::from_slice(&ctx, field.to_bytes()));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
+    // VoxelField(bytes) — load a field produced by VoxelBytes
+    ctx.set_global(
+        "VoxelField",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let bytes: piccolo::String = stack.consume(ctx)?;
+            let field = VoxelField::from_bytes(bytes.as_bytes()).map_err(|e| e.into_value(ctx))?;
+            stack.replace(ctx, wrap_object(ctx, LObject(Some(Box::new(field)))));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+
     ctx.set_global(
         "Mesh",
         Callback::from_fn(&ctx, move |ctx, _, mut stack| {
@@ -375,7 +1338,9 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
             let (table, smooth): (Table, Option<f64>) = stack.consume(ctx)?;
             let objects = objects_from_table(ctx, table)?;
             if objects.is_empty() {
-                return Err("Intersection requires at least one object".into_value(ctx).into());
+                return Err("Intersection requires at least one object"
+                    .into_value(ctx)
+                    .into());
             }
             let obj: Box<dyn Primitive> =
                 Box::new(Intersection::new(objects, smooth.unwrap_or(0.0) as f32));
@@ -391,7 +1356,9 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
             let (table, smooth): (Table, Option<f64>) = stack.consume(ctx)?;
             let objects = objects_from_table(ctx, table)?;
             if objects.is_empty() {
-                return Err("Difference requires at least one object".into_value(ctx).into());
+                return Err("Difference requires at least one object"
+                    .into_value(ctx)
+                    .into());
             }
             let obj: Box<dyn Primitive> =
                 Box::new(Difference::new(objects, smooth.unwrap_or(0.0) as f32));
@@ -400,107 +1367,2460 @@ fn setup_factories(ctx: Context<'_>, console: Arc<Mutex<String>>) {
         }),
     )
     .unwrap();
-}
 
-const LUA_ALIASES: &str = r#"
-pi  = 3.14159265358979323846
-tau = 6.28318530717958647692
+    ctx.set_global("Complement", complement(ctx)).unwrap();
 
-function Box(x, y, z, smooth)
-    if type(x) ~= "number" or type(y) ~= "number" or type(z) ~= "number" then
-        error("all arguments must be numbers")
-    end
-    local s = 0
-    if type(smooth) == "number" then s = smooth end
-    return __Box(x, y, z, s)
-end
+    // Xor(a, b): inside exactly one of a and b, i.e. (a ∪ b) minus (a ∩ b)
+    ctx.set_global(
+        "Xor",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (a, b): (UserData, UserData) = stack.consume(ctx)?;
+            let a = a.downcast_static::<LObject>()?.as_primitive();
+            let b = b.downcast_static::<LObject>()?.as_primitive();
+            let (Some(a), Some(b)) = (a, b) else {
+                return Err("Xor expects two objects".into_value(ctx).into());
+            };
+            let both: Box<dyn Primitive> =
+                Box::new(Intersection::new(vec![a.clone_box(), b.clone_box()], 0.0));
+            let either: Box<dyn Primitive> = Box::new(Union::new(vec![a, b], 0.0));
+            let obj: Box<dyn Primitive> = Box::new(Difference::new(vec![either, both], 0.0));
+            stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
 
-function Cylinder(arg)
-    if type(arg.l) ~= "number" then error("l must be a valid number") end
-    local r1, r2
-    if type(arg.r) == "number" then
-        r1, r2 = arg.r, arg.r
-    elseif type(arg.r1) == "number" and type(arg.r2) == "number" then
-        r1, r2 = arg.r1, arg.r2
-    else
-        error("specify either r or r1 and r2")
-    end
-    local s = 0
-    if type(arg.s) == "number" then s = arg.s end
-    return __Cylinder(arg.l, r1, r2, s)
-end
+    // Stack({obj, ...}, axis?, gap?): each object placed on the one before
+    // along axis, default "z"
+    ctx.set_global(
+        "Stack",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (table, axis, gap): (Table, Value, Option<f64>) = stack.consume(ctx)?;
+            let objects = objects_from_table(ctx, table)?;
+            if objects.is_empty() {
+                return Err("Stack requires at least one object".into_value(ctx).into());
+            }
+            objects.iter().for_each(|o| bake_object(ctx, o.as_ref()));
+            let axis = axis_arg(ctx, axis, "Stack")?;
+            let placed = layout::stack(objects, axis, gap.unwrap_or(0.0) as f32)
+                .map_err(|e| e.into_value(ctx))?;
+            let obj: Box<dyn Primitive> = Box::new(Union::new(placed, 0.0));
+            stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
 
-function Plane3Points(a, b, c)
-    if type(a) ~= "table" or type(b) ~= "table" or type(c) ~= "table" or
-        #a ~= 3 or #b ~= 3 or #c ~= 3 then
-        error("all three arguments must be tables of len 3")
-    end
-    for i = 1, 3 do
-        if type(a[i]) ~= "number" or type(b[i]) ~= "number" or type(c[i]) ~= "number" then
-            error("all table elements must be numbers")
-        end
-    end
-    return __Plane3Points(a[1],a[2],a[3], b[1],b[2],b[3], c[1],c[2],c[3])
-end
+    // Distribute({obj, ...}, axis, spacing): centres spacing apart along axis
+    ctx.set_global(
+        "Distribute",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let (table, axis, spacing): (Table, Value, f64) = stack.consume(ctx)?;
+            let objects = objects_from_table(ctx, table)?;
+            if objects.is_empty() {
+                return Err("Distribute requires at least one object"
+                    .into_value(ctx)
+                    .into());
+            }
+            objects.iter().for_each(|o| bake_object(ctx, o.as_ref()));
+            let axis = axis_arg(ctx, axis, "Distribute")?;
+            let placed =
+                layout::distribute(objects, axis, spacing as f32).map_err(|e| e.into_value(ctx))?;
+            let obj: Box<dyn Primitive> = Box::new(Union::new(placed, 0.0));
+            stack.replace(ctx, wrap_object(ctx, LObject(Some(obj))));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
 
-function PlaneHessian(n, p)
-    if type(n) ~= "table" or #n ~= 3 or
-        type(n[1]) ~= "number" or type(n[2]) ~= "number" or type(n[3]) ~= "number" then
-        error("first argument (normal) must be a table of 3 numbers")
-    end
-    if type(p) ~= "number" then
-        error("second argument must be a number (p in hessian form)")
-    end
-    return __PlaneHessian(n[1], n[2], n[3], p)
-end
-"#;
+    // __Clip(obj, min_x, min_y, min_z, max_x, max_y, max_z)
+    ctx.set_global(
+        "__Clip",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let ud: UserData = stack.from_front(ctx)?;
+            let (x0, y0, z0, x1, y1, z1): (f64, f64, f64, f64, f64, f64) = stack.consume(ctx)?;
+            let obj = ud.downcast_static::<LObject>()?;
+            let new_obj = LObject(obj.as_primitive().map(|o| {
+                clip_to_bounds(
+                    o,
+                    [x0 as f32, y0 as f32, z0 as f32],
+                    [x1 as f32, y1 as f32, z1 as f32],
+                )
+            }));
+            stack.replace(ctx, wrap_object(ctx, new_obj));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+}
 
-// ── public API ────────────────────────────────────────────────────────────────
+// ── operators ─────────────────────────────────────────────────────────────────
+// piccolo 0.3 does arithmetic on numbers only and never looks for `__add` and
+// friends. So scripts and modules are compiled with `+`, `-`, `*`, `/` and
+// unary minus turned into calls to the functions in ARITH, which do the
+// arithmetic or call the operands' metamethod as Lua would. Their names are
+// no Lua identifier, so a script cannot shadow them.
+//
+// Operations whose operands are certainly numbers stay native: number
+// literals, numeric `for` variables, locals only ever assigned such numbers,
+// and arithmetic on all of these. Whether a local is only assigned numbers
+// can depend on other locals, so the rewrite assumes it of every local and
+// runs again without the ones it found assigned something else, until it
+// finds none.
+//
+// piccolo reports no location for errors the VM raises itself, such as
+// calling or indexing nil. So the rewrite also sets the global LINE to the
+// chunk and line of each statement that can fail before it runs; a failed
+// run reads it back. Functions keep the LINE they were called at and put it
+// back when they return, but for tail calls, which would stop being ones.
 
-pub fn eval(script: &str) -> EvalResult {
-    let mut lua = Lua::core();
+/// The functions the rewritten operators call, in the order add, sub, mul,
+/// div, unm.
+const ARITH: [&str; 5] = [
+    "__arith add",
+    "__arith sub",
+    "__arith mul",
+    "__arith div",
+    "__arith unm",
+];
 
-    let print_buffer: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
-    let result: Arc<Mutex<Option<Box<dyn Primitive>>>> = Arc::new(Mutex::new(None));
+/// The global holding `chunk << LINE_BITS | line` for the statement last
+/// started, where chunk indexes `CallTracker::chunks`.
+const LINE: &str = "__line at";
+const LINE_BITS: u32 = 24;
+/// The local holding LINE as a function was called, and the function setting
+/// LINE to its first argument and returning the others.
+const CALLER: &str = "__line caller";
+const RESTORE: &str = "__line restore";
 
-    {
-        let print_buffer = print_buffer.clone();
-        let result = result.clone();
+struct ChunkRewrite<S> {
+    /// Interned names of the ARITH functions.
+    names: [S; 5],
+    /// Interned LINE, and the chunk's part of its value; None for chunks
+    /// that are not located.
+    line: S,
+    chunk: Option<i64>,
+    /// Interned CALLER and RESTORE.
+    caller: S,
+    restore: S,
+    /// Interned `self`, the first parameter of a method.
+    this: S,
+    /// Locals in scope, innermost last, with the index of their declaration
+    /// in the chunk.
+    scope: Vec<(S, usize)>,
+    /// Locals declared so far in this pass.
+    declared: usize,
+    /// Locals assigned something other than a number, over all passes.
+    mixed: HashSet<usize>,
+    /// Whether this pass added to `mixed`.
+    changed: bool,
+}
 
-        lua.try_enter(|ctx| {
-            setup_methods_metatable(ctx);
-            setup_print(ctx, print_buffer.clone());
-            setup_factories(ctx, print_buffer);
+impl<S: Clone + PartialEq> ChunkRewrite<S> {
+    /// Rewrite `chunk`, running again while there are locals left to rule out.
+    fn chunk(&mut self, chunk: &Chunk<S>) -> Chunk<S> {
+        loop {
+            let mut rewritten = chunk.clone();
+            self.declared = 0;
+            self.changed = false;
+            self.block(&mut rewritten.block);
+            self.keep_caller(&mut rewritten.block);
+            if !self.changed {
+                return rewritten;
+            }
+        }
+    }
 
-            let result = result.clone();
-            ctx.set_global(
-                "build",
-                Callback::from_fn(&ctx, move |ctx, _, mut stack| {
-                    let ud: UserData = stack.from_front(ctx)?;
-                    let obj = ud.downcast_static::<LObject>()?;
-                    *result.lock().unwrap() = obj.as_primitive();
-                    stack.clear();
-                    Ok(CallbackReturn::Return)
-                }),
-            )?;
+    fn declare(&mut self, name: &S, numeric: bool) {
+        let local = self.declared;
+        self.declared += 1;
+        self.scope.push((name.clone(), local));
+        if !numeric {
+            self.changed |= self.mixed.insert(local);
+        }
+    }
 
-            Ok(())
-        })?;
+    fn local(&self, name: &S) -> Option<usize> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|&(_, local)| local)
+    }
+
+    /// Note that `name` is assigned a value that is a number if `numeric`.
+    fn assign(&mut self, name: &S, numeric: bool) {
+        if let (Some(local), false) = (self.local(name), numeric) {
+            self.changed |= self.mixed.insert(local);
+        }
     }
 
-    let aliases_exec = lua.try_enter(|ctx| {
-        let closure = Closure::load(ctx, None, LUA_ALIASES.as_bytes())?;
-        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
-    })?;
-    lua.execute::<()>(&aliases_exec)?;
+    /// `e` certainly evaluates to a number.
+    fn numeric(&self, e: &Expression<S>) -> bool {
+        let head = match &*e.head {
+            HeadExpression::Simple(SimpleExpression::Float(_) | SimpleExpression::Integer(_)) => {
+                true
+            }
+            HeadExpression::Simple(SimpleExpression::Suffixed(s)) if s.suffixes.is_empty() => {
+                match &s.primary {
+                    PrimaryExpression::Name(n) => {
+                        self.local(n).is_some_and(|l| !self.mixed.contains(&l))
+                    }
+                    PrimaryExpression::GroupedExpression(e) => self.numeric(e),
+                }
+            }
+            HeadExpression::UnaryOperator(UnaryOperator::Minus | UnaryOperator::BitNot, e) => {
+                self.numeric(e)
+            }
+            _ => false,
+        };
+        head && e.tail.iter().all(|(op, right)| {
+            use BinaryOperator::*;
+            matches!(
+                op,
+                Add | Sub
+                    | Mul
+                    | Div
+                    | Mod
+                    | Pow
+                    | IDiv
+                    | BitAnd
+                    | BitOr
+                    | BitXor
+                    | ShiftLeft
+                    | ShiftRight
+            ) && self.numeric(right)
+        })
+    }
 
-    let user_exec = lua.try_enter(|ctx| {
-        let closure = Closure::load(ctx, Some("script"), script.as_bytes())?;
-        Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
-    })?;
-    lua.execute::<()>(&user_exec)?;
+    fn block(&mut self, block: &mut Block<S>) {
+        let outer = self.scope.len();
+        self.statements(block);
+        self.scope.truncate(outer);
+    }
 
-    let output = print_buffer.lock().unwrap().clone();
-    let obj = result.lock().unwrap().take();
-    Ok((output, obj))
+    /// The statements of `block`, leaving its locals in scope.
+    fn statements(&mut self, block: &mut Block<S>) {
+        for mut statement in std::mem::take(&mut block.statements) {
+            if self.fallible(&statement) {
+                block.statements.extend(self.locate(statement.line_number));
+            }
+            self.statement(&mut statement.inner, statement.line_number);
+            block.statements.push(statement);
+        }
+        if let Some(ret) = &mut block.return_statement {
+            let total = ret.returns.iter().all(|e| self.total(e));
+            if !total {
+                block.statements.extend(self.locate(ret.line_number));
+            }
+            ret.inner
+                .returns
+                .iter_mut()
+                .for_each(|e| self.expression(e));
+            let kept = self.chunk.is_some() && !ret.returns.last().is_some_and(tail_call);
+            if kept && total {
+                block
+                    .statements
+                    .push(LineAnnotated::new(ret.line_number, self.restore_line()));
+            } else if kept {
+                // The values are worked out before LINE is put back.
+                let mut args = vec![self.name(&self.caller)];
+                args.append(&mut ret.inner.returns);
+                let call = SuffixedExpression {
+                    primary: PrimaryExpression::Name(self.restore.clone()),
+                    suffixes: vec![SuffixPart::Call(CallSuffix::Function(args))],
+                };
+                ret.inner
+                    .returns
+                    .push(literal(SimpleExpression::Suffixed(call)));
+            }
+        }
+    }
+
+    /// `e` is numeric arithmetic that cannot fail: it has no `%` or `//`,
+    /// which fail on integer zero.
+    fn total(&self, e: &Expression<S>) -> bool {
+        fn divides<S>(e: &Expression<S>) -> bool {
+            let head = match &*e.head {
+                HeadExpression::Simple(SimpleExpression::Suffixed(s)) => match &s.primary {
+                    PrimaryExpression::GroupedExpression(e) => divides(e),
+                    PrimaryExpression::Name(_) => false,
+                },
+                HeadExpression::UnaryOperator(_, e) => divides(e),
+                _ => false,
+            };
+            head || e.tail.iter().any(|(op, right)| {
+                matches!(op, BinaryOperator::Mod | BinaryOperator::IDiv) || divides(right)
+            })
+        }
+        self.numeric(e) && !divides(e)
+    }
+
+    /// `statement` can raise an error: it does more than numeric arithmetic.
+    fn fallible(&self, statement: &Statement<S>) -> bool {
+        match statement {
+            Statement::LocalStatement(s) => !s.values.iter().all(|e| self.total(e)),
+            Statement::Assignment(s) => {
+                !(s.targets
+                    .iter()
+                    .all(|t| matches!(t, AssignmentTarget::Name(_)))
+                    && s.values.iter().all(|e| self.total(e)))
+            }
+            Statement::For(ForStatement::Numeric {
+                initial,
+                limit,
+                step,
+                ..
+            }) => {
+                !(self.total(initial)
+                    && self.total(limit)
+                    && step.as_ref().is_none_or(|e| self.total(e)))
+            }
+            Statement::Function(s) => !s.fields.is_empty() || s.method.is_some(),
+            Statement::Do(_)
+            | Statement::LocalFunction(_)
+            | Statement::Label(_)
+            | Statement::Break
+            | Statement::Goto(_) => false,
+            _ => true,
+        }
+    }
+
+    /// The statement setting LINE to `line` of this chunk, if it is located.
+    fn locate(&self, line: LineNumber) -> Option<LineAnnotated<Statement<S>>> {
+        let code = self.chunk? << LINE_BITS | (line.0 as i64 + 1);
+        let statement = Statement::Assignment(AssignmentStatement {
+            targets: vec![AssignmentTarget::Name(self.line.clone())],
+            values: vec![literal(SimpleExpression::Integer(code))],
+        });
+        Some(LineAnnotated::new(line, statement))
+    }
+
+    /// Expression reading the variable `name`.
+    fn name(&self, name: &S) -> Expression<S> {
+        literal(SimpleExpression::Suffixed(SuffixedExpression {
+            primary: PrimaryExpression::Name(name.clone()),
+            suffixes: Vec::new(),
+        }))
+    }
+
+    /// `LINE = CALLER`
+    fn restore_line(&self) -> Statement<S> {
+        Statement::Assignment(AssignmentStatement {
+            targets: vec![AssignmentTarget::Name(self.line.clone())],
+            values: vec![self.name(&self.caller)],
+        })
+    }
+
+    /// Have the function with the rewritten `body` keep LINE as it was called
+    /// in CALLER and put it back when it ends; its return statements do so
+    /// themselves.
+    fn keep_caller(&self, body: &mut Block<S>) {
+        if self.chunk.is_none() {
+            return;
+        }
+        let first = body
+            .statements
+            .first()
+            .map_or(body.closed_on, |s| s.line_number);
+        let keep = Statement::LocalStatement(LocalStatement {
+            names: vec![self.caller.clone()],
+            values: vec![self.name(&self.line)],
+        });
+        if body.return_statement.is_none() {
+            append(
+                body,
+                LineAnnotated::new(body.closed_on, self.restore_line()),
+            );
+        }
+        body.statements.insert(0, LineAnnotated::new(first, keep));
+    }
+
+    fn function(&mut self, definition: &mut FunctionDefinition<S>, method: bool) {
+        let outer = self.scope.len();
+        if method {
+            let this = self.this.clone();
+            self.declare(&this, false);
+        }
+        for p in &definition.parameters {
+            self.declare(p, false);
+        }
+        self.block(&mut definition.body);
+        self.keep_caller(&mut definition.body);
+        self.scope.truncate(outer);
+    }
+
+    fn statement(&mut self, statement: &mut Statement<S>, line: LineNumber) {
+        match statement {
+            Statement::If(s) => {
+                self.expression(&mut s.if_part.0);
+                self.block(&mut s.if_part.1);
+                for (cond, block) in &mut s.else_if_parts {
+                    self.expression(cond);
+                    self.block(block);
+                }
+                if let Some(block) = &mut s.else_part {
+                    self.block(block);
+                }
+            }
+            // The condition runs again after the body, unless it returns.
+            Statement::While(s) => {
+                self.expression(&mut s.condition);
+                self.block(&mut s.block);
+                if let (None, Some(locate)) = (&s.block.return_statement, self.locate(line)) {
+                    append(&mut s.block, locate);
+                }
+            }
+            Statement::Do(block) => self.block(block),
+            Statement::For(ForStatement::Numeric {
+                name,
+                initial,
+                limit,
+                step,
+                body,
+            }) => {
+                self.expression(initial);
+                self.expression(limit);
+                if let Some(step) = step {
+                    self.expression(step);
+                }
+                let outer = self.scope.len();
+                self.declare(name, true);
+                self.block(body);
+                self.scope.truncate(outer);
+            }
+            Statement::For(ForStatement::Generic {
+                names,
+                arguments,
+                body,
+            }) => {
+                arguments.iter_mut().for_each(|e| self.expression(e));
+                let outer = self.scope.len();
+                names.iter().for_each(|n| self.declare(n, false));
+                self.block(body);
+                self.scope.truncate(outer);
+            }
+            // The condition sees the locals of the body.
+            Statement::Repeat(s) => {
+                let outer = self.scope.len();
+                self.statements(&mut s.body);
+                if s.body.return_statement.is_none() {
+                    s.body.statements.extend(self.locate(s.body.closed_on));
+                }
+                self.expression(&mut s.until);
+                self.scope.truncate(outer);
+            }
+            Statement::Function(s) => {
+                if s.fields.is_empty() && s.method.is_none() {
+                    self.assign(&s.name, false);
+                }
+                self.function(&mut s.definition, s.method.is_some());
+            }
+            Statement::LocalFunction(s) => {
+                self.declare(&s.name, false);
+                self.function(&mut s.definition, false);
+            }
+            Statement::LocalStatement(s) => {
+                s.values.iter_mut().for_each(|e| self.expression(e));
+                let numeric: Vec<bool> = s.values.iter().map(|e| self.numeric(e)).collect();
+                for (i, name) in s.names.iter().enumerate() {
+                    self.declare(name, numeric.get(i).copied().unwrap_or(false));
+                }
+            }
+            Statement::FunctionCall(s) => {
+                self.suffixed(&mut s.head);
+                self.call(&mut s.call);
+            }
+            Statement::Assignment(s) => {
+                for target in &mut s.targets {
+                    if let AssignmentTarget::Field(head, field) = target {
+                        self.suffixed(head);
+                        self.field(field);
+                    }
+                }
+                s.values.iter_mut().for_each(|e| self.expression(e));
+                let numeric: Vec<bool> = s.values.iter().map(|e| self.numeric(e)).collect();
+                for (i, target) in s.targets.iter().enumerate() {
+                    if let AssignmentTarget::Name(name) = target {
+                        self.assign(name, numeric.get(i).copied().unwrap_or(false));
+                    }
+                }
+            }
+            Statement::Label(_) | Statement::Break | Statement::Goto(_) => {}
+        }
+    }
+
+    fn expression(&mut self, expression: &mut Expression<S>) {
+        let Expression { head, tail } =
+            std::mem::replace(expression, literal(SimpleExpression::Nil));
+        let head = match *head {
+            HeadExpression::UnaryOperator(UnaryOperator::Minus, mut operand) => {
+                self.expression(&mut operand);
+                if self.numeric(&operand) {
+                    HeadExpression::UnaryOperator(UnaryOperator::Minus, operand)
+                } else {
+                    HeadExpression::Simple(self.arith_call(4, vec![operand]))
+                }
+            }
+            HeadExpression::UnaryOperator(op, mut operand) => {
+                self.expression(&mut operand);
+                HeadExpression::UnaryOperator(op, operand)
+            }
+            HeadExpression::Simple(mut simple) => {
+                self.simple(&mut simple);
+                HeadExpression::Simple(simple)
+            }
+        };
+        // The compiler folds the tail from the left, and so does this.
+        let mut acc = Expression {
+            head: Box::new(head),
+            tail: Vec::new(),
+        };
+        for (op, mut right) in tail {
+            self.expression(&mut right);
+            let function = match op {
+                BinaryOperator::Add => 0,
+                BinaryOperator::Sub => 1,
+                BinaryOperator::Mul => 2,
+                BinaryOperator::Div => 3,
+                _ => {
+                    acc.tail.push((op, right));
+                    continue;
+                }
+            };
+            if self.numeric(&acc) && self.numeric(&right) {
+                acc.tail.push((op, right));
+            } else {
+                acc = literal(self.arith_call(function, vec![acc, right]));
+            }
+        }
+        *expression = acc;
+    }
+
+    fn simple(&mut self, simple: &mut SimpleExpression<S>) {
+        match simple {
+            SimpleExpression::TableConstructor(t) => self.table(t),
+            SimpleExpression::Function(f) => self.function(f, false),
+            SimpleExpression::Suffixed(s) => self.suffixed(s),
+            _ => {}
+        }
+    }
+
+    fn table(&mut self, table: &mut TableConstructor<S>) {
+        for field in &mut table.fields {
+            match field {
+                ConstructorField::Array(e) | ConstructorField::Record(RecordKey::Named(_), e) => {
+                    self.expression(e)
+                }
+                ConstructorField::Record(RecordKey::Indexed(key), e) => {
+                    self.expression(key);
+                    self.expression(e);
+                }
+            }
+        }
+    }
+
+    fn suffixed(&mut self, suffixed: &mut SuffixedExpression<S>) {
+        if let PrimaryExpression::GroupedExpression(e) = &mut suffixed.primary {
+            self.expression(e);
+        }
+        for suffix in &mut suffixed.suffixes {
+            match suffix {
+                SuffixPart::Field(field) => self.field(field),
+                SuffixPart::Call(call) => self.call(call),
+            }
+        }
+    }
+
+    fn field(&mut self, field: &mut FieldSuffix<S>) {
+        if let FieldSuffix::Indexed(e) = field {
+            self.expression(e);
+        }
+    }
+
+    fn call(&mut self, call: &mut CallSuffix<S>) {
+        match call {
+            CallSuffix::Method(_, args) | CallSuffix::Function(args) => {
+                args.iter_mut().for_each(|e| self.expression(e))
+            }
+        }
+    }
+
+    /// `ARITH[function](operands...)`, each operand in parentheses so that a
+    /// call among them passes on one value only.
+    fn arith_call(&self, function: usize, operands: Vec<Expression<S>>) -> SimpleExpression<S> {
+        let args = operands
+            .into_iter()
+            .map(|e| {
+                literal(SimpleExpression::Suffixed(SuffixedExpression {
+                    primary: PrimaryExpression::GroupedExpression(e),
+                    suffixes: Vec::new(),
+                }))
+            })
+            .collect();
+        SimpleExpression::Suffixed(SuffixedExpression {
+            primary: PrimaryExpression::Name(self.names[function].clone()),
+            suffixes: vec![SuffixPart::Call(CallSuffix::Function(args))],
+        })
+    }
+}
+
+fn literal<S>(simple: SimpleExpression<S>) -> Expression<S> {
+    Expression {
+        head: Box::new(HeadExpression::Simple(simple)),
+        tail: Vec::new(),
+    }
+}
+
+/// Add `statement` after the last statement of `block`. A label that ends the
+/// block has to stay last, or a goto to it past a local would jump into the
+/// local's scope, so the statements go into a `do` block of their own first.
+fn append<S>(block: &mut Block<S>, statement: LineAnnotated<Statement<S>>) {
+    if let Some(last) = block.statements.last()
+        && matches!(last.inner, Statement::Label(_))
+    {
+        let inner = Block {
+            statements: std::mem::take(&mut block.statements),
+            return_statement: None,
+            closed_on: block.closed_on,
+        };
+        let line = inner.statements[0].line_number;
+        block
+            .statements
+            .push(LineAnnotated::new(line, Statement::Do(inner)));
+    }
+    block.statements.push(statement);
+}
+
+/// `e` is a call, which `return` makes a tail call.
+fn tail_call<S>(e: &Expression<S>) -> bool {
+    e.tail.is_empty()
+        && matches!(&*e.head, HeadExpression::Simple(SimpleExpression::Suffixed(s))
+            if matches!(s.suffixes.last(), Some(SuffixPart::Call(_))))
+}
+
+/// Compile `source` as the chunk `name`, with its arithmetic going through
+/// ARITH where it has to. Statements of the script and of host modules set
+/// LINE, with the chunk registered in `tracker`.
+fn load_chunk<'gc>(
+    ctx: Context<'gc>,
+    name: &str,
+    source: &str,
+    tracker: &SharedTracker,
+) -> Result<Closure<'gc>, PrototypeError> {
+    #[derive(Clone, Copy)]
+    struct Interner<'gc>(Context<'gc>);
+
+    impl<'gc> StringInterner for Interner<'gc> {
+        type String = piccolo::String<'gc>;
+
+        fn intern(&mut self, s: &[u8]) -> Self::String {
+            self.0.intern(s)
+        }
+    }
+
+    let chunk = parse_chunk(source.as_bytes(), Interner(ctx))?;
+    let located = builtin_module(name).is_none().then(|| {
+        let mut tracker = tracker.lock().unwrap();
+        tracker.chunks.push(name.to_string());
+        tracker.chunks.len() as i64 - 1
+    });
+    let mut rewrite = ChunkRewrite {
+        names: ARITH.map(|n| ctx.intern(n.as_bytes())),
+        line: ctx.intern(LINE.as_bytes()),
+        chunk: located,
+        caller: ctx.intern(CALLER.as_bytes()),
+        restore: ctx.intern(RESTORE.as_bytes()),
+        this: ctx.intern(b"self"),
+        scope: Vec::new(),
+        declared: 0,
+        mixed: HashSet::new(),
+        changed: false,
+    };
+    let compiled = compile_chunk(&rewrite.chunk(&chunk), Interner(ctx))?;
+    let proto = FunctionPrototype::from_compiled(&ctx, ctx.intern(name.as_bytes()), &compiled);
+    Ok(Closure::new(&ctx, proto, Some(ctx.globals())).expect("chunks only have _ENV as upvalue"))
+}
+
+/// The metamethod `event` of `v`, if it has one.
+fn metamethod<'gc>(ctx: Context<'gc>, v: Value<'gc>, event: &'static str) -> Option<Function<'gc>> {
+    let mt = match v {
+        Value::Table(t) => t.metatable(),
+        Value::UserData(u) => u.metatable(),
+        _ => None,
+    }?;
+    match mt.get(ctx, event) {
+        Value::Function(f) => Some(f),
+        _ => None,
+    }
+}
+
+/// `ARITH[op](a, b)`: `raw(a, b)` for numbers, otherwise the `event`
+/// metamethod of `a` or else of `b`.
+fn arith_operator<'gc>(
+    ctx: Context<'gc>,
+    event: &'static str,
+    raw: for<'a> fn(Value<'a>, Value<'a>) -> Option<Value<'a>>,
+) -> Callback<'gc> {
+    Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+        let (a, b): (Value, Value) = stack.consume(ctx)?;
+        if let Some(v) = raw(a, b) {
+            stack.replace(ctx, v);
+            return Ok(CallbackReturn::Return);
+        }
+        let Some(function) = metamethod(ctx, a, event).or_else(|| metamethod(ctx, b, event)) else {
+            let culprit = if a.to_constant().is_some_and(|c| c.to_number().is_some()) {
+                b
+            } else {
+                a
+            };
+            let msg = format!(
+                "attempt to perform arithmetic on a {} value",
+                culprit.type_name()
+            );
+            return Err(msg.into_value(ctx).into());
+        };
+        stack.replace(ctx, (a, b));
+        Ok(CallbackReturn::Call {
+            function,
+            then: None,
+        })
+    })
+}
+
+fn setup_operators(ctx: Context<'_>) {
+    ctx.set_global(
+        RESTORE,
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            ctx.set_global(LINE, stack.pop_front())?;
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+    let [add, sub, mul, div, unm] = ARITH;
+    ctx.set_global(add, arith_operator(ctx, "__add", raw_ops::add))
+        .unwrap();
+    ctx.set_global(sub, arith_operator(ctx, "__sub", raw_ops::subtract))
+        .unwrap();
+    ctx.set_global(mul, arith_operator(ctx, "__mul", raw_ops::multiply))
+        .unwrap();
+    ctx.set_global(div, arith_operator(ctx, "__div", raw_ops::float_divide))
+        .unwrap();
+    ctx.set_global(
+        unm,
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let a: Value = stack.consume(ctx)?;
+            if let Some(v) = raw_ops::negate(a) {
+                stack.replace(ctx, v);
+                return Ok(CallbackReturn::Return);
+            }
+            let Some(function) = metamethod(ctx, a, "__unm") else {
+                let msg = format!("attempt to perform arithmetic on a {} value", a.type_name());
+                return Err(msg.into_value(ctx).into());
+            };
+            stack.replace(ctx, (a, a));
+            Ok(CallbackReturn::Call {
+                function,
+                then: None,
+            })
+        }),
+    )
+    .unwrap();
+}
+
+// ── vectors ───────────────────────────────────────────────────────────────────
+// vec2, vec3 and mat4 are immutable values backed by nalgebra, with their
+// arithmetic going through the rewritten operators like that of objects. A
+// mat4 is a homogeneous transform: `m * v` maps the vec3 v as a point.
+
+#[derive(Clone, Copy)]
+struct LVec2(na::Vector2<f64>);
+#[derive(Clone, Copy)]
+struct LVec3(na::Vector3<f64>);
+#[derive(Clone, Copy)]
+struct LMat4(na::Matrix4<f64>);
+
+/// A number, vector or matrix, as vector arithmetic sees it.
+#[derive(Clone, Copy)]
+enum Operand {
+    Number(f64),
+    Vec2(na::Vector2<f64>),
+    Vec3(na::Vector3<f64>),
+    Mat4(na::Matrix4<f64>),
+}
+
+impl Operand {
+    /// `v` as an operand; a live number gives its value and is baked.
+    fn of(ctx: Context<'_>, v: Value<'_>) -> Option<Operand> {
+        match live_value(ctx, v) {
+            Some(n) => Some(Operand::Number(n)),
+            None => Operand::peek(v),
+        }
+    }
+
+    /// `v` as an operand, for looking at: a live number stays live.
+    fn peek(v: Value<'_>) -> Option<Operand> {
+        match v {
+            Value::Integer(i) => Some(Operand::Number(i as f64)),
+            Value::Number(n) => Some(Operand::Number(n)),
+            Value::UserData(ud) => {
+                if let Some(s) = live_scalar(v) {
+                    Some(Operand::Number(s.value as f64))
+                } else if let Ok(v) = ud.downcast_static::<LVec2>() {
+                    Some(Operand::Vec2(v.0))
+                } else if let Ok(v) = ud.downcast_static::<LVec3>() {
+                    Some(Operand::Vec3(v.0))
+                } else if let Ok(m) = ud.downcast_static::<LMat4>() {
+                    Some(Operand::Mat4(m.0))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn into_value(self, ctx: Context<'_>) -> Value<'_> {
+        match self {
+            Operand::Number(n) => Value::Number(n),
+            Operand::Vec2(v) => vector_value(ctx, LVec2(v), "__lvec2_mt"),
+            Operand::Vec3(v) => vector_value(ctx, LVec3(v), "__lvec3_mt"),
+            Operand::Mat4(m) => vector_value(ctx, LMat4(m), "__lmat4_mt"),
+        }
+    }
+}
+
+fn vector_value<'gc, T: 'static>(
+    ctx: Context<'gc>,
+    value: T,
+    metatable: &'static str,
+) -> Value<'gc> {
+    let ud = UserData::new_static(&ctx, value);
+    if let Value::Table(mt) = ctx.get_global(metatable) {
+        ud.set_metatable(&ctx, Some(mt));
+    }
+    ud.into()
+}
+
+/// The type of `v` as errors name it.
+fn type_of(v: Value<'_>) -> &'static str {
+    match (v, Operand::peek(v)) {
+        (_, Some(Operand::Vec2(_))) => "vec2",
+        (_, Some(Operand::Vec3(_))) => "vec3",
+        (_, Some(Operand::Mat4(_))) => "mat4",
+        (_, Some(Operand::Number(_))) => "number",
+        (Value::UserData(_), _) => "object",
+        _ => v.type_name(),
+    }
+}
+
+/// `v` as `print` and `tostring` show it, if it is a live number, vector or
+/// matrix.
+fn describe(v: Value<'_>) -> Option<String> {
+    let join = |xs: &mut dyn Iterator<Item = &f64>| {
+        xs.map(|x| x.to_string()).collect::<Vec<_>>().join(", ")
+    };
+    Some(match Operand::peek(v)? {
+        Operand::Number(_) => return live_scalar(v).map(|s| s.value.to_string()),
+        Operand::Vec2(v) => format!("vec2({})", join(&mut v.iter())),
+        Operand::Vec3(v) => format!("vec3({})", join(&mut v.iter())),
+        Operand::Mat4(m) => {
+            let rows: Vec<String> = m.row_iter().map(|r| join(&mut r.iter())).collect();
+            format!("mat4({})", rows.join("; "))
+        }
+    })
+}
+
+#[derive(Clone, Copy)]
+enum VectorOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl VectorOp {
+    fn verb(self) -> &'static str {
+        match self {
+            VectorOp::Add => "add",
+            VectorOp::Sub => "subtract",
+            VectorOp::Mul => "multiply",
+            VectorOp::Div => "divide",
+        }
+    }
+
+    /// `a op b`, if it is defined. Vectors of one size add and subtract,
+    /// scale by numbers, and multiply and divide componentwise; matrices
+    /// multiply with each other and with vec3 points.
+    fn apply(self, a: Operand, b: Operand) -> Option<Operand> {
+        use Operand::*;
+        Some(match (self, a, b) {
+            (VectorOp::Add, Vec2(a), Vec2(b)) => Vec2(a + b),
+            (VectorOp::Add, Vec3(a), Vec3(b)) => Vec3(a + b),
+            (VectorOp::Sub, Vec2(a), Vec2(b)) => Vec2(a - b),
+            (VectorOp::Sub, Vec3(a), Vec3(b)) => Vec3(a - b),
+            (VectorOp::Mul, Vec2(v), Number(s)) | (VectorOp::Mul, Number(s), Vec2(v)) => {
+                Vec2(v * s)
+            }
+            (VectorOp::Mul, Vec3(v), Number(s)) | (VectorOp::Mul, Number(s), Vec3(v)) => {
+                Vec3(v * s)
+            }
+            (VectorOp::Mul, Vec2(a), Vec2(b)) => Vec2(a.component_mul(&b)),
+            (VectorOp::Mul, Vec3(a), Vec3(b)) => Vec3(a.component_mul(&b)),
+            (VectorOp::Mul, Mat4(a), Mat4(b)) => Mat4(a * b),
+            (VectorOp::Mul, Mat4(m), Vec3(v)) => Vec3(m.transform_point(&v.into()).coords),
+            (VectorOp::Div, Vec2(v), Number(s)) => Vec2(v / s),
+            (VectorOp::Div, Vec3(v), Number(s)) => Vec3(v / s),
+            (VectorOp::Div, Vec2(a), Vec2(b)) => Vec2(a.component_div(&b)),
+            (VectorOp::Div, Vec3(a), Vec3(b)) => Vec3(a.component_div(&b)),
+            _ => return None,
+        })
+    }
+}
+
+/// The `__add`, `__sub`, `__mul` or `__div` metamethod of vectors and matrices.
+fn vector_operator(ctx: Context<'_>, op: VectorOp) -> Callback<'_> {
+    Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+        let (a, b): (Value, Value) = stack.consume(ctx)?;
+        let result = Operand::of(ctx, a)
+            .zip(Operand::of(ctx, b))
+            .and_then(|(x, y)| op.apply(x, y));
+        let Some(result) = result else {
+            let msg = format!("cannot {} {} and {}", op.verb(), type_of(a), type_of(b));
+            return Err(msg.into_value(ctx).into());
+        };
+        stack.replace(ctx, result.into_value(ctx));
+        Ok(CallbackReturn::Return)
+    })
+}
+
+/// The numbers in `stack` as N components: N numbers, or one table of N
+/// numbers. Anything else among them is an error naming its position.
+fn components<'gc, const N: usize>(
+    ctx: Context<'gc>,
+    stack: &mut Stack<'gc, '_>,
+    name: &str,
+) -> Result<[f64; N], Error<'gc>> {
+    let (list, values): (bool, Vec<Value>) = match stack.get(0) {
+        Value::Table(t) if stack.len() == 1 => {
+            (true, (1..=t.length()).map(|i| t.get(ctx, i)).collect())
+        }
+        _ => (false, stack.drain(..).collect()),
+    };
+    stack.clear();
+    let mut numbers = Vec::with_capacity(values.len());
+    for (i, v) in values.into_iter().enumerate() {
+        let Some(Operand::Number(n)) = Operand::of(ctx, v) else {
+            let i = i + 1;
+            return Err(if list {
+                argument_error(
+                    ctx,
+                    1,
+                    &format!("{name} expects a number at position {i} in list"),
+                )
+            } else {
+                argument_error(
+                    ctx,
+                    i as u32,
+                    &format!("{name} expects a number as argument {i}"),
+                )
+            });
+        };
+        numbers.push(n);
+    }
+    numbers.try_into().map_err(|_| {
+        let msg = format!("{name} expects {N} numbers or a table of {N} numbers");
+        msg.into_value(ctx).into()
+    })
+}
+
+/// The rest of `stack` as x, y and z: a vec3 or three numbers.
+fn xyz_args<'gc, T: FromValue<'gc>>(
+    ctx: Context<'gc>,
+    stack: &mut Stack<'gc, '_>,
+    name: &str,
+) -> Result<[T; 3], Error<'gc>> {
+    let values = match Operand::of(ctx, stack.get(0)) {
+        Some(Operand::Vec3(v)) if stack.len() == 1 => [v.x, v.y, v.z].map(Value::Number),
+        _ => [0, 1, 2].map(|i| stack.get(i)),
+    };
+    stack.clear();
+    let bad = || format!("{name} expects a vec3 or three numbers").into_value(ctx);
+    // Where T is a plain number, a live number gives its value.
+    let plain = |v| match Operand::of(ctx, v) {
+        Some(Operand::Number(n)) => Value::Number(n),
+        _ => v,
+    };
+    let [x, y, z] = values.map(|v| T::from_value(ctx, v).or_else(|_| T::from_value(ctx, plain(v))));
+    Ok([
+        x.map_err(|_| bad())?,
+        y.map_err(|_| bad())?,
+        z.map_err(|_| bad())?,
+    ])
+}
+
+/// `rgb` as a colour, if each component is between 0 and 1.
+fn check_color<'gc>(ctx: Context<'gc>, rgb: [f64; 3]) -> Result<[f32; 3], Error<'gc>> {
+    if rgb.iter().all(|c| (0.0..=1.0).contains(c)) {
+        Ok(rgb.map(|c| c as f32))
+    } else {
+        Err("color components must be between 0 and 1"
+            .into_value(ctx)
+            .into())
+    }
+}
+
+/// The colour argument `v` of `name`: a vec3 or a table {r, g, b}.
+fn color_arg<'gc>(ctx: Context<'gc>, v: Value<'gc>, name: &str) -> Result<[f32; 3], Error<'gc>> {
+    let number = |v| match Operand::of(ctx, v) {
+        Some(Operand::Number(n)) => Some(n),
+        _ => None,
+    };
+    let rgb = match (Operand::of(ctx, v), v) {
+        (Some(Operand::Vec3(c)), _) => Some([c.x, c.y, c.z]),
+        (_, Value::Table(t)) if t.length() == 3 => match [1, 2, 3].map(|i| number(t.get(ctx, i))) {
+            [Some(r), Some(g), Some(b)] => Some([r, g, b]),
+            _ => None,
+        },
+        _ => None,
+    };
+    match rgb {
+        Some(rgb) => check_color(ctx, rgb),
+        None => Err(format!("{name} color must be a vec3 or a table {{r,g,b}}")
+            .into_value(ctx)
+            .into()),
+    }
+}
+
+/// The vec3 argument `v` of `name`.
+fn vec3_arg<'gc>(
+    ctx: Context<'gc>,
+    v: Value<'gc>,
+    name: &str,
+) -> Result<na::Vector3<f64>, Error<'gc>> {
+    match Operand::of(ctx, v) {
+        Some(Operand::Vec3(v)) => Ok(v),
+        _ => Err(format!("{name} expects a vec3, got {}", type_of(v))
+            .into_value(ctx)
+            .into()),
+    }
+}
+
+/// The mat4 argument `m` of `name`.
+fn mat4_arg<'gc>(
+    ctx: Context<'gc>,
+    m: Value<'gc>,
+    name: &str,
+) -> Result<na::Matrix4<f64>, Error<'gc>> {
+    match Operand::of(ctx, m) {
+        Some(Operand::Mat4(m)) => Ok(m),
+        _ => Err(format!("{name} expects a mat4, got {}", type_of(m))
+            .into_value(ctx)
+            .into()),
+    }
+}
+
+/// `__index` of a vector type: the components by name or position, else
+/// `methods`.
+fn vector_index<'gc>(ctx: Context<'gc>, methods: Table<'gc>) -> Callback<'gc> {
+    Callback::from_fn_with(&ctx, methods, |methods, ctx, _, mut stack| {
+        let (v, key): (Value, Value) = stack.consume(ctx)?;
+        let axis = match key {
+            Value::String(k) => ["x", "y", "z"]
+                .iter()
+                .position(|a| a.as_bytes() == k.as_bytes()),
+            Value::Integer(i @ 1..=3) => Some(i as usize - 1),
+            _ => None,
+        };
+        let value = match (Operand::of(ctx, v), axis) {
+            (Some(Operand::Vec2(v)), Some(i)) if i < 2 => Value::Number(v[i]),
+            (Some(Operand::Vec3(v)), Some(i)) => Value::Number(v[i]),
+            _ => methods.get(ctx, key),
+        };
+        stack.replace(ctx, value);
+        Ok(CallbackReturn::Return)
+    })
+}
+
+/// Methods of vec2 (`DIM` 2) or vec3 (`DIM` 3).
+fn vector_methods<const DIM: usize>(ctx: Context<'_>) -> Table<'_> {
+    let methods = Table::new(&ctx);
+
+    methods
+        .set(
+            ctx,
+            "dot",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (a, b): (Value, Value) = stack.consume(ctx)?;
+                let dot = match (Operand::of(ctx, a), Operand::of(ctx, b)) {
+                    (Some(Operand::Vec2(a)), Some(Operand::Vec2(b))) => a.dot(&b),
+                    (Some(Operand::Vec3(a)), Some(Operand::Vec3(b))) => a.dot(&b),
+                    _ => {
+                        let msg = format!(
+                            "cannot take the dot product of {} and {}",
+                            type_of(a),
+                            type_of(b)
+                        );
+                        return Err(msg.into_value(ctx).into());
+                    }
+                };
+                stack.replace(ctx, dot);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    if DIM == 3 {
+        methods
+            .set(
+                ctx,
+                "cross",
+                Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                    let (a, b): (Value, Value) = stack.consume(ctx)?;
+                    let (Some(Operand::Vec3(x)), Some(Operand::Vec3(y))) =
+                        (Operand::of(ctx, a), Operand::of(ctx, b))
+                    else {
+                        let msg = format!(
+                            "cannot take the cross product of {} and {}",
+                            type_of(a),
+                            type_of(b)
+                        );
+                        return Err(msg.into_value(ctx).into());
+                    };
+                    stack.replace(ctx, Operand::Vec3(x.cross(&y)).into_value(ctx));
+                    Ok(CallbackReturn::Return)
+                }),
+            )
+            .unwrap();
+    }
+
+    methods
+        .set(
+            ctx,
+            "length",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let v: Value = stack.consume(ctx)?;
+                let length = match Operand::of(ctx, v) {
+                    Some(Operand::Vec2(v)) => v.norm(),
+                    Some(Operand::Vec3(v)) => v.norm(),
+                    _ => {
+                        return Err(format!("cannot take the length of {}", type_of(v))
+                            .into_value(ctx)
+                            .into());
+                    }
+                };
+                stack.replace(ctx, length);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "normalize",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let v: Value = stack.consume(ctx)?;
+                let unit = match Operand::of(ctx, v) {
+                    Some(Operand::Vec2(v)) => v.try_normalize(EPSILON).map(Operand::Vec2),
+                    Some(Operand::Vec3(v)) => v.try_normalize(EPSILON).map(Operand::Vec3),
+                    _ => {
+                        return Err(format!("cannot normalize {}", type_of(v))
+                            .into_value(ctx)
+                            .into());
+                    }
+                };
+                let Some(unit) = unit else {
+                    return Err("cannot normalize a zero-length vector"
+                        .into_value(ctx)
+                        .into());
+                };
+                stack.replace(ctx, unit.into_value(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // v:unpack(): the components as separate values
+    methods
+        .set(
+            ctx,
+            "unpack",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let v: Value = stack.consume(ctx)?;
+                match Operand::of(ctx, v) {
+                    Some(Operand::Vec2(v)) => stack.replace(ctx, (v.x, v.y)),
+                    Some(Operand::Vec3(v)) => stack.replace(ctx, (v.x, v.y, v.z)),
+                    _ => {
+                        return Err(format!("cannot unpack {}", type_of(v))
+                            .into_value(ctx)
+                            .into());
+                    }
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+}
+
+fn matrix_methods(ctx: Context<'_>) -> Table<'_> {
+    let methods = Table::new(&ctx);
+
+    methods
+        .set(
+            ctx,
+            "inverse",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let m = mat4_arg(ctx, stack.get(0), "inverse")?;
+                let Some(inverse) = m.try_inverse() else {
+                    return Err("matrix is not invertible".into_value(ctx).into());
+                };
+                stack.replace(ctx, Operand::Mat4(inverse).into_value(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+        .set(
+            ctx,
+            "transpose",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let m = mat4_arg(ctx, stack.get(0), "transpose")?;
+                stack.replace(ctx, Operand::Mat4(m.transpose()).into_value(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // m:transform_vector(v): v mapped as a direction, without the translation
+    methods
+        .set(
+            ctx,
+            "transform_vector",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (m, v): (Value, Value) = stack.consume(ctx)?;
+                let m = mat4_arg(ctx, m, "transform_vector")?;
+                let v = vec3_arg(ctx, v, "transform_vector")?;
+                stack.replace(ctx, Operand::Vec3(m.transform_vector(&v)).into_value(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    // m:unpack(): the 16 entries, row by row
+    methods
+        .set(
+            ctx,
+            "unpack",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let m = mat4_arg(ctx, stack.get(0), "unpack")?;
+                stack.clear();
+                for row in m.row_iter() {
+                    row.iter().for_each(|&x| stack.push_back(Value::Number(x)));
+                }
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+
+    methods
+}
+
+/// Builds a matrix from the arguments of a `mat4` constructor.
+type Construct =
+    for<'gc, 'a> fn(Context<'gc>, Stack<'gc, 'a>) -> Result<na::Matrix4<f64>, Error<'gc>>;
+
+/// Constructors of `mat4`, all returning transforms meant for `obj:transform`.
+fn matrix_constructors(ctx: Context<'_>) -> Table<'_> {
+    let mat4 = Table::new(&ctx);
+    let constructor = |name: &'static str, f: Construct| {
+        let callback = Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+            let m = f(ctx, stack.sub_stack(0))?;
+            stack.replace(ctx, Operand::Mat4(m).into_value(ctx));
+            Ok(CallbackReturn::Return)
+        });
+        mat4.set(ctx, name, callback).unwrap();
+    };
+
+    constructor("identity", |_, _| Ok(na::Matrix4::identity()));
+    constructor("translation", |ctx, mut stack| {
+        let t: [f64; 3] = xyz_args(ctx, &mut stack, "translation")?;
+        Ok(na::Matrix4::new_translation(&t.into()))
+    });
+    // mat4.scaling(s) scales uniformly
+    constructor("scaling", |ctx, mut stack| {
+        if let (1, Some(Operand::Number(s))) = (stack.len(), Operand::of(ctx, stack.get(0))) {
+            return Ok(na::Matrix4::new_scaling(s));
+        }
+        let s: [f64; 3] = xyz_args(ctx, &mut stack, "scaling")?;
+        Ok(na::Matrix4::new_nonuniform_scaling(&s.into()))
+    });
+    // The rotation `obj:rotate(x, y, z)` applies: the inverse of nalgebra's
+    // Euler rotation, see `Rotate`.
+    constructor("rotation", |ctx, mut stack| {
+        let [x, y, z]: [f64; 3] = xyz_args(ctx, &mut stack, "rotation")?;
+        Ok(na::Rotation3::from_euler_angles(x, y, z)
+            .inverse()
+            .to_homogeneous())
+    });
+    // mat4.axis_angle(axis, angle): counterclockwise looking down the axis
+    constructor("axis_angle", |ctx, mut stack| {
+        let (axis, angle): (Value, f64) = stack.consume(ctx)?;
+        let axis = vec3_arg(ctx, axis, "axis_angle")?;
+        let Some(axis) = na::Unit::try_new(axis, EPSILON) else {
+            return Err("rotation axis must not be zero".into_value(ctx).into());
+        };
+        Ok(na::Rotation3::from_axis_angle(&axis, angle).to_homogeneous())
+    });
+
+    // mat4(a, b, ...): the matrix with these 16 entries, row by row
+    let metatable = Table::new(&ctx);
+    metatable
+        .set(
+            ctx,
+            MetaMethod::Call,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                stack.pop_front();
+                let m: [f64; 16] = components(ctx, &mut stack, "mat4")?;
+                stack.replace(
+                    ctx,
+                    Operand::Mat4(na::Matrix4::from_row_slice(&m)).into_value(ctx),
+                );
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+    mat4.set_metatable(&ctx, Some(metatable));
+    mat4
+}
+
+fn setup_vectors(ctx: Context<'_>) {
+    let types = [
+        ("__lvec2_mt", vector_methods::<2>(ctx)),
+        ("__lvec3_mt", vector_methods::<3>(ctx)),
+        ("__lmat4_mt", matrix_methods(ctx)),
+    ];
+    for (name, methods) in types {
+        let mt = Table::new(&ctx);
+        mt.set(ctx, MetaMethod::Index, vector_index(ctx, methods))
+            .unwrap();
+        // kept for setup_tracing
+        mt.set(ctx, "__methods", methods).unwrap();
+        mt.set(ctx, "__add", vector_operator(ctx, VectorOp::Add))
+            .unwrap();
+        mt.set(ctx, "__sub", vector_operator(ctx, VectorOp::Sub))
+            .unwrap();
+        mt.set(ctx, "__mul", vector_operator(ctx, VectorOp::Mul))
+            .unwrap();
+        mt.set(ctx, "__div", vector_operator(ctx, VectorOp::Div))
+            .unwrap();
+        mt.set(
+            ctx,
+            "__unm",
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let v: Value = stack.from_front(ctx)?;
+                let negated = match Operand::of(ctx, v) {
+                    Some(Operand::Vec2(v)) => Operand::Vec2(-v),
+                    Some(Operand::Vec3(v)) => Operand::Vec3(-v),
+                    _ => {
+                        return Err(format!("cannot negate {}", type_of(v))
+                            .into_value(ctx)
+                            .into());
+                    }
+                };
+                stack.replace(ctx, negated.into_value(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+        mt.set(
+            ctx,
+            MetaMethod::Len,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let v: Value = stack.from_front(ctx)?;
+                let len: i64 = match Operand::of(ctx, v) {
+                    Some(Operand::Vec2(_)) => 2,
+                    Some(Operand::Vec3(_)) => 3,
+                    _ => {
+                        return Err(format!("cannot take the length of {}", type_of(v))
+                            .into_value(ctx)
+                            .into());
+                    }
+                };
+                stack.replace(ctx, len);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+        mt.set(
+            ctx,
+            MetaMethod::Eq,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let (a, b): (Value, Value) = stack.consume(ctx)?;
+                let equal = match (Operand::of(ctx, a), Operand::of(ctx, b)) {
+                    (Some(Operand::Vec2(a)), Some(Operand::Vec2(b))) => a == b,
+                    (Some(Operand::Vec3(a)), Some(Operand::Vec3(b))) => a == b,
+                    (Some(Operand::Mat4(a)), Some(Operand::Mat4(b))) => a == b,
+                    _ => false,
+                };
+                stack.replace(ctx, equal);
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+        mt.set(
+            ctx,
+            MetaMethod::ToString,
+            Callback::from_fn(&ctx, |ctx, _, mut stack| {
+                let v: Value = stack.consume(ctx)?;
+                stack.replace(ctx, describe(v).unwrap_or_default().into_value(ctx));
+                Ok(CallbackReturn::Return)
+            }),
+        )
+        .unwrap();
+        ctx.set_global(name, mt).unwrap();
+    }
+
+    ctx.set_global(
+        "vec2",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let v: [f64; 2] = components(ctx, &mut stack, "vec2")?;
+            stack.replace(ctx, Operand::Vec2(v.into()).into_value(ctx));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+    ctx.set_global(
+        "vec3",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            let v: [f64; 3] = components(ctx, &mut stack, "vec3")?;
+            stack.replace(ctx, Operand::Vec3(v.into()).into_value(ctx));
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+    ctx.set_global("mat4", matrix_constructors(ctx)).unwrap();
+
+    // __xyz(v): the components of v if it is a vec3, else nothing
+    ctx.set_global(
+        "__xyz",
+        Callback::from_fn(&ctx, |ctx, _, mut stack| {
+            match Operand::of(ctx, stack.get(0)) {
+                Some(Operand::Vec3(v)) => stack.replace(ctx, (v.x, v.y, v.z)),
+                _ => stack.clear(),
+            }
+            Ok(CallbackReturn::Return)
+        }),
+    )
+    .unwrap();
+}
+
+// ── modules ───────────────────────────────────────────────────────────────────
+// `require(name)` runs the module the host supplied under that name, once per
+// run, and returns what it returned. Modules share the script's globals, and
+// their chunk name is the module name, so errors point into them.
+//
+// The modules truescad bundles come first. Like the prelude, they are library
+// code: their functions are traced, and errors point at the script's call.
+
+/// Modules every script can require, by name.
+const BUILTIN_MODULES: &[(&str, &str)] = &[("truescad.parts", include_str!("parts.lua"))];
+
+fn builtin_module(name: &str) -> Option<&'static str> {
+    BUILTIN_MODULES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, source)| *source)
+}
+
+/// Stores a module's result in `loaded` once it ran.
+#[derive(Collect)]
+#[collect(no_drop)]
+struct ModuleLoaded<'gc> {
+    loaded: Table<'gc>,
+    #[collect(require_static)]
+    name: String,
+    #[collect(require_static)]
+    loading: Arc<Mutex<Vec<String>>>,
+    /// Set for bundled modules, whose functions are traced.
+    #[collect(require_static)]
+    tracker: Option<SharedTracker>,
+}
+
+impl<'gc> Sequence<'gc> for ModuleLoaded<'gc> {
+    fn poll(
+        &mut self,
+        ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        mut stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        self.loading.lock().unwrap().pop();
+        let value = match stack.get(0) {
+            Value::Nil => Value::Boolean(true),
+            v => v,
+        };
+        if let (Some(tracker), Value::Table(exports)) = (&self.tracker, value) {
+            trace_table(ctx, exports, &[], tracker);
+        }
+        self.loaded
+            .set(ctx, ctx.intern(self.name.as_bytes()), value)?;
+        stack.replace(ctx, value);
+        Ok(SequencePoll::Return)
+    }
+
+    fn error(
+        &mut self,
+        _ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        _stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        self.loading.lock().unwrap().pop();
+        Err(error)
+    }
+}
+
+/// Chunk names of the evaluator's own code, which no module can take.
+const RESERVED_CHUNKS: [&str; 2] = ["script", "prelude"];
+
+/// `require`, with the results of the modules that ran kept where scripts
+/// cannot reach them.
+fn setup_require(ctx: Context<'_>, modules: Arc<Modules>, tracker: SharedTracker) {
+    let loading: Arc<Mutex<Vec<String>>> = Arc::default();
+    ctx.set_global(
+        "require",
+        Callback::from_fn_with(&ctx, Table::new(&ctx), move |&loaded, ctx, _, mut stack| {
+            let lua_name: piccolo::String = stack.consume(ctx)?;
+            let cached = loaded.get(ctx, lua_name);
+            if !cached.is_nil() {
+                stack.replace(ctx, cached);
+                return Ok(CallbackReturn::Return);
+            }
+            let name = lua_string(lua_name);
+            if RESERVED_CHUNKS.contains(&name.as_str()) {
+                return Err(format!("module name '{name}' is reserved")
+                    .into_value(ctx)
+                    .into());
+            }
+            {
+                let loading = loading.lock().unwrap();
+                if loading.contains(&name) {
+                    let chain = loading.join(" -> ");
+                    let msg = format!("module '{name}' requires itself: {chain} -> {name}");
+                    return Err(msg.into_value(ctx).into());
+                }
+            }
+            let builtin = builtin_module(&name);
+            let Some(source) = builtin.or_else(|| modules.get(&name).map(String::as_str)) else {
+                return Err(format!("module '{name}' not found").into_value(ctx).into());
+            };
+            let closure = match load_chunk(ctx, &name, source, &tracker) {
+                Ok(c) => c,
+                Err(e) => {
+                    let mut tracker = tracker.lock().unwrap();
+                    tracker.module = Some((tracker.calls.len(), name));
+                    return Err(e.into());
+                }
+            };
+            loading.lock().unwrap().push(name.clone());
+            stack.replace(ctx, lua_name);
+            let tracker = builtin.map(|_| tracker.clone());
+            let loaded = ModuleLoaded {
+                loaded,
+                name,
+                loading: loading.clone(),
+                tracker,
+            };
+            Ok(CallbackReturn::Call {
+                function: closure.into(),
+                then: Some(BoxSequence::new(&ctx, loaded)),
+            })
+        }),
+    )
+    .unwrap();
+}
+
+// ── call tracing ──────────────────────────────────────────────────────────────
+// piccolo keeps no line information in errors, and a callback can only see the
+// Lua frame that called it. So every function the prelude gives scripts is
+// wrapped in a callback that records where it was called from, and the calls
+// in progress when an error passes through make the traceback.
+
+/// A call from Lua code into a function the prelude provides.
+#[derive(Clone, Debug)]
+struct CallSite {
+    function: String,
+    chunk: String,
+    /// 1-based; 0 when the caller is not Lua code.
+    line: u32,
+    /// The Lua function making the call, as a traceback names it.
+    caller: String,
+}
+
+#[derive(Default)]
+struct CallTracker {
+    calls: Vec<CallSite>,
+    /// The error that left a traced call first, as text, with the calls in
+    /// progress at that point.
+    failure: Option<(String, Vec<CallSite>)>,
+    /// The argument `argerror` blamed, with the depth of its call.
+    argument: Option<(usize, u32)>,
+    /// The module `require` failed to compile, with the depth of its call.
+    module: Option<(usize, String)>,
+    /// Chunks whose statements set LINE, by their index in it.
+    chunks: Vec<String>,
+    /// Chunk and line LINE held when the run failed.
+    line: Option<(String, u32)>,
+}
+
+type SharedTracker = Arc<Mutex<CallTracker>>;
+
+/// Pops the call when the traced function returns or fails.
+#[derive(Collect)]
+#[collect(require_static)]
+struct EndCall(SharedTracker);
+
+impl<'gc> Sequence<'gc> for EndCall {
+    fn poll(
+        &mut self,
+        _ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        _stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let mut tracker = self.0.lock().unwrap();
+        tracker.calls.pop();
+        // A failure deeper down that this call survived was caught by pcall.
+        let depth = tracker.calls.len();
+        if tracker
+            .failure
+            .as_ref()
+            .is_some_and(|(_, calls)| calls.len() > depth)
+        {
+            tracker.failure = None;
+        }
+        if tracker.argument.is_some_and(|(d, _)| d > depth) {
+            tracker.argument = None;
+        }
+        if tracker.module.as_ref().is_some_and(|(d, _)| *d > depth) {
+            tracker.module = None;
+        }
+        Ok(SequencePoll::Return)
+    }
+
+    fn error(
+        &mut self,
+        _ctx: Context<'gc>,
+        _exec: Execution<'gc, '_>,
+        error: Error<'gc>,
+        _stack: Stack<'gc, '_>,
+    ) -> Result<SequencePoll<'gc>, Error<'gc>> {
+        let mut tracker = self.0.lock().unwrap();
+        if tracker.failure.is_none() {
+            tracker.failure = Some((error.to_string(), tracker.calls.clone()));
+        }
+        tracker.calls.pop();
+        Err(error)
+    }
+}
+
+/// `function` wrapped to record its calls as `name`.
+fn traced<'gc>(
+    ctx: Context<'gc>,
+    name: String,
+    function: Function<'gc>,
+    tracker: SharedTracker,
+) -> Callback<'gc> {
+    Callback::from_fn_with(&ctx, function, move |function, ctx, exec, _| {
+        let site = match exec.upper_lua_frame() {
+            Some(frame) => {
+                let chunk = lua_string(frame.chunk_name);
+                let caller = match frame.current_function {
+                    FunctionRef::Named(n, _) => format!("function '{}'", lua_string(n)),
+                    FunctionRef::Expression(l) => format!("function <{chunk}:{l}>"),
+                    FunctionRef::Chunk => "main chunk".to_string(),
+                };
+                let line = frame.current_line.0 as u32 + 1;
+                CallSite {
+                    function: name.clone(),
+                    chunk,
+                    line,
+                    caller,
+                }
+            }
+            None => CallSite {
+                function: name.clone(),
+                chunk: "[C]".to_string(),
+                line: 0,
+                caller: "?".to_string(),
+            },
+        };
+        tracker.lock().unwrap().calls.push(site);
+        let end = BoxSequence::new(&ctx, EndCall(tracker.clone()));
+        Ok(CallbackReturn::Call {
+            function: *function,
+            then: Some(end),
+        })
+    })
+}
+
+/// Trace the functions in `table`, except internal `__` ones and those in
+/// `skip`.
+fn trace_table<'gc>(
+    ctx: Context<'gc>,
+    table: Table<'gc>,
+    skip: &[String],
+    tracker: &SharedTracker,
+) {
+    let functions: Vec<(String, Function<'gc>)> = table
+        .iter()
+        .filter_map(|(k, v)| match (k, v) {
+            (Value::String(k), Value::Function(f)) => Some((lua_string(k), f)),
+            _ => None,
+        })
+        .filter(|(k, _)| !k.starts_with("__") && !skip.contains(k))
+        .collect();
+    for (name, f) in functions {
+        let wrapped = traced(ctx, name.clone(), f, tracker.clone());
+        table.set(ctx, name, wrapped).unwrap();
+    }
+}
+
+/// Trace the globals the prelude defined (the ones not in `stdlib`, plus
+/// `error` and `assert`), the `mat4` constructors and the object, vector and
+/// matrix methods.
+fn setup_tracing(ctx: Context<'_>, stdlib: &[String], tracker: SharedTracker) {
+    let skip: Vec<String> = stdlib
+        .iter()
+        .filter(|k| *k != "error" && *k != "assert")
+        .cloned()
+        .collect();
+    trace_table(ctx, ctx.globals(), &skip, &tracker);
+    if let Value::Table(mt) = ctx.get_global("__lobj_mt")
+        && let Value::Table(methods) = mt.get(ctx, MetaMethod::Index)
+    {
+        trace_table(ctx, methods, &[], &tracker);
+    }
+    for name in ["__lvec2_mt", "__lvec3_mt", "__lmat4_mt"] {
+        if let Value::Table(mt) = ctx.get_global(name)
+            && let Value::Table(methods) = mt.get(ctx, "__methods")
+        {
+            trace_table(ctx, methods, &[], &tracker);
+        }
+    }
+    if let Value::Table(mat4) = ctx.get_global("mat4") {
+        trace_table(ctx, mat4, &[], &tracker);
+    }
+    // __argerror(n, msg): fail blaming argument n of the traced call in progress
+    ctx.set_global(
+        "__argerror",
+        Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+            let (n, msg): (u32, Value) = stack.consume(ctx)?;
+            let mut tracker = tracker.lock().unwrap();
+            tracker.argument = Some((tracker.calls.len(), n));
+            Err(msg.into())
+        }),
+    )
+    .unwrap();
+}
+
+const LUA_ALIASES: &str = r#"
+pi  = 3.14159265358979323846
+tau = 6.28318530717958647692
+
+-- error() blaming argument n of the function the script called
+local function argerror(n, msg) __argerror(n, msg) end
+
+-- x, y, z of v, a vec3 or a table of three numbers; nothing if it is neither
+local function xyz(v)
+    if type(v) == "userdata" then return __xyz(v) end
+    if type(v) ~= "table" or #v ~= 3 then return end
+    for i = 1, 3 do
+        if type(v[i]) ~= "number" then return end
+    end
+    return v[1], v[2], v[3]
+end
+
+-- v is a number, plain or live
+local function isnumber(v) return type(v) == "number" or __islive(v) end
+
+function Box(x, y, z, smooth)
+    local size = {x, y, z}
+    for i = 1, 3 do
+        if not isnumber(size[i]) then argerror(i, "all arguments must be numbers") end
+    end
+    local s = 0
+    if type(smooth) == "number" then s = smooth end
+    return __Box(x, y, z, s)
+end
+
+function Cylinder(arg)
+    if type(arg) ~= "table" then argerror(1, "Cylinder expects a table of named arguments") end
+    if not isnumber(arg.l) then argerror(1, "l must be a valid number") end
+    local r1, r2
+    if isnumber(arg.r) then
+        r1, r2 = arg.r, arg.r
+    elseif isnumber(arg.r1) and isnumber(arg.r2) then
+        r1, r2 = arg.r1, arg.r2
+    else
+        argerror(1, "specify either r or r1 and r2")
+    end
+    local s = 0
+    if type(arg.s) == "number" then s = arg.s end
+    return __Cylinder(arg.l, r1, r2, s)
+end
+
+function Gear(arg)
+    if type(arg) ~= "table" then argerror(1, "Gear expects a table of named arguments") end
+    if type(arg.module) ~= "number" or arg.module <= 0 then
+        argerror(1, "module must be a positive number")
+    end
+    if math.type(arg.teeth) ~= "integer" or arg.teeth < 3 then
+        argerror(1, "teeth must be an integer >= 3")
+    end
+    if type(arg.pressure_angle) ~= "number" or
+        arg.pressure_angle <= 0 or arg.pressure_angle >= pi / 2 then
+        argerror(1, "pressure_angle must be a number in (0, pi/2) radians")
+    end
+    if type(arg.thickness) ~= "number" or arg.thickness <= 0 then
+        argerror(1, "thickness must be a positive number")
+    end
+    local helix, bore = 0, 0
+    if arg.helix_angle ~= nil then
+        if type(arg.helix_angle) ~= "number" or math.abs(arg.helix_angle) >= pi / 2 then
+            argerror(1, "helix_angle must be a number in (-pi/2, pi/2) radians")
+        end
+        helix = arg.helix_angle
+    end
+    if arg.bore ~= nil then
+        if type(arg.bore) ~= "number" or arg.bore < 0 then
+            argerror(1, "bore must be a non-negative number")
+        end
+        bore = arg.bore
+    end
+    return __Gear(arg.module, arg.teeth, arg.pressure_angle, arg.thickness, helix, bore)
+end
+
+function Blobs(balls, threshold)
+    if type(balls) ~= "table" or #balls == 0 then
+        argerror(1, "first argument must be a non-empty list of {x,y,z,r} or {vec3,r} tables")
+    end
+    local list = {}
+    for i = 1, #balls do
+        local b = balls[i]
+        if type(b) ~= "table" or (#b ~= 4 and #b ~= 2) then
+            argerror(1, "ball " .. i .. " must be a table {x,y,z,r} or {vec3,r}")
+        end
+        local x, y, z, r
+        if #b == 2 then
+            x, y, z = __xyz(b[1])
+            r = b[2]
+        else
+            x, y, z, r = b[1], b[2], b[3], b[4]
+        end
+        if #b == 2 and x == nil then argerror(1, "ball " .. i .. " centre must be a vec3") end
+        local entries = {x, y, z, r}
+        for j = 1, 4 do
+            if type(entries[j]) ~= "number" then argerror(1, "ball " .. i .. " entries must be numbers") end
+        end
+        if r <= 0 then argerror(1, "ball " .. i .. " radius must be positive") end
+        list[i] = {x, y, z, r}
+    end
+    if type(threshold) ~= "number" or threshold <= 0 or threshold >= 1 then
+        argerror(2, "threshold must be a number in (0, 1)")
+    end
+    return __Blobs(list, threshold)
+end
+
+function Minkowski(obj, kernel)
+    if type(kernel) ~= "table" then
+        argerror(2, "second argument must be a kernel table: {ball=r}, {box={x,y,z}} or {cylinder={l=,r=}}")
+    end
+    if kernel.ball ~= nil then
+        if type(kernel.ball) ~= "number" or kernel.ball < 0 then
+            argerror(2, "ball radius must be a non-negative number")
+        end
+        return __Minkowski(obj, "ball", kernel.ball, 0, 0)
+    elseif kernel.box ~= nil then
+        local x, y, z = xyz(kernel.box)
+        if x == nil then argerror(2, "box must be a vec3 or a table {x,y,z}") end
+        if x < 0 or y < 0 or z < 0 then argerror(2, "box sizes must be non-negative numbers") end
+        return __Minkowski(obj, "box", x / 2, y / 2, z / 2)
+    elseif kernel.cylinder ~= nil then
+        local c = kernel.cylinder
+        if type(c) ~= "table" or type(c.r) ~= "number" or type(c.l) ~= "number" or
+            c.r < 0 or c.l < 0 then
+            argerror(2, "cylinder must be a table {l=length, r=radius} of non-negative numbers")
+        end
+        return __Minkowski(obj, "cylinder", c.r, c.l / 2, 0)
+    end
+    argerror(2, "kernel must have one of the keys ball, box or cylinder")
+end
+
+function param(name, default, opts)
+    if type(name) ~= "string" then argerror(1, "parameter name must be a string") end
+    opts = opts or {}
+    if type(opts) ~= "table" then argerror(3, "third argument must be an options table") end
+    local kind = opts.type
+    if kind == nil then
+        if opts.options ~= nil then kind = "enum"
+        elseif type(default) == "boolean" then kind = "boolean"
+        elseif type(default) == "string" then kind = "string"
+        else kind = "number" end
+    end
+    for _, key in ipairs({"description", "group"}) do
+        if opts[key] ~= nil and type(opts[key]) ~= "string" then argerror(3, key .. " must be a string") end
+    end
+    if kind ~= "enum" and opts.options ~= nil then argerror(3, "options are only for enum parameters") end
+    if opts.live ~= nil and type(opts.live) ~= "boolean" then argerror(3, "live must be a boolean") end
+    if opts.live and kind ~= "number" then argerror(3, "only number parameters can be live") end
+    if kind == "number" or kind == "integer" then
+        if type(default) ~= "number" then argerror(2, "default must be a number") end
+        for _, key in ipairs({"min", "max", "step"}) do
+            if opts[key] ~= nil and type(opts[key]) ~= "number" then
+                argerror(3, key .. " must be a number")
+            end
+        end
+        if (opts.min ~= nil and default < opts.min) or (opts.max ~= nil and default > opts.max) then
+            argerror(2, "default must lie between min and max")
+        end
+        if opts.step ~= nil and opts.step <= 0 then argerror(3, "step must be positive") end
+        if kind == "integer" then
+            if opts.step ~= nil then argerror(3, "integer parameters always step by 1") end
+            if default % 1 ~= 0 then argerror(2, "default must be an integer") end
+            for _, key in ipairs({"min", "max"}) do
+                if opts[key] ~= nil and opts[key] % 1 ~= 0 then
+                    argerror(3, key .. " must be an integer")
+                end
+            end
+        end
+    elseif kind == "boolean" then
+        if type(default) ~= "boolean" then argerror(2, "default must be a boolean") end
+    elseif kind == "string" then
+        if type(default) ~= "string" then argerror(2, "default must be a string") end
+    elseif kind == "enum" then
+        if type(opts.options) ~= "table" or #opts.options == 0 then
+            argerror(3, "options must be a non-empty list")
+        end
+        local found = false
+        for i = 1, #opts.options do
+            local o = opts.options[i]
+            if type(o) ~= "number" and type(o) ~= "string" then
+                argerror(3, "options must be numbers or strings")
+            end
+            if o == default then found = true end
+        end
+        if not found then argerror(2, "default must be one of the options") end
+    else
+        argerror(3, "type must be number, integer, boolean, enum or string")
+    end
+    return __param(name, kind, default, opts.min, opts.max, opts.step,
+        opts.description, opts.group, opts.options, opts.live)
+end
+
+-- the corners of b, argument n, as {x,y,z} tables; fails unless b is a box
+-- {min={x,y,z}, max={x,y,z}} or {min=vec3, max=vec3}
+local function checkbounds(b, n)
+    if type(b) ~= "table" then
+        argerror(n, "bounds must be a table {min={x,y,z}, max={x,y,z}}")
+    end
+    local min, max = {xyz(b.min)}, {xyz(b.max)}
+    if #min ~= 3 or #max ~= 3 then
+        argerror(n, "bounds min and max must be vec3 values or tables of 3 numbers")
+    end
+    for i = 1, 3 do
+        if min[i] >= max[i] then argerror(n, "bounds min must be below max on every axis") end
+    end
+    return min, max
+end
+
+function build(obj, opts)
+    if opts ~= nil and type(opts) ~= "table" then argerror(2, "second argument must be an options table") end
+    opts = opts or {}
+    if opts.name ~= nil and (type(opts.name) ~= "string" or opts.name == "") then
+        argerror(2, "name must be a non-empty string")
+    end
+    local color
+    if opts.color ~= nil then
+        color = {xyz(opts.color)}
+        if #color ~= 3 then argerror(2, "color must be a vec3 or a table {r,g,b}") end
+        for i = 1, 3 do
+            if color[i] < 0 or color[i] > 1 then argerror(2, "color components must be between 0 and 1") end
+        end
+    end
+    local min, max
+    if opts.bounds ~= nil then min, max = checkbounds(opts.bounds, 2) end
+    return __build(obj, opts.name, color, min, max)
+end
+
+function Clip(obj, b)
+    local min, max = checkbounds(b, 2)
+    return __Clip(obj, min[1], min[2], min[3], max[1], max[2], max[3])
+end
+
+function Plane3Points(a, b, c)
+    local points = {a, b, c}
+    local p = {}
+    for n = 1, 3 do
+        p[n] = {xyz(points[n])}
+        if #p[n] ~= 3 then argerror(n, "all three arguments must be vec3 values or tables of 3 numbers") end
+    end
+    return __Plane3Points(p[1][1],p[1][2],p[1][3], p[2][1],p[2][2],p[2][3], p[3][1],p[3][2],p[3][3])
+end
+
+function PlaneHessian(n, p)
+    local x, y, z = xyz(n)
+    if x == nil then
+        argerror(1, "first argument (normal) must be a vec3 or a table of 3 numbers")
+    end
+    if type(p) ~= "number" then
+        argerror(2, "second argument must be a number (p in hessian form)")
+    end
+    return __PlaneHessian(x, y, z, p)
+end
+"#;
+
+// ── public API ────────────────────────────────────────────────────────────────
+
+/// Bounds on the work one run of a script may do.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Lua instructions, roughly: piccolo's fuel, where a call into Rust costs
+    /// a few units and `Voxelize` one more per voxel.
+    pub fuel: u64,
+    /// Bytes of Lua memory, checked every few thousand instructions. Geometry
+    /// held by Rust objects is not counted.
+    pub memory: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            fuel: 50_000_000,
+            memory: 256 << 20,
+        }
+    }
+}
+
+/// Where and why a script failed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Chunk the location is in; the user's script is "script".
+    pub chunk: Option<String>,
+    /// 1-based line, where known. Runtime errors outside the prelude's
+    /// functions (arithmetic on nil, calling a nil value, …) give the line of
+    /// the statement that was running.
+    pub line: Option<u32>,
+    /// 1-based column of the failing call. The Lua compiler tracks no columns,
+    /// so this is where the function name appears on the line; there is none
+    /// when the line calls it more than once.
+    pub column: Option<u32>,
+    /// The traced calls in progress, innermost first, in the form of a Lua
+    /// traceback. Calls between user functions are not visible to it.
+    pub traceback: Vec<String>,
+    /// The function the script called at `line` that failed.
+    pub function: Option<String>,
+    /// 1-based position of the argument `function` rejected, where known.
+    pub argument: Option<u32>,
+}
+
+impl Diagnostic {
+    /// The diagnostic for `error`, located through the calls `tracker` saw.
+    fn new(error: &StaticError, tracker: &CallTracker, script: &str, modules: &Modules) -> Self {
+        let message = match error {
+            StaticError::Lua(e) => e.to_string(),
+            StaticError::Runtime(e) => e.to_string(),
+        };
+        let mut d = Diagnostic {
+            message,
+            ..Default::default()
+        };
+        match &tracker.failure {
+            Some((text, calls)) if *text == error.to_string() => {
+                if let Some(innermost) = calls.last() {
+                    d.traceback
+                        .push(format!("in function '{}'", innermost.function));
+                }
+                for c in calls.iter().rev() {
+                    d.traceback
+                        .push(format!("{}:{}: in {}", c.chunk, c.line, c.caller));
+                }
+                // The innermost call made by the script or a module.
+                let user = calls.iter().rposition(|c| {
+                    c.chunk != "prelude" && c.chunk != "[C]" && builtin_module(&c.chunk).is_none()
+                });
+                if let Some((depth, module)) = &tracker.module {
+                    // `require` failed to compile the module.
+                    d.chunk = Some(module.clone());
+                    d.line = syntax_error_line(&d.message);
+                    d.function = user
+                        .filter(|&i| i + 1 == *depth)
+                        .map(|i| calls[i].function.clone());
+                } else if let Some(i) = user {
+                    let site = &calls[i];
+                    let source = match site.chunk.as_str() {
+                        "script" => Some(script),
+                        module => modules.get(module).map(String::as_str),
+                    };
+                    d.chunk = Some(site.chunk.clone());
+                    d.line = Some(site.line);
+                    d.column = source.and_then(|s| call_column(s, site.line, &site.function));
+                    d.function = Some(site.function.clone());
+                    d.argument = tracker
+                        .argument
+                        .filter(|&(depth, _)| depth == i + 1)
+                        .map(|(_, n)| n);
+                }
+            }
+            _ => {
+                d.line = syntax_error_line(&d.message);
+                if d.line.is_some() {
+                    d.chunk = Some("script".to_string());
+                }
+            }
+        }
+        // Otherwise the statement that was running, from LINE.
+        if d.line.is_none()
+            && let Some((chunk, line)) = &tracker.line
+        {
+            d.chunk = Some(chunk.clone());
+            d.line = Some(*line);
+        }
+        d
+    }
+}
+
+/// Formats like a Lua error: `script:3: message`.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.chunk, self.line) {
+            (Some(chunk), Some(line)) => write!(f, "{chunk}:{line}: {}", self.message),
+            _ => f.write_str(&self.message),
+        }
+    }
+}
+
+/// The line a syntax error names.
+fn syntax_error_line(message: &str) -> Option<u32> {
+    let (_, rest) = message.split_once("at line ")?;
+    rest.split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// 1-based column where a call to `function` starts on `line` of `source`,
+/// if the line calls it exactly once.
+fn call_column(source: &str, line: u32, function: &str) -> Option<u32> {
+    let text = source.lines().nth(line.checked_sub(1)? as usize)?;
+    let is_name = |c: char| c.is_alphanumeric() || c == '_';
+    let mut calls = text.match_indices(function).filter(|&(i, _)| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + function.len()..].trim_start().chars().next();
+        !before.is_some_and(is_name) && matches!(after, Some('(' | '{' | '"' | '\''))
+    });
+    let (start, _) = calls.next()?;
+    if calls.next().is_some() {
+        return None;
+    }
+    Some(text[..start].chars().count() as u32 + 1)
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    /// The script failed to compile or raised an error.
+    Script(Diagnostic),
+    /// The script ran past `Limits::fuel`.
+    OutOfFuel(u64),
+    /// The script's memory grew past `Limits::memory`.
+    OutOfMemory(usize),
+}
+
+impl EvalError {
+    /// The error as a diagnostic; running out of budget has no location.
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            EvalError::Script(d) => d.clone(),
+            _ => Diagnostic {
+                message: self.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Script(d) => d.fmt(f),
+            EvalError::OutOfFuel(n) => {
+                write!(f, "script exceeded budget: ran more than {n} instructions")
+            }
+            EvalError::OutOfMemory(n) => {
+                write!(
+                    f,
+                    "script exceeded budget: used more than {n} bytes of memory"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Evaluate `script` with every parameter at its default.
+pub fn eval(script: &str) -> EvalResult {
+    eval_with_params(script, &HashMap::new())
+}
+
+/// Evaluate `script` with the parameters named in `overrides` set to the given
+/// values instead of their defaults. Values are fitted to the parameter as by
+/// `Param::accept`; those that do not fit are ignored.
+pub fn eval_with_params(script: &str, overrides: &HashMap<String, ParamValue>) -> EvalResult {
+    Evaluation::new(script, &Modules::new(), overrides, Limits::default()).finish()
+}
+
+/// Evaluate `script` with `modules` available to `require`.
+pub fn eval_with_modules(script: &str, modules: &Modules) -> EvalResult {
+    Evaluation::new(script, modules, &HashMap::new(), Limits::default()).finish()
+}
+
+/// An evaluation the host runs in slices, so that a long script does not
+/// block it.
+///
+/// A parameter declared live stays live while the script only passes it, and
+/// arithmetic on it, to Scalar fields. Then the preview can follow it through
+/// the uniform alone. Reading its value as a plain number, or measuring an
+/// object built from it, bakes the value into the result instead.
+pub struct Evaluation {
+    run: Result<Run, Option<EvalError>>,
+}
+
+impl Evaluation {
+    pub fn new(
+        script: &str,
+        modules: &Modules,
+        overrides: &HashMap<String, ParamValue>,
+        limits: Limits,
+    ) -> Self {
+        let modules = Arc::new(modules.clone());
+        Evaluation {
+            run: Run::start(script, modules, overrides.clone(), limits).map_err(Some),
+        }
+    }
+
+    /// Continue for about `fuel` more instructions. Ready once the script is
+    /// done; the evaluation must not be stepped after.
+    pub fn step(&mut self, mut fuel: u64) -> Poll<EvalResult> {
+        match &mut self.run {
+            Ok(run) => match run.step(&mut fuel) {
+                Some(result) => Poll::Ready(result),
+                None => Poll::Pending,
+            },
+            Err(e) => Poll::Ready(Err(e.take().expect("evaluation stepped after it finished"))),
+        }
+    }
+
+    /// Run the evaluation to the end.
+    pub fn finish(mut self) -> EvalResult {
+        loop {
+            if let Poll::Ready(result) = self.step(u64::MAX) {
+                return result;
+            }
+        }
+    }
+}
+
+/// One run of a script.
+struct Run {
+    lua: Lua,
+    executor: StashedExecutor,
+    limits: Limits,
+    /// Fuel left of `limits.fuel`.
+    fuel: u64,
+    params: SharedParams,
+    print_buffer: Arc<Mutex<String>>,
+    bodies: Arc<Mutex<Vec<Body>>>,
+    tracker: SharedTracker,
+    script: String,
+    modules: Arc<Modules>,
+}
+
+impl Run {
+    /// Fuel per step of the executor; garbage is collected between steps.
+    const FUEL_PER_STEP: u64 = 4096;
+
+    fn start(
+        script: &str,
+        modules: Arc<Modules>,
+        overrides: HashMap<String, ParamValue>,
+        limits: Limits,
+    ) -> Result<Run, EvalError> {
+        Self::load(script, modules.clone(), overrides, limits).map_err(|e| {
+            EvalError::Script(Diagnostic::new(
+                &e,
+                &CallTracker::default(),
+                script,
+                &modules,
+            ))
+        })
+    }
+
+    fn load(
+        script: &str,
+        modules: Arc<Modules>,
+        overrides: HashMap<String, ParamValue>,
+        limits: Limits,
+    ) -> Result<Run, StaticError> {
+        let mut lua = Lua::core();
+        let stdlib: Vec<String> = lua.enter(|ctx| {
+            let names = ctx.globals().iter().filter_map(|(k, _)| match k {
+                Value::String(k) => Some(lua_string(k)),
+                _ => None,
+            });
+            names.collect()
+        });
+        let tracker = SharedTracker::default();
+        let params: SharedParams = Arc::new(Mutex::new(ParamRegistry {
+            overrides,
+            declared: Vec::new(),
+        }));
+
+        let print_buffer: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+        let bodies: Arc<Mutex<Vec<Body>>> = Arc::new(Mutex::new(Vec::new()));
+
+        {
+            let print_buffer = print_buffer.clone();
+            let bodies = bodies.clone();
+            let params = params.clone();
+
+            lua.try_enter(|ctx| {
+                setup_methods_metatable(ctx);
+                setup_vectors(ctx);
+                setup_print(ctx, print_buffer.clone());
+                setup_factories(ctx, print_buffer);
+                setup_params(ctx, params);
+                setup_operators(ctx);
+                setup_require(ctx, modules.clone(), tracker.clone());
+                let _ = ctx
+                    .singleton::<Rootable![RunShared]>()
+                    .tracker
+                    .set(tracker.clone());
+
+                // __build(obj, name?, {r, g, b}?, {min}?, {max}?)
+                ctx.set_global(
+                    "__build",
+                    Callback::from_fn(&ctx, move |ctx, _, mut stack| {
+                        type Vec3 = Option<[f64; 3]>;
+                        let (ud, name, color, min, max): (
+                            UserData,
+                            Option<String>,
+                            Vec3,
+                            Vec3,
+                            Vec3,
+                        ) = stack.consume(ctx)?;
+                        let Some(mut object) = ud.downcast_static::<LObject>()?.as_primitive()
+                        else {
+                            return Ok(CallbackReturn::Return);
+                        };
+                        if let (Some(min), Some(max)) = (min, max) {
+                            object = clip_to_bounds(
+                                object,
+                                min.map(|v| v as f32),
+                                max.map(|v| v as f32),
+                            );
+                        }
+                        let body = Body {
+                            name: name.unwrap_or_else(|| DEFAULT_BODY.to_string()),
+                            object,
+                            color: color.map(|c| c.map(|v| v as f32)),
+                        };
+                        let mut bodies = bodies.lock().unwrap();
+                        match bodies.iter_mut().find(|b| b.name == body.name) {
+                            Some(b) => *b = body,
+                            None => bodies.push(body),
+                        }
+                        Ok(CallbackReturn::Return)
+                    }),
+                )?;
+
+                Ok(())
+            })?;
+        }
+
+        let aliases_exec = lua.try_enter(|ctx| {
+            let closure = Closure::load(ctx, Some("prelude"), LUA_ALIASES.as_bytes())?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+        lua.execute::<()>(&aliases_exec)?;
+        lua.enter(|ctx| setup_tracing(ctx, &stdlib, tracker.clone()));
+
+        let executor = lua.try_enter(|ctx| {
+            let closure = load_chunk(ctx, "script", script, &tracker)?;
+            Ok(ctx.stash(Executor::start(ctx, closure.into(), ())))
+        })?;
+        Ok(Run {
+            lua,
+            executor,
+            limits,
+            fuel: limits.fuel,
+            params,
+            print_buffer,
+            bodies,
+            tracker,
+            script: script.to_string(),
+            modules,
+        })
+    }
+
+    /// Run the script on, taking what it uses from `fuel`. Returns None when
+    /// `fuel` is spent before the script finished.
+    fn step(&mut self, fuel: &mut u64) -> Option<EvalResult> {
+        while *fuel > 0 {
+            let slice = (*fuel).min(self.fuel).min(Self::FUEL_PER_STEP) as i32;
+            let mut f = Fuel::with(slice);
+            let done = self
+                .lua
+                .enter(|ctx| ctx.fetch(&self.executor).step(ctx, &mut f));
+            // A step may overrun its fuel a little.
+            let used = (i64::from(slice) - i64::from(f.remaining())).max(0) as u64;
+            *fuel = fuel.saturating_sub(used);
+            self.fuel = self.fuel.saturating_sub(used);
+            if self.lua.total_memory() > self.limits.memory {
+                return Some(Err(EvalError::OutOfMemory(self.limits.memory)));
+            }
+            if done {
+                return Some(self.finish());
+            }
+            if self.fuel == 0 {
+                return Some(Err(EvalError::OutOfFuel(self.limits.fuel)));
+            }
+        }
+        None
+    }
+
+    fn finish(&mut self) -> EvalResult {
+        self.lua
+            .try_enter(|ctx| ctx.fetch(&self.executor).take_result::<()>(ctx)?)
+            .map_err(|e| {
+                let mut tracker = self.tracker.lock().unwrap();
+                let line = self.lua.enter(|ctx| match ctx.get_global(LINE) {
+                    Value::Integer(code) => {
+                        Some((code >> LINE_BITS, code & ((1 << LINE_BITS) - 1)))
+                    }
+                    _ => None,
+                });
+                tracker.line = line.and_then(|(chunk, line)| {
+                    Some((tracker.chunks.get(chunk as usize)?.clone(), line as u32))
+                });
+                EvalError::Script(Diagnostic::new(&e, &tracker, &self.script, &self.modules))
+            })?;
+        let output = self.print_buffer.lock().unwrap().clone();
+        let bodies = std::mem::take(&mut *self.bodies.lock().unwrap());
+        let declared = std::mem::take(&mut self.params.lock().unwrap().declared);
+        Ok((output, bodies, declared))
+    }
 }
@@ -0,0 +1,222 @@
+use std::fmt;
+use std::hash::Hasher;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+// ── Script parameters ─────────────────────────────────────────────────────────
+// `param(name, default, opts)` declares a value the host can tweak, like the
+// OpenSCAD customizer. Number parameters declared `live` are special: in the
+// script such a parameter is a Scalar, its current value together with GLSL
+// computing that value from the parameter uniforms, and arithmetic on it stays
+// a Scalar. Primitives with Scalar fields emit that GLSL, so the preview
+// follows a slider by updating one uniform. Everywhere else the value is baked
+// into the tree, and changing the parameter means running the script again.
+
+/// The value of a parameter. Integers are numbers with no fractional part.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamValue {
+    Number(f64),
+    Boolean(bool),
+    Text(String),
+}
+
+impl ParamValue {
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            ParamValue::Number(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+/// What a parameter accepts, and so which control the host shows for it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParamKind {
+    Number {
+        min: Option<f64>,
+        max: Option<f64>,
+        step: Option<f64>,
+    },
+    Integer {
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+    Boolean,
+    /// One of a fixed list of numbers or strings.
+    Enum(Vec<ParamValue>),
+    Text,
+}
+
+impl ParamKind {
+    /// The name `param` takes in its `type` option.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParamKind::Number { .. } => "number",
+            ParamKind::Integer { .. } => "integer",
+            ParamKind::Boolean => "boolean",
+            ParamKind::Enum(_) => "enum",
+            ParamKind::Text => "string",
+        }
+    }
+}
+
+/// A tunable value declared by the script.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub kind: ParamKind,
+    pub value: ParamValue,
+    pub default: ParamValue,
+    pub description: Option<String>,
+    /// Heading the host files the parameter under.
+    pub group: Option<String>,
+    /// Declared live, and every use of the parameter is a uniform, so the
+    /// preview can follow a new value without re-running the script. Only
+    /// number parameters can be live.
+    pub live: bool,
+}
+
+impl Param {
+    /// Name of the GLSL uniform holding the value.
+    pub fn uniform(&self) -> String {
+        uniform_name(&self.name)
+    }
+
+    /// `value` as this parameter would take it: numbers are limited to the
+    /// declared range and integers rounded. None if the value has the wrong
+    /// type or is not one of the choices.
+    pub fn accept(&self, value: &ParamValue) -> Option<ParamValue> {
+        match (&self.kind, value) {
+            (ParamKind::Number { min, max, .. }, &ParamValue::Number(v)) => {
+                let v = min.map_or(v, |m| v.max(m));
+                Some(ParamValue::Number(max.map_or(v, |m| v.min(m))))
+            }
+            (ParamKind::Integer { min, max }, &ParamValue::Number(v)) if v.is_finite() => {
+                let v = v.round() as i64;
+                let v = min.map_or(v, |m| v.max(m));
+                Some(ParamValue::Number(max.map_or(v, |m| v.min(m)) as f64))
+            }
+            (ParamKind::Boolean, ParamValue::Boolean(_))
+            | (ParamKind::Text, ParamValue::Text(_)) => Some(value.clone()),
+            (ParamKind::Enum(options), _) if options.contains(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+pub fn uniform_name(param: &str) -> String {
+    format!("u_{param}")
+}
+
+/// A number that may depend on parameters.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scalar {
+    pub value: f32,
+    /// GLSL for the value in terms of uniforms; None for constants.
+    expr: Option<String>,
+    /// Parameters the value depends on.
+    params: Vec<String>,
+}
+
+impl Scalar {
+    pub fn constant(value: f32) -> Self {
+        Scalar {
+            value,
+            expr: None,
+            params: Vec::new(),
+        }
+    }
+
+    /// The parameter `name`, currently `value`.
+    pub fn param(name: &str, value: f32) -> Self {
+        Scalar {
+            value,
+            expr: Some(uniform_name(name)),
+            params: vec![name.to_string()],
+        }
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.expr.is_none()
+    }
+
+    pub fn params(&self) -> &[String] {
+        &self.params
+    }
+
+    /// GLSL for the value: the expression, or a literal for constants.
+    pub fn glsl(&self) -> String {
+        self.to_string()
+    }
+
+    /// Feed the value and expression into a structural hash.
+    pub fn hash_into(&self, state: &mut dyn Hasher) {
+        state.write_u32(self.value.to_bits());
+        state.write(self.expr.as_deref().unwrap_or("").as_bytes());
+    }
+
+    fn combine(self, other: Scalar, value: f32, op: &str) -> Scalar {
+        if self.is_constant() && other.is_constant() {
+            return Scalar::constant(value);
+        }
+        let mut params = self.params.clone();
+        for p in &other.params {
+            if !params.contains(p) {
+                params.push(p.clone());
+            }
+        }
+        Scalar {
+            value,
+            expr: Some(format!("({self} {op} {other})")),
+            params,
+        }
+    }
+}
+
+impl From<f32> for Scalar {
+    fn from(value: f32) -> Self {
+        Scalar::constant(value)
+    }
+}
+
+/// Formats as GLSL. Constants honour the precision, so `{:.8}` prints them
+/// like the plain floats the shader code formats.
+impl fmt::Display for Scalar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.expr {
+            Some(e) => f.write_str(e),
+            None => write!(f, "{:.*}", f.precision().unwrap_or(8), self.value),
+        }
+    }
+}
+
+macro_rules! scalar_op {
+    ($Trait:ident, $method:ident, $op:tt) => {
+        impl<T: Into<Scalar>> $Trait<T> for Scalar {
+            type Output = Scalar;
+            fn $method(self, other: T) -> Scalar {
+                let other = other.into();
+                let value = self.value $op other.value;
+                self.combine(other, value, stringify!($op))
+            }
+        }
+    };
+}
+
+scalar_op!(Add, add, +);
+scalar_op!(Sub, sub, -);
+scalar_op!(Mul, mul, *);
+scalar_op!(Div, div, /);
+
+impl Neg for Scalar {
+    type Output = Scalar;
+    fn neg(self) -> Scalar {
+        match self.expr {
+            None => Scalar::constant(-self.value),
+            Some(e) => Scalar {
+                value: -self.value,
+                expr: Some(format!("(-{e})")),
+                params: self.params,
+            },
+        }
+    }
+}
@@ -0,0 +1,358 @@
+-- truescad.parts: common mechanical parts, sizes in millimetres.
+--
+-- Parts stand on the XY plane and grow up +Z. Holes and pockets are shapes
+-- to subtract: they go down -Z from a surface at z = 0 and stick out a
+-- little above it, so the cut leaves no skin. Threads are not modelled.
+
+local parts = {}
+
+local function argerror(n, msg) __argerror(n, msg) end
+
+-- How far holes reach above their surface.
+local OVERSHOOT = 0.1
+
+-- Metric screws. d: nominal diameter; pitch: coarse thread pitch;
+-- close, normal, loose: clearance holes (ISO 273); socket: head diameter and
+-- height (ISO 4762) and key: its hex key; hex: head across flats and height
+-- (ISO 4017); button: head diameter and height (ISO 7380); countersunk: head
+-- diameter (ISO 10642, 90°); nut: across flats and height (ISO 4032);
+-- washer: outer diameter and thickness (ISO 7089); insert: hole diameter and
+-- length of a heat-set insert.
+local METRIC = {
+    ["M2"] = {d = 2, pitch = 0.4, close = 2.2, normal = 2.4, loose = 2.6,
+        socket = {3.8, 2}, key = 1.5, hex = {4, 1.4}, button = {3.5, 1.3}, countersunk = 3.8,
+        nut = {4, 1.6}, washer = {5, 0.3}, insert = {3.2, 4}},
+    ["M2.5"] = {d = 2.5, pitch = 0.45, close = 2.7, normal = 2.9, loose = 3.1,
+        socket = {4.5, 2.5}, key = 2, hex = {5, 1.7}, button = {4.7, 1.5}, countersunk = 4.7,
+        nut = {5, 2}, washer = {6, 0.5}, insert = {3.6, 5}},
+    ["M3"] = {d = 3, pitch = 0.5, close = 3.2, normal = 3.4, loose = 3.6,
+        socket = {5.5, 3}, key = 2.5, hex = {5.5, 2}, button = {5.7, 1.65}, countersunk = 6.72,
+        nut = {5.5, 2.4}, washer = {7, 0.5}, insert = {4, 5.7}},
+    ["M4"] = {d = 4, pitch = 0.7, close = 4.3, normal = 4.5, loose = 4.8,
+        socket = {7, 4}, key = 3, hex = {7, 2.8}, button = {7.6, 2.2}, countersunk = 8.96,
+        nut = {7, 3.2}, washer = {9, 0.8}, insert = {5.6, 8.1}},
+    ["M5"] = {d = 5, pitch = 0.8, close = 5.3, normal = 5.5, loose = 5.8,
+        socket = {8.5, 5}, key = 4, hex = {8, 3.5}, button = {9.5, 2.75}, countersunk = 11.2,
+        nut = {8, 4.7}, washer = {10, 1}, insert = {6.4, 9.5}},
+    ["M6"] = {d = 6, pitch = 1, close = 6.4, normal = 6.6, loose = 7,
+        socket = {10, 6}, key = 5, hex = {10, 4}, button = {10.5, 3.3}, countersunk = 13.44,
+        nut = {10, 5.2}, washer = {12, 1.6}, insert = {8, 12.7}},
+    ["M8"] = {d = 8, pitch = 1.25, close = 8.4, normal = 9, loose = 10,
+        socket = {13, 8}, key = 6, hex = {13, 5.3}, button = {14, 4.4}, countersunk = 17.92,
+        nut = {13, 6.8}, washer = {16, 1.6}},
+    ["M10"] = {d = 10, pitch = 1.5, close = 10.5, normal = 11, loose = 12,
+        socket = {16, 10}, key = 8, hex = {16, 6.4}, button = {17.5, 5.5}, countersunk = 22.4,
+        nut = {16, 8.4}, washer = {20, 2}},
+}
+local SIZES = "M2, M2.5, M3, M4, M5, M6, M8 or M10"
+
+-- Deep groove ball bearings: bore, outer diameter, width.
+local BEARINGS = {
+    ["623"] = {3, 10, 4}, ["624"] = {4, 13, 5}, ["625"] = {5, 16, 5},
+    ["626"] = {6, 19, 6}, ["608"] = {8, 22, 7}, ["688"] = {8, 16, 5},
+    ["6000"] = {10, 26, 8}, ["6001"] = {12, 28, 8}, ["6200"] = {10, 30, 9},
+}
+local BEARING_NAMES = "623, 624, 625, 626, 608, 688, 6000, 6001 or 6200"
+
+-- ── argument checking ────────────────────────────────────────────────────────
+
+local function named(arg, part)
+    if arg == nil then return {} end
+    if type(arg) ~= "table" then argerror(1, part .. " expects a table of named arguments") end
+    return arg
+end
+
+-- arg[key], a positive number (or >= 0 with zero_ok), or default if missing.
+local function number(arg, key, default, zero_ok)
+    local v = arg[key]
+    if v == nil then return default end
+    if type(v) ~= "number" or v < 0 or (v == 0 and not zero_ok) then
+        if zero_ok then argerror(1, key .. " must be a non-negative number") end
+        argerror(1, key .. " must be a positive number")
+    end
+    return v
+end
+
+-- arg[key], one of the strings in choices, or default if missing.
+local function choice(arg, key, choices, default)
+    local v = arg[key]
+    if v == nil then return default end
+    local names = ""
+    for i, c in ipairs(choices) do
+        if v == c then return v end
+        names = names .. (i > 1 and ", " or "") .. c
+    end
+    argerror(1, key .. " must be one of " .. names)
+end
+
+local function metric(arg)
+    local size = arg.size or "M3"
+    local m = METRIC[size]
+    if type(size) ~= "string" or m == nil then argerror(1, "size must be " .. SIZES) end
+    return m, size
+end
+
+local function bearing(arg)
+    local name = arg.bearing or "608"
+    if type(name) == "number" then name = tostring(name) end
+    local b = BEARINGS[name]
+    if type(name) ~= "string" or b == nil then argerror(1, "bearing must be " .. BEARING_NAMES) end
+    return b
+end
+
+-- ── shapes ───────────────────────────────────────────────────────────────────
+
+-- Cylinder of radius r from z0 up to z1.
+local function rod(r, z0, z1)
+    return Cylinder({l = z1 - z0, r = r}):translate(0, 0, (z0 + z1) / 2)
+end
+
+-- Cone from radius r0 at z0 to r1 at z1.
+local function cone(r0, r1, z0, z1)
+    return Cylinder({l = z1 - z0, r1 = r0, r2 = r1}):translate(0, 0, (z0 + z1) / 2)
+end
+
+-- Hexagonal prism, flats across s, from z0 up to z1.
+local function hexagon(s, z0, z1)
+    local slab = Box(s * 2 / math.sqrt(3), s, z1 - z0):translate(0, 0, (z0 + z1) / 2)
+    return Intersection({slab, slab:rotate(0, 0, pi / 3), slab:rotate(0, 0, 2 * pi / 3)})
+end
+
+-- Domed head of diameter dk and height k on z = 0.
+local function dome(dk, k)
+    local r = ((dk / 2) ^ 2 + k ^ 2) / (2 * k)
+    return Intersection({Sphere(r):translate(0, 0, k - r), rod(dk / 2, 0, k)})
+end
+
+-- ── fasteners ────────────────────────────────────────────────────────────────
+
+local HEADS = {"socket", "hex", "button", "countersunk"}
+
+-- parts.bolt{size="M3", length=10, head="socket"}
+-- Head on z = 0, shank down to -length. Countersunk heads sink below z = 0
+-- and count towards the length, as the standard measures them.
+function parts.bolt(arg)
+    arg = named(arg, "bolt")
+    local m = metric(arg)
+    local length = number(arg, "length", 10)
+    local head = choice(arg, "head", HEADS, "socket")
+    local shank = rod(m.d / 2, -length, 0)
+    local top
+    if head == "socket" then
+        local dk, k = m.socket[1], m.socket[2]
+        top = Difference({rod(dk / 2, 0, k), hexagon(m.key, k / 2, k + OVERSHOOT)})
+    elseif head == "hex" then
+        top = hexagon(m.hex[1], 0, m.hex[2])
+    elseif head == "button" then
+        top = dome(m.button[1], m.button[2])
+    else
+        local sink = (m.countersunk - m.d) / 2
+        if sink >= length then argerror(1, "length must be longer than the head") end
+        top = cone(m.d / 2, m.countersunk / 2, -sink, 0)
+    end
+    return Union({top, shank})
+end
+
+-- parts.nut{size="M3", height=standard}
+function parts.nut(arg)
+    arg = named(arg, "nut")
+    local m = metric(arg)
+    local height = number(arg, "height", m.nut[2])
+    return Difference({hexagon(m.nut[1], 0, height), rod(m.d / 2, -OVERSHOOT, height + OVERSHOOT)})
+end
+
+-- parts.washer{size="M3"}
+function parts.washer(arg)
+    arg = named(arg, "washer")
+    local m = metric(arg)
+    local outer, h = m.washer[1], m.washer[2]
+    return Difference({rod(outer / 2, 0, h), rod(m.close / 2, -OVERSHOOT, h + OVERSHOOT)})
+end
+
+-- parts.bolt_hole{size="M3", length=10, fit="normal", head=nil, sink=0, clearance=0.2}
+-- Clearance hole, with a counterbore or countersink when head is given. The
+-- top of the head ends up sink below the surface.
+function parts.bolt_hole(arg)
+    arg = named(arg, "bolt_hole")
+    local m = metric(arg)
+    local length = number(arg, "length", 10)
+    local fit = choice(arg, "fit", {"close", "normal", "loose"}, "normal")
+    local head = choice(arg, "head", HEADS, nil)
+    local c = number(arg, "clearance", 0.2, true)
+    local r = m[fit] / 2
+    local hole = rod(r, -length, OVERSHOOT)
+    if head == nil then return hole end
+    if head == "countersunk" then
+        local top = m.countersunk / 2 + c
+        local sink = number(arg, "sink", 0, true)
+        local seat = Union({cone(r, top, -(top - r), 0), rod(top, 0, sink + OVERSHOOT)})
+        return Union({hole, seat:translate(0, 0, -sink)})
+    end
+    local dk, k
+    if head == "hex" then
+        dk, k = m.hex[1] * 2 / math.sqrt(3), m.hex[2]
+    else
+        dk, k = m[head][1], m[head][2]
+    end
+    local sink = number(arg, "sink", 0, true)
+    return Union({hole, rod(dk / 2 + c, -(k + sink), OVERSHOOT)})
+end
+
+-- parts.countersink{size="M3", length=10, fit="normal", sink=0, clearance=0.2}
+-- bolt_hole for a countersunk screw.
+function parts.countersink(arg)
+    arg = named(arg, "countersink")
+    local hole = {head = "countersunk"}
+    for k, v in pairs(arg) do
+        if k ~= "head" then hole[k] = v end
+    end
+    return parts.bolt_hole(hole)
+end
+
+-- parts.nut_trap{size="M3", depth=nut height, clearance=0.2, slot=0}
+-- Hexagonal pocket for a nut; a slot opens it towards +X so the nut can
+-- slide in from the side.
+function parts.nut_trap(arg)
+    arg = named(arg, "nut_trap")
+    local m = metric(arg)
+    local c = number(arg, "clearance", 0.2, true)
+    local depth = number(arg, "depth", m.nut[2] + c)
+    local slot = number(arg, "slot", 0, true)
+    local s = m.nut[1] + 2 * c
+    local trap = hexagon(s, -depth, OVERSHOOT)
+    if slot == 0 then return trap end
+    local channel = Box(slot, s, depth + OVERSHOOT):translate(slot / 2, 0, (OVERSHOOT - depth) / 2)
+    return Union({trap, channel})
+end
+
+-- parts.insert_hole{size="M3", depth=insert length + 1, chamfer=0.5}
+-- Hole to melt a heat-set insert into, with a chamfered mouth.
+function parts.insert_hole(arg)
+    arg = named(arg, "insert_hole")
+    local m, size = metric(arg)
+    if m.insert == nil then argerror(1, "there is no heat-set insert for " .. size) end
+    local r = m.insert[1] / 2
+    local depth = number(arg, "depth", m.insert[2] + 1)
+    local chamfer = number(arg, "chamfer", 0.5, true)
+    local hole = rod(r, -depth, OVERSHOOT)
+    if chamfer == 0 then return hole end
+    local mouth = Union({cone(r, r + chamfer, -chamfer, 0), rod(r + chamfer, 0, OVERSHOOT)})
+    return Union({hole, mouth})
+end
+
+-- parts.standoff{size="M3", height=6, diameter=2*d+2, hole="insert"}
+-- Round boss for a screw: hole is "insert" (a heat-set insert from the
+-- top), "tap" (to cut threads into) or "clearance" (a through hole).
+function parts.standoff(arg)
+    arg = named(arg, "standoff")
+    local m, size = metric(arg)
+    local height = number(arg, "height", 6)
+    local diameter = number(arg, "diameter", 2 * m.d + 2)
+    local kind = choice(arg, "hole", {"insert", "tap", "clearance"}, "insert")
+    local hole
+    if kind == "insert" then
+        if m.insert == nil then argerror(1, "there is no heat-set insert for " .. size) end
+        local depth = math.min(height, m.insert[2] + 1)
+        hole = parts.insert_hole({size = size, depth = depth}):translate(0, 0, height)
+    else
+        local r = (kind == "tap") and (m.d - m.pitch) / 2 or m.normal / 2
+        hole = rod(r, -OVERSHOOT, height + OVERSHOOT)
+    end
+    return Difference({rod(diameter / 2, 0, height), hole})
+end
+
+-- ── bearings ─────────────────────────────────────────────────────────────────
+
+-- parts.bearing{bearing="608"}
+-- The bearing itself, as a ring on z = 0.
+function parts.bearing(arg)
+    arg = named(arg, "bearing")
+    local b = bearing(arg)
+    return Difference({rod(b[2] / 2, 0, b[3]), rod(b[1] / 2, -OVERSHOOT, b[3] + OVERSHOOT)})
+end
+
+-- parts.bearing_seat{bearing="608", depth=width, clearance=0.1, lip=1, relief=0}
+-- Pocket the outer race presses into. relief continues a hole that much
+-- deeper, lip narrower than the pocket, so the inner race can turn.
+function parts.bearing_seat(arg)
+    arg = named(arg, "bearing_seat")
+    local b = bearing(arg)
+    local depth = number(arg, "depth", b[3])
+    local c = number(arg, "clearance", 0.1, true)
+    local lip = number(arg, "lip", 1)
+    local relief = number(arg, "relief", 0, true)
+    local r = b[2] / 2 + c
+    local seat = rod(r, -depth, OVERSHOOT)
+    if relief == 0 then return seat end
+    if lip >= r then argerror(1, "lip must be less than the bearing radius") end
+    return Union({seat, rod(r - lip, -depth - relief, -depth + OVERSHOOT)})
+end
+
+-- ── flexures and joints ──────────────────────────────────────────────────────
+
+-- parts.snap_clip{length=10, width=5, thickness=1.5, hook=1, hook_length=2}
+-- Cantilever snap-fit: a beam from z = 0 up to length with a hook sticking
+-- out towards +X at its tip. The hook's top is a ramp to push past; its
+-- flat underside holds.
+function parts.snap_clip(arg)
+    arg = named(arg, "snap_clip")
+    local length = number(arg, "length", 10)
+    local width = number(arg, "width", 5)
+    local t = number(arg, "thickness", 1.5)
+    local hook = number(arg, "hook", 1)
+    local hook_length = number(arg, "hook_length", 2)
+    if hook_length >= length then argerror(1, "hook_length must be less than length") end
+    local beam = Box(t, width, length):translate(0, 0, length / 2)
+    local block = Box(hook, width, hook_length):translate(t / 2 + hook / 2, 0, length - hook_length / 2)
+    -- plane through the beam's tip and the hook's outer bottom edge
+    local n = math.sqrt(hook_length ^ 2 + hook ^ 2)
+    local nx, nz = hook_length / n, hook / n
+    local ramp = PlaneHessian({nx, 0, nz}, nx * t / 2 + nz * length)
+    return Union({beam, Intersection({block, ramp})})
+end
+
+-- parts.hinge{length=30, diameter=6, knuckles=5, clearance=0.3, pin=diameter/2, leaf=10, thickness=2}
+-- Print-in-place hinge along the X axis. Returns its two halves: the first
+-- carries the pin and a leaf towards -Y, the second turns on the pin with
+-- a leaf towards +Y.
+function parts.hinge(arg)
+    arg = named(arg, "hinge")
+    local length = number(arg, "length", 30)
+    local diameter = number(arg, "diameter", 6)
+    local knuckles = arg.knuckles or 5
+    if math.type(knuckles) ~= "integer" or knuckles < 2 then
+        argerror(1, "knuckles must be an integer >= 2")
+    end
+    local c = number(arg, "clearance", 0.3, true)
+    local pin = number(arg, "pin", diameter / 2)
+    local leaf = number(arg, "leaf", 10)
+    local t = number(arg, "thickness", 2)
+    if pin + 2 * c >= diameter then argerror(1, "pin must be thinner than diameter - 2 * clearance") end
+    if t > diameter then argerror(1, "thickness must not exceed diameter") end
+    local segment = (length - (knuckles - 1) * c) / knuckles
+    if segment <= 0 then argerror(1, "length is too short for that many knuckles") end
+
+    local r = diameter / 2
+    local function along_x(radius, l, x)
+        return Cylinder({l = l, r = radius}):rotate(0, pi / 2, 0):translate(x, 0, 0)
+    end
+    local knuckle = {{}, {}}
+    local gap = {{}, {}}
+    for i = 0, knuckles - 1 do
+        local x = -length / 2 + segment / 2 + i * (segment + c)
+        local half = i % 2 + 1
+        knuckle[half][#knuckle[half] + 1] = along_x(r, segment, x)
+        gap[half][#gap[half] + 1] = along_x(r + c, segment + 2 * c, x)
+    end
+    local function half(k, side)
+        local plate = Box(length, leaf + r, t):translate(0, side * (leaf + r) / 2, 0)
+        local body = {Union({plate, table.unpack(knuckle[k])})}
+        for _, g in ipairs(gap[3 - k]) do body[#body + 1] = g end
+        return Difference(body)
+    end
+    local a = Union({half(1, -1), along_x(pin / 2, length, 0)})
+    local b = Difference({half(2, 1), along_x(pin / 2 + c, length + 2 * OVERSHOOT, 0)})
+    return a, b
+end
+
+return parts
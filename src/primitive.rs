@@ -1,26 +1,188 @@
+use crate::interval::{Interval, length};
+use crate::param::{Scalar, uniform_name};
+use crate::tape::{Op, Reg, Tape};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+
+const INF: f32 = 1e10;
+/// Box extents at or beyond this magnitude mean the object is unbounded.
+pub const UNBOUNDED: f32 = 1e9;
+/// Material of surfaces without one: linear RGB colour and specular strength.
+pub const DEFAULT_MATERIAL: &str = "vec4(0.7, 0.7, 0.7, 0.0)";
+
 pub trait Primitive: Send + Sync {
     /// Generate GLSL statements into `ctx`; return the name of the float variable
     /// holding the signed distance result.
     fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String;
     /// CPU evaluation for tessellation. Positive = outside, negative = inside.
     fn eval(&self, p: [f32; 3]) -> f32;
+    /// `eval` over a batch: writes the value at `points[i]` to `out[i]`. Nodes
+    /// with children override this to run each child over the whole batch, so
+    /// the per-point work sits in flat loops the compiler can vectorize.
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        for (o, &p) in out.iter_mut().zip(points) {
+            *o = self.eval(p);
+        }
+    }
+    /// Append instructions computing `eval` at the point held in registers `p`
+    /// and return the register with the result. The default calls back into
+    /// `eval` through an opaque instruction.
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        tape.push(Op::Opaque(self.clone_box(), p))
+    }
+    /// Conservative range of `eval` over `bbox`: every point inside evaluates to
+    /// a value in `(lo, hi)`.
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32);
+    /// Analytic gradient of `eval` at `p` (not necessarily unit length).
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3];
     fn bbox(&self) -> Bbox;
+    /// Bounds of the part of the object inside `clip`. Unbounded primitives
+    /// override this when a finite clip box bounds them further (a cone cut by
+    /// two planes, say).
+    fn bbox_within(&self, clip: &Bbox) -> Bbox {
+        self.bbox().intersection(clip)
+    }
+    /// The half-space `normal · p <= offset` this object describes, if it is one.
+    /// Intersections clip such children against each other rather than
+    /// intersecting their infinite boxes.
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        None
+    }
+    /// Bounds of everything outside the object. Infinite, except for objects
+    /// that are themselves the outside of something bounded.
+    fn complement_bbox(&self) -> Bbox {
+        Bbox::infinite()
+    }
+    /// Feed the kind, parameters and children of this node into `state`.
+    /// Trees that feed in the same writes are taken to be equal, which lets
+    /// the shader emit a repeated subtree once as a function, so everything
+    /// the field depends on must be fed in.
+    fn hash_structure(&self, state: &mut dyn Hasher);
     fn clone_box(&self) -> Box<dyn Primitive>;
 }
 
+/// Writes longer than this many bytes, such as voxel data, are not copied
+/// into structure keys; see `StructureKeys`.
+const LONG_WRITE: usize = 64;
+
+/// Structure keys of subtrees: the bytes that `Primitive::hash_structure`
+/// feeds in, each write prefixed with its length, so that two trees are
+/// structurally equal exactly when their keys are. Long writes are stored
+/// once here and stand in the key as their index.
+#[derive(Default)]
+pub struct StructureKeys {
+    payloads: HashMap<Vec<u8>, usize>,
+}
+
+impl StructureKeys {
+    /// Key of the whole tree under `obj`.
+    pub fn key(&mut self, obj: &dyn Primitive) -> Vec<u8> {
+        let mut recorder = KeyRecorder {
+            key: Vec::new(),
+            payloads: &mut self.payloads,
+        };
+        obj.hash_structure(&mut recorder);
+        recorder.key
+    }
+}
+
+struct KeyRecorder<'a> {
+    key: Vec<u8>,
+    payloads: &'a mut HashMap<Vec<u8>, usize>,
+}
+
+impl Hasher for KeyRecorder<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        if bytes.len() <= LONG_WRITE {
+            self.key.push(bytes.len() as u8);
+            self.key.extend_from_slice(bytes);
+            return;
+        }
+        let index = match self.payloads.get(bytes) {
+            Some(&n) => n,
+            None => {
+                let n = self.payloads.len();
+                self.payloads.insert(bytes.to_vec(), n);
+                n
+            }
+        };
+        self.key.push(u8::MAX);
+        self.key.extend_from_slice(&index.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        let mut state = DefaultHasher::new();
+        state.write(&self.key);
+        state.finish()
+    }
+}
+
+/// Feed a node's kind and numeric parameters into a structural hash.
+pub fn hash_node(state: &mut dyn Hasher, kind: &str, params: &[f32]) {
+    state.write(kind.as_bytes());
+    let bytes: Vec<u8> = params
+        .iter()
+        .flat_map(|v| v.to_bits().to_le_bytes())
+        .collect();
+    state.write(&bytes);
+}
+
+/// Feed a list of children into a structural hash.
+pub fn hash_children(state: &mut dyn Hasher, children: &[Box<dyn Primitive>]) {
+    state.write_usize(children.len());
+    for c in children {
+        c.hash_structure(state);
+    }
+}
+
+/// Normalize `v`, falling back to +Z where the gradient vanishes.
+pub fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if len > 0.0 {
+        v.map(|c| c / len)
+    } else {
+        [0.0, 0.0, 1.0]
+    }
+}
+
 impl Clone for Box<dyn Primitive> {
     fn clone(&self) -> Self {
         self.clone_box()
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Bbox {
     pub min: [f32; 3],
     pub max: [f32; 3],
 }
 
 impl Bbox {
+    /// The box reported by unbounded objects.
+    pub fn infinite() -> Bbox {
+        Bbox {
+            min: [-INF; 3],
+            max: [INF; 3],
+        }
+    }
+
+    /// `Ok(self)` if the box is finite, otherwise an error naming the axes the
+    /// object extends to infinity along.
+    pub fn finite(self) -> Result<Bbox, String> {
+        let open: Vec<&str> = ["X", "Y", "Z"]
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| self.min[i] <= -UNBOUNDED || self.max[i] >= UNBOUNDED)
+            .map(|(_, axis)| axis)
+            .collect();
+        if open.is_empty() {
+            Ok(self)
+        } else {
+            Err(format!("object is unbounded along {}", open.join(", ")))
+        }
+    }
+
     pub fn width(&self) -> f32 {
         (self.max[0] - self.min[0])
             .max(self.max[1] - self.min[1])
@@ -42,6 +204,101 @@ impl Bbox {
         }
     }
 
+    pub fn axis(&self, i: usize) -> Interval {
+        Interval::new(self.min[i], self.max[i])
+    }
+
+    pub fn axes(&self) -> [Interval; 3] {
+        [self.axis(0), self.axis(1), self.axis(2)]
+    }
+
+    pub fn from_axes(a: [Interval; 3]) -> Bbox {
+        Bbox {
+            min: a.map(|i| i.lo),
+            max: a.map(|i| i.hi),
+        }
+    }
+
+    pub fn center(&self) -> [f32; 3] {
+        std::array::from_fn(|i| 0.5 * (self.min[i] + self.max[i]))
+    }
+
+    /// Half the length of the box diagonal.
+    pub fn radius(&self) -> f32 {
+        length(&self.axes().map(|a| Interval::point(0.5 * a.width()))).hi
+    }
+
+    pub fn expand(&self, r: f32) -> Bbox {
+        Bbox {
+            min: self.min.map(|v| v - r),
+            max: self.max.map(|v| v + r),
+        }
+    }
+
+    /// Bounds of the part of the box satisfying every `normal · p <= offset`.
+    /// The clipped region is a convex polytope; its vertices are the points
+    /// where three of the planes (box faces included) meet and which satisfy
+    /// all the others.
+    pub fn clip(&self, half_spaces: &[([f32; 3], f32)]) -> Bbox {
+        if half_spaces.is_empty() {
+            return *self;
+        }
+        let mut planes: Vec<([f64; 3], f64)> = Vec::with_capacity(6 + half_spaces.len());
+        for i in 0..3 {
+            let mut n = [0.0; 3];
+            n[i] = 1.0;
+            planes.push((n, self.max[i] as f64));
+            n[i] = -1.0;
+            planes.push((n, -self.min[i] as f64));
+        }
+        planes.extend(
+            half_spaces
+                .iter()
+                .map(|(n, d)| (n.map(f64::from), *d as f64)),
+        );
+
+        let dot = |a: [f64; 3], b: [f64; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+        let cross = |a: [f64; 3], b: [f64; 3]| {
+            [
+                a[1] * b[2] - a[2] * b[1],
+                a[2] * b[0] - a[0] * b[2],
+                a[0] * b[1] - a[1] * b[0],
+            ]
+        };
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+        for i in 0..planes.len() {
+            for j in i + 1..planes.len() {
+                for k in j + 1..planes.len() {
+                    let ((a, da), (b, db), (c, dc)) = (planes[i], planes[j], planes[k]);
+                    let (bc, ca, ab) = (cross(b, c), cross(c, a), cross(a, b));
+                    let det = dot(a, bc);
+                    if det.abs() < 1e-9 {
+                        continue;
+                    }
+                    let v: [f64; 3] =
+                        std::array::from_fn(|m| (da * bc[m] + db * ca[m] + dc * ab[m]) / det);
+                    let tol = 1e-6 * (1.0 + v.iter().map(|x| x.abs()).sum::<f64>());
+                    if planes.iter().all(|&(n, d)| dot(n, v) <= d + tol) {
+                        for m in 0..3 {
+                            min[m] = min[m].min(v[m]);
+                            max[m] = max[m].max(v[m]);
+                        }
+                    }
+                }
+            }
+        }
+        if min[0] > max[0] {
+            // Empty: the half-spaces exclude the whole box.
+            let c = self.center();
+            return Bbox { min: c, max: c };
+        }
+        Bbox {
+            min: std::array::from_fn(|m| (min[m] as f32).max(self.min[m])),
+            max: std::array::from_fn(|m| (max[m] as f32).min(self.max[m])),
+        }
+    }
+
     pub fn intersection(&self, other: &Bbox) -> Bbox {
         Bbox {
             min: [
@@ -58,10 +315,77 @@ impl Bbox {
     }
 }
 
+/// How `obj:debug()`, `obj:ghost()` and `obj:only()` mark a subtree for the
+/// preview.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mark {
+    /// Drawn as a highlighted translucent overlay.
+    Debug,
+    /// Drawn as a faint translucent overlay.
+    Ghost,
+    /// Drawn alone, without the rest of the scene.
+    Only,
+}
+
+/// Bulk data the emitted code reads from a uniform the host uploads, rather
+/// than from literals that would bloat the shader source.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShaderData {
+    /// `uniform vec4 name[n]`.
+    Vec4Array(Vec<[f32; 4]>),
+    /// `uniform highp sampler3D name`, one float per texel, x fastest.
+    Texture3d { dims: [usize; 3], values: Vec<f32> },
+}
+
+/// What `GlslCtx::annotate` found on its pass over a tree.
+pub struct Annotations {
+    /// The distance variable of the tree.
+    pub distance: String,
+    /// The `vec4` material of the closest surface.
+    pub material: String,
+    /// Each marked subtree: its mark, distance variable and material.
+    pub marked: Vec<(Mark, String, String)>,
+}
+
 pub struct GlslCtx {
     counter: usize,
     pub statements: Vec<String>,
     pub helpers: Vec<String>,
+    /// Parameters read by the emitted code, each declared as a float uniform.
+    pub params: Vec<String>,
+    /// Data uniforms read by the emitted code, by name.
+    pub data: Vec<(String, ShaderData)>,
+    /// Functions for repeated subtrees, each after the functions it calls.
+    pub functions: Vec<String>,
+    /// Occurrences of each subtree, by structure key, from `count_uses`.
+    keys: StructureKeys,
+    uses: HashMap<Vec<u8>, usize>,
+    counting: bool,
+    /// Function emitted for a repeated subtree; None where the subtree is a
+    /// single statement and cheaper to repeat than to call.
+    shared: HashMap<Vec<u8>, Option<String>>,
+    /// While `annotate` emits the scene: the material variable of each
+    /// distance variable that has one, in the order they were set, and the
+    /// distance variable of each marked subtree with the factor that takes
+    /// it to scene units.
+    materials: Option<Vec<(String, String)>>,
+    markers: Option<Vec<(Mark, String, f32)>>,
+    /// The factor the ancestors of the node being emitted apply to its
+    /// distance.
+    scale: f32,
+    /// Material and marker nodes emitted so far, and the shared subtrees that
+    /// contain one, from `count_uses`. Those are emitted inline while tracking
+    /// them, since their functions only return the distance.
+    material_nodes: usize,
+    marker_nodes: usize,
+    colored: HashSet<Vec<u8>>,
+    marked: HashSet<Vec<u8>>,
+}
+
+impl Default for GlslCtx {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GlslCtx {
@@ -70,6 +394,20 @@ impl GlslCtx {
             counter: 0,
             statements: Vec::new(),
             helpers: Vec::new(),
+            params: Vec::new(),
+            data: Vec::new(),
+            functions: Vec::new(),
+            keys: StructureKeys::default(),
+            uses: HashMap::new(),
+            counting: false,
+            shared: HashMap::new(),
+            materials: None,
+            markers: None,
+            scale: 1.0,
+            material_nodes: 0,
+            marker_nodes: 0,
+            colored: HashSet::new(),
+            marked: HashSet::new(),
         }
     }
 
@@ -85,6 +423,12 @@ impl GlslCtx {
         format!("p{n}")
     }
 
+    pub fn fresh_material(&mut self) -> String {
+        let n = self.counter;
+        self.counter += 1;
+        format!("m{n}")
+    }
+
     pub fn push(&mut self, s: impl Into<String>) {
         self.statements.push(s.into());
     }
@@ -94,4 +438,273 @@ impl GlslCtx {
             self.helpers.push(src.to_string());
         }
     }
+
+    /// GLSL for `s`: a literal, or an expression of parameter uniforms.
+    pub fn scalar(&mut self, s: &Scalar) -> String {
+        for p in s.params() {
+            if !self.params.contains(p) {
+                self.params.push(p.clone());
+            }
+        }
+        s.glsl()
+    }
+
+    /// Index of the uniform holding `data`, named `{prefix}{index}`. Identical
+    /// data declared before is shared rather than uploaded twice, so helpers
+    /// named after the index are the same function wherever they are emitted.
+    pub fn upload(&mut self, prefix: &str, data: ShaderData) -> usize {
+        let name = |n: usize| format!("{prefix}{n}");
+        let same = |(n, (v, d)): (usize, &(String, ShaderData))| *d == data && *v == name(n);
+        if let Some(n) = self.data.iter().enumerate().position(same) {
+            return n;
+        }
+        let n = self.data.len();
+        self.data.push((name(n), data));
+        n
+    }
+
+    /// Declarations of the parameter and data uniforms the emitted code reads.
+    pub fn uniform_declarations(&self) -> String {
+        let params = self
+            .params
+            .iter()
+            .map(|p| format!("uniform float {};\n", uniform_name(p)));
+        let data = self.data.iter().map(|(name, data)| match data {
+            ShaderData::Vec4Array(values) => format!("uniform vec4 {name}[{}];\n", values.len()),
+            ShaderData::Texture3d { .. } => format!("uniform highp sampler3D {name};\n"),
+        });
+        params.chain(data).collect()
+    }
+
+    /// Count how often each subtree of `obj` would be emitted, so that `child`
+    /// can turn the repeated ones into functions, and note the material and
+    /// marker nodes in it. A subtree seen before is not descended into again:
+    /// its children are emitted once, inside its function.
+    pub fn count_uses(&mut self, obj: &dyn Primitive) {
+        let lens = (self.helpers.len(), self.params.len(), self.data.len());
+        self.counting = true;
+        self.child(obj, "p");
+        self.counting = false;
+        self.counter = 0;
+        self.statements.clear();
+        self.helpers.truncate(lens.0);
+        self.params.truncate(lens.1);
+        self.data.truncate(lens.2);
+    }
+
+    /// Note that a material node is being emitted.
+    pub fn mark_material(&mut self) {
+        self.material_nodes += 1;
+    }
+
+    /// Whether the emitted tree contains a material node.
+    pub fn has_materials(&self) -> bool {
+        self.material_nodes > 0
+    }
+
+    /// Note that a marker node is being emitted.
+    pub fn mark_marker(&mut self) {
+        self.marker_nodes += 1;
+    }
+
+    /// Whether the emitted tree contains a marker node.
+    pub fn has_markers(&self) -> bool {
+        self.marker_nodes > 0
+    }
+
+    /// Record that the subtree at distance `d` is marked with `mark`, while
+    /// tracking markers.
+    pub fn record_marker(&mut self, mark: Mark, d: &str) {
+        if let Some(markers) = &mut self.markers {
+            markers.push((mark, d.to_string(), self.scale));
+        }
+    }
+
+    /// Run `emit` for a child whose distance the node multiplies by `factor`,
+    /// so that the markers below it record their distance in scene units.
+    pub fn scaled(&mut self, factor: f32, emit: impl FnOnce(&mut Self) -> String) -> String {
+        let outer = self.scale;
+        self.scale *= factor;
+        let d = emit(self);
+        self.scale = outer;
+        d
+    }
+
+    /// Run `emit`, returning with its result the markers it recorded instead
+    /// of recording them: each one's mark, distance variable and the factor
+    /// from it to the distance of the node. For code whose variables are out
+    /// of scope afterwards, such as the body of a loop.
+    pub fn markers_within(
+        &mut self,
+        emit: impl FnOnce(&mut Self) -> String,
+    ) -> (String, Vec<(Mark, String, f32)>) {
+        let Some(outer) = self.markers.replace(Vec::new()) else {
+            return (emit(self), Vec::new());
+        };
+        let d = emit(self);
+        let within = self.markers.replace(outer).unwrap_or_default();
+        let scale = self.scale;
+        (
+            d,
+            within
+                .into_iter()
+                .map(|(mark, d, s)| (mark, d, s / scale))
+                .collect(),
+        )
+    }
+
+    /// Whether nodes should record the materials of their surfaces, see
+    /// `annotate`.
+    pub fn tracking_materials(&self) -> bool {
+        self.materials.is_some()
+    }
+
+    /// The variable holding the material of the surface at distance `d`, if
+    /// it has one.
+    pub fn material_of(&self, d: &str) -> Option<String> {
+        let materials = self.materials.as_ref()?;
+        materials
+            .iter()
+            .rev()
+            .find(|(v, _)| v == d)
+            .map(|(_, m)| m.clone())
+    }
+
+    /// Record that the surface at distance `d` has the material in `m`.
+    pub fn set_material(&mut self, d: &str, m: String) {
+        if let Some(materials) = &mut self.materials {
+            materials.push((d.to_string(), m));
+        }
+    }
+
+    /// Run `emit` without tracking materials, for a node whose own material
+    /// covers everything it emits.
+    pub fn without_materials(&mut self, emit: impl FnOnce(&mut Self) -> String) -> String {
+        let materials = self.materials.take();
+        let d = emit(self);
+        self.materials = materials;
+        d
+    }
+
+    /// Emit `obj` at `p` like `child`, tracking materials and markers.
+    /// Materials are `vec4`s of linear RGB colour and specular strength:
+    /// unions take the material of the nearest child, intersections and
+    /// differences that of the child whose surface bounds them, and smooth
+    /// operators blend them. Marked subtrees are recorded with their distance
+    /// where they sit in the tree, even when a difference subtracts them. Call
+    /// it after `count_uses`, which finds the shared subtrees to emit inline.
+    pub fn annotate(&mut self, obj: &dyn Primitive, p: &str) -> Annotations {
+        self.materials = Some(Vec::new());
+        self.markers = Some(Vec::new());
+        let distance = self.child(obj, p);
+        let material_of = |d: &str| {
+            self.material_of(d)
+                .unwrap_or_else(|| DEFAULT_MATERIAL.to_string())
+        };
+        let material = material_of(&distance);
+        let marked = self.markers.iter().flatten();
+        let marked = marked
+            .map(|(k, d, s)| (*k, scaled_distance(d, *s), material_of(d)))
+            .collect();
+        self.materials = None;
+        self.markers = None;
+        Annotations {
+            distance,
+            material,
+            marked,
+        }
+    }
+
+    /// Emit `obj` evaluated at the point `p`. Composite primitives emit their
+    /// children through this rather than calling `expression` directly, so a
+    /// subtree that occurs several times becomes one function called at each
+    /// place.
+    ///
+    /// While tracking materials, a node that records none for its result takes
+    /// the last one recorded below it, so nodes that only move the point or
+    /// rescale the distance keep the material of their child.
+    pub fn child(&mut self, obj: &dyn Primitive, p: &str) -> String {
+        let Some(before) = self.materials.as_ref().map(Vec::len) else {
+            return self.emit(obj, p);
+        };
+        let d = self.emit(obj, p);
+        if self.material_of(&d).is_none() {
+            let materials = self.materials.as_mut().unwrap();
+            if materials.len() > before {
+                let m = materials.last().unwrap().1.clone();
+                materials.push((d.clone(), m));
+            }
+        }
+        d
+    }
+
+    fn emit(&mut self, obj: &dyn Primitive, p: &str) -> String {
+        let key = self.keys.key(obj);
+        if self.counting {
+            let n = self.uses.entry(key.clone()).or_insert(0);
+            *n += 1;
+            if *n > 1 {
+                return self.fresh_float();
+            }
+            let (materials, markers) = (self.material_nodes, self.marker_nodes);
+            let d = obj.expression(p, self);
+            if self.material_nodes > materials {
+                self.colored.insert(key.clone());
+            }
+            if self.marker_nodes > markers {
+                self.marked.insert(key);
+            }
+            return d;
+        }
+        if self.uses.get(&key).is_none_or(|&n| n < 2)
+            || (self.tracking_materials() && self.colored.contains(&key))
+            || (self.markers.is_some() && self.marked.contains(&key))
+        {
+            return obj.expression(p, self);
+        }
+        let function = match self.shared.get(&key) {
+            Some(f) => f.clone(),
+            None => {
+                let f = self.define(obj);
+                self.shared.insert(key, f.clone());
+                f
+            }
+        };
+        match function {
+            Some(f) => {
+                let d = self.fresh_float();
+                self.push(format!("float {d} = {f}({p});"));
+                d
+            }
+            None => obj.expression(p, self),
+        }
+    }
+
+    /// Emit `obj` as a function of its point, unless it is a single statement.
+    /// The function only returns the distance, so it tracks nothing.
+    fn define(&mut self, obj: &dyn Primitive) -> Option<String> {
+        let outer = std::mem::take(&mut self.statements);
+        let tracked = (self.materials.take(), self.markers.take());
+        let result = obj.expression("p", self);
+        (self.materials, self.markers) = tracked;
+        let body = std::mem::replace(&mut self.statements, outer);
+        if body.len() < 2 {
+            return None;
+        }
+        let name = format!("sdShared{}", self.functions.len());
+        let stmts: String = body.iter().map(|s| format!("    {s}\n")).collect();
+        self.functions.push(format!(
+            "float {name}(vec3 p) {{\n{stmts}    return {result};\n}}"
+        ));
+        Some(name)
+    }
+}
+
+/// The distance `d` multiplied by `factor`, as GLSL.
+pub fn scaled_distance(d: &str, factor: f32) -> String {
+    if factor == 1.0 {
+        d.to_string()
+    } else {
+        format!("{d} * {factor:.8}")
+    }
 }
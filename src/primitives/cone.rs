@@ -1,4 +1,7 @@
-use crate::primitive::{Bbox, GlslCtx, Primitive};
+use crate::interval::length;
+use crate::primitive::{Bbox, GlslCtx, Primitive, hash_node};
+use crate::tape::{Op, Reg, Tape};
+use std::hash::Hasher;
 
 const INF: f32 = 1e10;
 
@@ -33,8 +36,49 @@ impl Primitive for InfCone {
         let r = (x * x + y * y).sqrt();
         (r - (self.slope * (z + self.offset)).abs()) * self.dm
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        for (o, &[x, y, z]) in out.iter_mut().zip(points) {
+            let r = (x * x + y * y).sqrt();
+            *o = (r - (self.slope * (z + self.offset)).abs()) * self.dm;
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let r = tape.length(&p[..2]);
+        let z = tape.push(Op::AddImm(p[2], self.offset));
+        let z = tape.push(Op::MulImm(z, self.slope));
+        let z = tape.push(Op::Abs(z));
+        let d = tape.push(Op::Sub(r, z));
+        tape.push(Op::MulImm(d, self.dm))
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let r = length(&[bbox.axis(0), bbox.axis(1)]);
+        ((r - ((bbox.axis(2) + self.offset) * self.slope).abs()) * self.dm).into()
+    }
+    fn gradient(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let r = (x * x + y * y).sqrt();
+        let (gx, gy) = if r > 0.0 { (x / r, y / r) } else { (0.0, 0.0) };
+        let gz = -self.slope * (self.slope * (z + self.offset)).signum();
+        [gx * self.dm, gy * self.dm, gz * self.dm]
+    }
     fn bbox(&self) -> Bbox {
-        Bbox { min: [-INF, -INF, -INF], max: [INF, INF, INF] }
+        Bbox {
+            min: [-INF, -INF, -INF],
+            max: [INF, INF, INF],
+        }
+    }
+    fn bbox_within(&self, clip: &Bbox) -> Bbox {
+        // The radius grows linearly away from the apex, so the z range of the
+        // clip box bounds it.
+        let (z0, z1) = (clip.min[2], clip.max[2]);
+        let r = self.slope.abs() * (z0 + self.offset).abs().max((z1 + self.offset).abs());
+        Bbox {
+            min: [-r, -r, z0],
+            max: [r, r, z1],
+        }
+        .intersection(clip)
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "InfCone", &[self.slope, self.offset]);
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
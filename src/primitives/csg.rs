@@ -1,4 +1,7 @@
-use crate::primitive::{Bbox, GlslCtx, Primitive};
+use crate::interval::Interval;
+use crate::primitive::{Bbox, DEFAULT_MATERIAL, GlslCtx, Primitive, hash_children, hash_node};
+use crate::tape::{Op, Reg, Tape};
+use std::hash::Hasher;
 
 const SMIN_HELPER: &str = "\
 float smin(float a, float b, float k) {
@@ -12,16 +15,136 @@ float smax(float a, float b, float k) {
     return mix(a, b, h) + k*h*(1.0-h);
 }";
 
-fn cpu_smin(a: f32, b: f32, k: f32) -> f32 {
+const MIX_MATERIAL_HELPER: &str = "\
+vec4 mixMaterial(float a, float b, vec4 ma, vec4 mb, float k) {
+    return mix(mb, ma, clamp(0.5 + 0.5*(b-a)/k, 0.0, 1.0));
+}";
+
+pub(crate) fn cpu_smin(a: f32, b: f32, k: f32) -> f32 {
     let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
     b * (1.0 - h) + a * h - k * h * (1.0 - h)
 }
 
-fn cpu_smax(a: f32, b: f32, k: f32) -> f32 {
+pub(crate) fn cpu_smax(a: f32, b: f32, k: f32) -> f32 {
     let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
     a * (1.0 - h) + b * h + k * h * (1.0 - h)
 }
 
+type ValueGrad = (f32, [f32; 3]);
+
+/// Value and gradient of a min-fold. Inside the smooth blend the derivative of
+/// the weight h cancels, leaving ∇smin = h·∇a + (1-h)·∇b.
+fn reduce_min_grad(vals: impl Iterator<Item = ValueGrad>, k: f32) -> ValueGrad {
+    vals.reduce(|(a, ga), (b, gb)| {
+        if k > 0.0 {
+            let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+            (
+                cpu_smin(a, b, k),
+                std::array::from_fn(|i| ga[i] * h + gb[i] * (1.0 - h)),
+            )
+        } else if a <= b {
+            (a, ga)
+        } else {
+            (b, gb)
+        }
+    })
+    .unwrap()
+}
+
+/// Value and gradient of a max-fold; ∇smax = (1-h)·∇a + h·∇b.
+fn reduce_max_grad(vals: impl Iterator<Item = ValueGrad>, k: f32) -> ValueGrad {
+    vals.reduce(|(a, ga), (b, gb)| {
+        if k > 0.0 {
+            let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+            (
+                cpu_smax(a, b, k),
+                std::array::from_fn(|i| ga[i] * (1.0 - h) + gb[i] * h),
+            )
+        } else if a >= b {
+            (a, ga)
+        } else {
+            (b, gb)
+        }
+    })
+    .unwrap()
+}
+
+/// Range of a min-fold over `vals`; smin undershoots min by at most k/4 per step.
+fn interval_min(vals: impl Iterator<Item = Interval>, k: f32) -> Interval {
+    let mut n = 0;
+    let r = vals.inspect(|_| n += 1).reduce(Interval::min).unwrap();
+    if k > 0.0 {
+        Interval::new(r.lo - (n - 1) as f32 * k / 4.0, r.hi)
+    } else {
+        r
+    }
+}
+
+/// Range of a max-fold over `vals`; smax overshoots max by at most k/4 per step.
+fn interval_max(vals: impl Iterator<Item = Interval>, k: f32) -> Interval {
+    let mut n = 0;
+    let r = vals.inspect(|_| n += 1).reduce(Interval::max).unwrap();
+    if k > 0.0 {
+        Interval::new(r.lo, r.hi + (n - 1) as f32 * k / 4.0)
+    } else {
+        r
+    }
+}
+
+/// Batched fold: evaluate the first child into `out`, then combine each further
+/// child's values into it with `op`, in the same order as the scalar fold.
+fn fold_many(
+    children: &[Box<dyn Primitive>],
+    points: &[[f32; 3]],
+    out: &mut [f32],
+    op: impl Fn(f32, f32) -> f32,
+) {
+    children[0].eval_many(points, out);
+    let mut tmp = vec![0.0; points.len()];
+    for c in &children[1..] {
+        c.eval_many(points, &mut tmp);
+        for (o, &v) in out.iter_mut().zip(&tmp) {
+            *o = op(*o, v);
+        }
+    }
+}
+
+/// Compile a left fold over the children's results, like the scalar `reduce`.
+fn compile_fold(
+    children: &[Box<dyn Primitive>],
+    p: [Reg; 3],
+    tape: &mut Tape,
+    op: impl Fn(&mut Tape, Reg, Reg) -> Reg,
+) -> Reg {
+    let first = children[0].compile(p, tape);
+    children[1..].iter().fold(first, |acc, c| {
+        let v = c.compile(p, tape);
+        op(tape, acc, v)
+    })
+}
+
+/// Bounds of the intersection of `children` and the extra half-spaces `planes`.
+/// Half-space children join `planes` and are clipped against each other; a
+/// second pass lets unbounded solids such as cones shrink to what remains.
+fn intersect_bounds(children: &[Box<dyn Primitive>], mut planes: Vec<([f32; 3], f32)>) -> Bbox {
+    let mut b = Bbox::infinite();
+    let mut solids = Vec::new();
+    for c in children {
+        match c.half_space() {
+            Some(h) => planes.push(h),
+            None => {
+                b = b.intersection(&c.bbox());
+                solids.push(c);
+            }
+        }
+    }
+    b = b.clip(&planes);
+    for c in solids {
+        b = c.bbox_within(&b);
+    }
+    b.clip(&planes)
+}
+
 /// Fold a list of GLSL variable names with `min(a, min(b, ...))`.
 fn fold_min(vars: &[String]) -> String {
     match vars {
@@ -58,6 +181,61 @@ fn fold_smax(vars: &[String], k: f32) -> String {
     }
 }
 
+/// The materials of the children at distances `vars`, while tracking
+/// materials and if any of them has one. Children without a material get the
+/// default one, or with `cut` that of the first child, so the faces an
+/// uncoloured object cuts into a coloured one keep its colour.
+fn child_materials(ctx: &GlslCtx, vars: &[String], cut: bool) -> Option<Vec<String>> {
+    if !ctx.tracking_materials() {
+        return None;
+    }
+    let mats: Vec<Option<String>> = vars.iter().map(|v| ctx.material_of(v)).collect();
+    if mats.iter().all(Option::is_none) {
+        return None;
+    }
+    let first = if cut { mats[0].clone() } else { None };
+    let fallback = first.unwrap_or_else(|| DEFAULT_MATERIAL.to_string());
+    Some(
+        mats.into_iter()
+            .map(|m| m.unwrap_or_else(|| fallback.clone()))
+            .collect(),
+    )
+}
+
+/// Give `d` the material of the surface a min-fold over `dists` comes from:
+/// that of the nearest one, blended where smoothing `k` blends the distances.
+/// Max-folds pass their distances negated.
+fn fold_materials(ctx: &mut GlslCtx, d: &str, dists: &[String], mats: &[String], k: f32) {
+    let (mut acc_d, mut acc_m) = (dists[0].clone(), mats[0].clone());
+    for (i, (v, m)) in dists.iter().zip(mats).enumerate().skip(1) {
+        let next_m = ctx.fresh_material();
+        if k > 0.0 {
+            ctx.add_helper(MIX_MATERIAL_HELPER);
+            ctx.push(format!(
+                "vec4 {next_m} = mixMaterial({acc_d}, {v}, {acc_m}, {m}, {k:.8});"
+            ));
+        } else {
+            ctx.push(format!("vec4 {next_m} = {acc_d} <= {v} ? {acc_m} : {m};"));
+        }
+        acc_m = next_m;
+        if i + 1 < dists.len() {
+            let next_d = ctx.fresh_float();
+            if k > 0.0 {
+                ctx.add_helper(SMIN_HELPER);
+                ctx.push(format!("float {next_d} = smin({acc_d}, {v}, {k:.8});"));
+            } else {
+                ctx.push(format!("float {next_d} = min({acc_d}, {v});"));
+            }
+            acc_d = next_d;
+        }
+    }
+    ctx.set_material(d, acc_m);
+}
+
+fn negated(vars: &[String]) -> Vec<String> {
+    vars.iter().map(|v| format!("-{v}")).collect()
+}
+
 // ── Union ─────────────────────────────────────────────────────────────────────
 
 #[derive(Clone)]
@@ -68,13 +246,20 @@ pub struct Union {
 
 impl Union {
     pub fn new(children: Vec<Box<dyn Primitive>>, smoothing: f32) -> Self {
-        Union { children, smoothing }
+        Union {
+            children,
+            smoothing,
+        }
     }
 }
 
 impl Primitive for Union {
     fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
-        let vars: Vec<String> = self.children.iter().map(|c| c.expression(p, ctx)).collect();
+        let vars: Vec<String> = self
+            .children
+            .iter()
+            .map(|c| ctx.child(c.as_ref(), p))
+            .collect();
         let d = ctx.fresh_float();
         if self.smoothing > 0.0 {
             ctx.add_helper(SMIN_HELPER);
@@ -82,18 +267,69 @@ impl Primitive for Union {
         } else {
             ctx.push(format!("float {d} = {};", fold_min(&vars)));
         }
+        if let Some(mats) = child_materials(ctx, &vars, false) {
+            fold_materials(ctx, &d, &vars, &mats, self.smoothing);
+        }
         d
     }
     fn eval(&self, p: [f32; 3]) -> f32 {
         let vals: Vec<f32> = self.children.iter().map(|c| c.eval(p)).collect();
         if self.smoothing > 0.0 {
-            vals.into_iter().reduce(|a, b| cpu_smin(a, b, self.smoothing)).unwrap()
+            vals.into_iter()
+                .reduce(|a, b| cpu_smin(a, b, self.smoothing))
+                .unwrap()
         } else {
             vals.into_iter().reduce(f32::min).unwrap()
         }
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let k = self.smoothing;
+        if k > 0.0 {
+            fold_many(&self.children, points, out, |a, b| cpu_smin(a, b, k));
+        } else {
+            fold_many(&self.children, points, out, f32::min);
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let k = self.smoothing;
+        compile_fold(&self.children, p, tape, |t, a, b| {
+            t.push(if k > 0.0 {
+                Op::SMin(a, b, k)
+            } else {
+                Op::Min(a, b)
+            })
+        })
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let vals = self
+            .children
+            .iter()
+            .map(|c| Interval::from(c.eval_interval(bbox)));
+        interval_min(vals, self.smoothing).into()
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        let vals = self.children.iter().map(|c| (c.eval(p), c.gradient(p)));
+        reduce_min_grad(vals, self.smoothing).1
+    }
     fn bbox(&self) -> Bbox {
-        self.children.iter().map(|c| c.bbox()).reduce(|a, b| a.union(&b)).unwrap()
+        // smin undershoots min by at most k/4 per fold step, which for
+        // 1-Lipschitz children moves the surface out by at most as much.
+        let b = self
+            .children
+            .iter()
+            .map(|c| c.bbox())
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let steps = self.children.len() - 1;
+        if self.smoothing > 0.0 {
+            b.expand(steps as f32 * self.smoothing / 4.0)
+        } else {
+            b
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Union", &[self.smoothing]);
+        hash_children(state, &self.children);
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
@@ -110,13 +346,20 @@ pub struct Intersection {
 
 impl Intersection {
     pub fn new(children: Vec<Box<dyn Primitive>>, smoothing: f32) -> Self {
-        Intersection { children, smoothing }
+        Intersection {
+            children,
+            smoothing,
+        }
     }
 }
 
 impl Primitive for Intersection {
     fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
-        let vars: Vec<String> = self.children.iter().map(|c| c.expression(p, ctx)).collect();
+        let vars: Vec<String> = self
+            .children
+            .iter()
+            .map(|c| ctx.child(c.as_ref(), p))
+            .collect();
         let d = ctx.fresh_float();
         if self.smoothing > 0.0 {
             ctx.add_helper(SMAX_HELPER);
@@ -124,18 +367,57 @@ impl Primitive for Intersection {
         } else {
             ctx.push(format!("float {d} = {};", fold_max(&vars)));
         }
+        if let Some(mats) = child_materials(ctx, &vars, true) {
+            fold_materials(ctx, &d, &negated(&vars), &mats, self.smoothing);
+        }
         d
     }
     fn eval(&self, p: [f32; 3]) -> f32 {
         let vals: Vec<f32> = self.children.iter().map(|c| c.eval(p)).collect();
         if self.smoothing > 0.0 {
-            vals.into_iter().reduce(|a, b| cpu_smax(a, b, self.smoothing)).unwrap()
+            vals.into_iter()
+                .reduce(|a, b| cpu_smax(a, b, self.smoothing))
+                .unwrap()
         } else {
             vals.into_iter().reduce(f32::max).unwrap()
         }
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let k = self.smoothing;
+        if k > 0.0 {
+            fold_many(&self.children, points, out, |a, b| cpu_smax(a, b, k));
+        } else {
+            fold_many(&self.children, points, out, f32::max);
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let k = self.smoothing;
+        compile_fold(&self.children, p, tape, |t, a, b| {
+            t.push(if k > 0.0 {
+                Op::SMax(a, b, k)
+            } else {
+                Op::Max(a, b)
+            })
+        })
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let vals = self
+            .children
+            .iter()
+            .map(|c| Interval::from(c.eval_interval(bbox)));
+        interval_max(vals, self.smoothing).into()
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        let vals = self.children.iter().map(|c| (c.eval(p), c.gradient(p)));
+        reduce_max_grad(vals, self.smoothing).1
+    }
     fn bbox(&self) -> Bbox {
-        self.children.iter().map(|c| c.bbox()).reduce(|a, b| a.intersection(&b)).unwrap()
+        // smax never undershoots max, so smoothing only shrinks the result.
+        intersect_bounds(&self.children, Vec::new())
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Intersection", &[self.smoothing]);
+        hash_children(state, &self.children);
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
@@ -153,19 +435,24 @@ pub struct Difference {
 
 impl Difference {
     pub fn new(children: Vec<Box<dyn Primitive>>, smoothing: f32) -> Self {
-        Difference { children, smoothing }
+        Difference {
+            children,
+            smoothing,
+        }
     }
 }
 
 impl Primitive for Difference {
     fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
-        let first = self.children[0].expression(p, ctx);
+        let first = ctx.child(self.children[0].as_ref(), p);
+        let mut vars = vec![first.clone()];
         let rest_neg: Vec<String> = self.children[1..]
             .iter()
             .map(|c| {
-                let v = c.expression(p, ctx);
+                let v = ctx.child(c.as_ref(), p);
                 let neg = ctx.fresh_float();
                 ctx.push(format!("float {neg} = -{v};"));
+                vars.push(v);
                 neg
             })
             .collect();
@@ -180,6 +467,9 @@ impl Primitive for Difference {
         } else {
             ctx.push(format!("float {d} = {};", fold_max(&all)));
         }
+        if let Some(mats) = child_materials(ctx, &vars, true) {
+            fold_materials(ctx, &d, &negated(&all), &mats, self.smoothing);
+        }
         d
     }
     fn eval(&self, p: [f32; 3]) -> f32 {
@@ -188,14 +478,115 @@ impl Primitive for Difference {
         let mut all = vec![first];
         all.extend(rest_neg);
         if self.smoothing > 0.0 {
-            all.into_iter().reduce(|a, b| cpu_smax(a, b, self.smoothing)).unwrap()
+            all.into_iter()
+                .reduce(|a, b| cpu_smax(a, b, self.smoothing))
+                .unwrap()
         } else {
             all.into_iter().reduce(f32::max).unwrap()
         }
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let k = self.smoothing;
+        if k > 0.0 {
+            fold_many(&self.children, points, out, |a, b| cpu_smax(a, -b, k));
+        } else {
+            fold_many(&self.children, points, out, |a, b| a.max(-b));
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let k = self.smoothing;
+        compile_fold(&self.children, p, tape, |t, a, b| {
+            let b = t.push(Op::Neg(b));
+            t.push(if k > 0.0 {
+                Op::SMax(a, b, k)
+            } else {
+                Op::Max(a, b)
+            })
+        })
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let first = Interval::from(self.children[0].eval_interval(bbox));
+        let rest = self.children[1..]
+            .iter()
+            .map(|c| -Interval::from(c.eval_interval(bbox)));
+        interval_max(std::iter::once(first).chain(rest), self.smoothing).into()
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        let first = (self.children[0].eval(p), self.children[0].gradient(p));
+        let rest = self.children[1..]
+            .iter()
+            .map(|c| (-c.eval(p), c.gradient(p).map(|g| -g)));
+        reduce_max_grad(std::iter::once(first).chain(rest), self.smoothing).1
+    }
     fn bbox(&self) -> Bbox {
-        // Conservative: use first child's bbox
-        self.children[0].bbox()
+        // Subtracting a half-space keeps its complement, which clips the first
+        // child; other subtrahends and smoothing only shrink the result.
+        let complements = self.children[1..]
+            .iter()
+            .filter_map(|c| c.half_space())
+            .map(|(n, d)| (n.map(|v| -v), -d))
+            .collect();
+        intersect_bounds(&self.children[..1], complements)
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Difference", &[self.smoothing]);
+        hash_children(state, &self.children);
+    }
+    fn clone_box(&self) -> Box<dyn Primitive> {
+        Box::new(self.clone())
+    }
+}
+
+// ── Complement ────────────────────────────────────────────────────────────────
+// everything outside the child: -d
+
+#[derive(Clone)]
+pub struct Complement {
+    pub child: Box<dyn Primitive>,
+}
+
+impl Complement {
+    pub fn new(child: Box<dyn Primitive>) -> Self {
+        Complement { child }
+    }
+}
+
+impl Primitive for Complement {
+    fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
+        let v = ctx.child(self.child.as_ref(), p);
+        let d = ctx.fresh_float();
+        ctx.push(format!("float {d} = -{v};"));
+        d
+    }
+    fn eval(&self, p: [f32; 3]) -> f32 {
+        -self.child.eval(p)
+    }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        self.child.eval_many(points, out);
+        out.iter_mut().for_each(|v| *v = -*v);
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let v = self.child.compile(p, tape);
+        tape.push(Op::Neg(v))
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        (-Interval::from(self.child.eval_interval(bbox))).into()
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        self.child.gradient(p).map(|g| -g)
+    }
+    fn bbox(&self) -> Bbox {
+        self.child.complement_bbox()
+    }
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        self.child.half_space().map(|(n, d)| (n.map(|v| -v), -d))
+    }
+    fn complement_bbox(&self) -> Bbox {
+        self.child.bbox()
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Complement", &[]);
+        hash_children(state, std::slice::from_ref(&self.child));
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
@@ -1,31 +1,60 @@
-use crate::primitive::{Bbox, GlslCtx, Primitive};
+use crate::interval::length;
+use crate::param::Scalar;
+use crate::primitive::{Bbox, GlslCtx, Primitive, hash_node, normalize};
+use crate::tape::{Op, Reg, Tape};
+use std::hash::Hasher;
 
 const INF: f32 = 1e10;
 
 /// Infinite cylinder along the Z-axis.
 #[derive(Clone)]
 pub struct InfCylinder {
-    pub radius: f32,
+    pub radius: Scalar,
 }
 
 impl InfCylinder {
-    pub fn new(radius: f32) -> Self {
-        InfCylinder { radius }
+    pub fn new(radius: impl Into<Scalar>) -> Self {
+        InfCylinder {
+            radius: radius.into(),
+        }
     }
 }
 
 impl Primitive for InfCylinder {
     fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
         let d = ctx.fresh_float();
-        ctx.push(format!("float {d} = length({p}.xy) - {:.8};", self.radius));
+        let r = ctx.scalar(&self.radius);
+        ctx.push(format!("float {d} = length({p}.xy) - {r};"));
         d
     }
     fn eval(&self, [x, y, _z]: [f32; 3]) -> f32 {
-        (x * x + y * y).sqrt() - self.radius
+        (x * x + y * y).sqrt() - self.radius.value
+    }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        for (o, &[x, y, _z]) in out.iter_mut().zip(points) {
+            *o = (x * x + y * y).sqrt() - self.radius.value;
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let r = tape.length(&p[..2]);
+        tape.push(Op::AddImm(r, -self.radius.value))
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        (length(&[bbox.axis(0), bbox.axis(1)]) - self.radius.value).into()
+    }
+    fn gradient(&self, [x, y, _z]: [f32; 3]) -> [f32; 3] {
+        normalize([x, y, 0.0])
     }
     fn bbox(&self) -> Bbox {
-        let r = self.radius;
-        Bbox { min: [-r, -r, -INF], max: [r, r, INF] }
+        let r = self.radius.value;
+        Bbox {
+            min: [-r, -r, -INF],
+            max: [r, r, INF],
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "InfCylinder", &[]);
+        self.radius.hash_into(state);
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
@@ -1,4 +1,21 @@
-use crate::primitive::{Bbox, GlslCtx, Primitive};
+use crate::primitive::{Bbox, GlslCtx, Primitive, hash_node};
+use crate::tape::{Op, Reg, Tape};
+use std::hash::Hasher;
+
+/// Rotate `(a, b)` by the angle in register `angle`: (a·c - b·s, a·s + b·c).
+fn compile_rotation(tape: &mut Tape, a: Reg, b: Reg, angle: Reg) -> [Reg; 2] {
+    let (s, c) = (tape.push(Op::Sin(angle)), tape.push(Op::Cos(angle)));
+    let (a_c, b_s) = (tape.push(Op::Mul(a, c)), tape.push(Op::Mul(b, s)));
+    let (a_s, b_c) = (tape.push(Op::Mul(a, s)), tape.push(Op::Mul(b, c)));
+    [tape.push(Op::Sub(a_c, b_s)), tape.push(Op::Add(a_s, b_c))]
+}
+
+/// Largest distance from the Z axis of any point in `b`.
+fn corner_radius(b: &Bbox) -> f32 {
+    let x = b.min[0].abs().max(b.max[0].abs());
+    let y = b.min[1].abs().max(b.max[1].abs());
+    (x * x + y * y).sqrt()
+}
 
 // ── Bender ────────────────────────────────────────────────────────────────────
 // Bends the XZ plane based on the Y coordinate.
@@ -28,21 +45,61 @@ impl Primitive for Bender {
             {p}.x * sin(_by_{p1}) + {p}.z * cos(_by_{p1}), \
             {p}.y);"
         ));
-        self.inner.expression(&p1, ctx)
+        ctx.child(self.inner.as_ref(), &p1)
     }
     fn eval(&self, [x, y, z]: [f32; 3]) -> f32 {
         let angle = y / self.width;
         let (s, c) = angle.sin_cos();
         self.inner.eval([x * c - z * s, x * s + z * c, y])
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let q: Vec<[f32; 3]> = points
+            .iter()
+            .map(|&[x, y, z]| {
+                let (s, c) = (y / self.width).sin_cos();
+                [x * c - z * s, x * s + z * c, y]
+            })
+            .collect();
+        self.inner.eval_many(&q, out);
+    }
+    fn compile(&self, [x, y, z]: [Reg; 3], tape: &mut Tape) -> Reg {
+        let angle = tape.push(Op::MulImm(y, 1.0 / self.width));
+        let [u, v] = compile_rotation(tape, x, z, angle);
+        self.inner.compile([u, v, y], tape)
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let [x, y, z] = bbox.axes();
+        let angle = y * (1.0 / self.width);
+        let (s, c) = (angle.sin(), angle.cos());
+        self.inner
+            .eval_interval(&Bbox::from_axes([x * c - z * s, x * s + z * c, y]))
+    }
+    fn gradient(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let angle = y / self.width;
+        let (s, c) = angle.sin_cos();
+        let g = self.inner.gradient([x * c - z * s, x * s + z * c, y]);
+        // Jacobian columns: ∂/∂x = (c, s, 0), ∂/∂z = (-s, c, 0),
+        // ∂/∂y = ((-x·s - z·c) / w, (x·c - z·s) / w, 1).
+        [
+            g[0] * c + g[1] * s,
+            (g[0] * (-x * s - z * c) + g[1] * (x * c - z * s)) / self.width + g[2],
+            -g[0] * s + g[1] * c,
+        ]
+    }
     fn bbox(&self) -> Bbox {
-        // After bending: new_z = old_y, and new_x/new_y = rotation of old_x/old_z.
-        // Max |new_x| = max |new_y| ≤ sqrt(x_half² + z_half²).
+        // The inner point is (R(y/w)·(x, z), y): world y is inner z, and world
+        // (x, z) is a rotation of inner (x, y), so its length is bounded by the
+        // inner box's XY corner radius.
         let b = self.inner.bbox();
-        let x_half = b.min[0].abs().max(b.max[0].abs());
-        let z_half = b.min[2].abs().max(b.max[2].abs());
-        let r = (x_half * x_half + z_half * z_half).sqrt();
-        Bbox { min: [-r, -r, b.min[1]], max: [r, r, b.max[1]] }
+        let r = corner_radius(&b);
+        Bbox {
+            min: [-r, b.min[2], -r],
+            max: [r, b.max[2], r],
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Bender", &[self.width]);
+        self.inner.hash_structure(state);
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
@@ -75,18 +132,58 @@ impl Primitive for Twister {
             \nfloat _c_{p1} = cos(_angle_{p1}), _s_{p1} = sin(_angle_{p1});\
             \nvec3 {p1} = vec3(_c_{p1}*{p}.x - _s_{p1}*{p}.y, _s_{p1}*{p}.x + _c_{p1}*{p}.y, {p}.z);"
         ));
-        self.inner.expression(&p1, ctx)
+        ctx.child(self.inner.as_ref(), &p1)
     }
     fn eval(&self, [x, y, z]: [f32; 3]) -> f32 {
         let angle = z / self.height * std::f32::consts::TAU;
         let (s, c) = angle.sin_cos();
         self.inner.eval([c * x - s * y, s * x + c * y, z])
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let q: Vec<[f32; 3]> = points
+            .iter()
+            .map(|&[x, y, z]| {
+                let (s, c) = (z / self.height * std::f32::consts::TAU).sin_cos();
+                [c * x - s * y, s * x + c * y, z]
+            })
+            .collect();
+        self.inner.eval_many(&q, out);
+    }
+    fn compile(&self, [x, y, z]: [Reg; 3], tape: &mut Tape) -> Reg {
+        let angle = tape.push(Op::MulImm(z, std::f32::consts::TAU / self.height));
+        let [u, v] = compile_rotation(tape, x, y, angle);
+        self.inner.compile([u, v, z], tape)
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let [x, y, z] = bbox.axes();
+        let angle = z * (std::f32::consts::TAU / self.height);
+        let (s, c) = (angle.sin(), angle.cos());
+        self.inner
+            .eval_interval(&Bbox::from_axes([c * x - s * y, s * x + c * y, z]))
+    }
+    fn gradient(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let k = std::f32::consts::TAU / self.height;
+        let (s, c) = (z * k).sin_cos();
+        let g = self.inner.gradient([c * x - s * y, s * x + c * y, z]);
+        // Jacobian columns: ∂/∂x = (c, s, 0), ∂/∂y = (-s, c, 0),
+        // ∂/∂z = ((-s·x - c·y)·k, (c·x - s·y)·k, 1).
+        [
+            g[0] * c + g[1] * s,
+            -g[0] * s + g[1] * c,
+            (g[0] * (-s * x - c * y) + g[1] * (c * x - s * y)) * k + g[2],
+        ]
+    }
     fn bbox(&self) -> Bbox {
         let b = self.inner.bbox();
-        let r = b.min[0].abs().max(b.max[0].abs())
-            .max(b.min[1].abs()).max(b.max[1].abs());
-        Bbox { min: [-r, -r, b.min[2]], max: [r, r, b.max[2]] }
+        let r = corner_radius(&b);
+        Bbox {
+            min: [-r, -r, b.min[2]],
+            max: [r, r, b.max[2]],
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Twister", &[self.height]);
+        self.inner.hash_structure(state);
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
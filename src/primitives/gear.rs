@@ -0,0 +1,206 @@
+use crate::primitive::{Bbox, GlslCtx, Primitive, hash_node};
+use std::hash::Hasher;
+
+// ── Involute gear ─────────────────────────────────────────────────────────────
+// Spur or helical gear centred on the origin, axis along Z.
+//
+// The 2D profile is evaluated in polar coordinates folded into half a tooth
+// sector. All involutes of one base circle are parallel curves, so the signed
+// distance to a flank is exact: rb * (θ + inv(α) - ψb), with cos(α) = rb / r and
+// inv(α) = tan(α) - α. Below the base circle the flank continues radially.
+// Tip and root are clipped by the addendum and dedendum circles.
+// Helical gears rotate the profile by z·tan(β)/rp; the field is then scaled by
+// the largest gradient stretch (at the tip circle) to stay a lower bound.
+
+const GEAR_HELPER: &str = "\
+float sdGear(vec3 p, float n, float rb, float ra, float rf, float psib, float twist, float halfT, float lip) {
+    float r = length(p.xy);
+    float sector = 6.28318530718 / n;
+    float a = atan(p.y, p.x) - p.z * twist;
+    float t = abs(mod(a + 0.5 * sector, sector) - 0.5 * sector);
+    float flank;
+    if (r > rb) {
+        float alpha = acos(rb / r);
+        flank = rb * (t + tan(alpha) - alpha - psib);
+    } else {
+        flank = r * sin(t - psib);
+    }
+    float d = min(max(flank, r - ra), r - rf) * lip;
+    return max(d, abs(p.z) - halfT);
+}";
+
+#[derive(Clone)]
+pub struct Gear {
+    pub module: f32,
+    pub teeth: u32,
+    pub pressure_angle: f32,
+    pub thickness: f32,
+    pub helix_angle: f32,
+    pub bore: f32,
+    rb: f32,    // base circle radius
+    ra: f32,    // addendum (tip) radius
+    rf: f32,    // dedendum (root) radius
+    psib: f32,  // half tooth angle at the base circle
+    twist: f32, // profile rotation per unit z
+    lip: f32,   // Lipschitz correction for the helical twist
+}
+
+fn involute(alpha: f32) -> f32 {
+    alpha.tan() - alpha
+}
+
+type ValueGrad = (f32, [f32; 3]);
+
+fn max_grad(a: ValueGrad, b: ValueGrad) -> ValueGrad {
+    if a.0 >= b.0 { a } else { b }
+}
+
+fn min_grad(a: ValueGrad, b: ValueGrad) -> ValueGrad {
+    if a.0 <= b.0 { a } else { b }
+}
+
+impl Gear {
+    /// `module` is the transverse module, angles are in radians and `bore` is
+    /// the diameter of a central hole (0 for none).
+    pub fn new(
+        module: f32,
+        teeth: u32,
+        pressure_angle: f32,
+        thickness: f32,
+        helix_angle: f32,
+        bore: f32,
+    ) -> Self {
+        let n = teeth as f32;
+        let rp = module * n / 2.0;
+        let rb = rp * pressure_angle.cos();
+        let ra = rp + module;
+        let rf = rp - 1.25 * module;
+        let psib = std::f32::consts::PI / (2.0 * n) + involute(pressure_angle);
+        let twist = helix_angle.tan() / rp;
+        let lip = 1.0 / (1.0 + (ra * twist) * (ra * twist)).sqrt();
+        Gear {
+            module,
+            teeth,
+            pressure_angle,
+            thickness,
+            helix_angle,
+            bore,
+            rb,
+            ra,
+            rf,
+            psib,
+            twist,
+            lip,
+        }
+    }
+}
+
+impl Primitive for Gear {
+    fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
+        ctx.add_helper(GEAR_HELPER);
+        let d = ctx.fresh_float();
+        ctx.push(format!(
+            "float {d} = sdGear({p}, {:.8}, {:.8}, {:.8}, {:.8}, {:.8}, {:.8}, {:.8}, {:.8});",
+            self.teeth as f32,
+            self.rb,
+            self.ra,
+            self.rf,
+            self.psib,
+            self.twist,
+            self.thickness / 2.0,
+            self.lip,
+        ));
+        if self.bore > 0.0 {
+            ctx.push(format!(
+                "{d} = max({d}, {:.8} - length({p}.xy));",
+                self.bore / 2.0
+            ));
+        }
+        d
+    }
+    fn eval(&self, [x, y, z]: [f32; 3]) -> f32 {
+        let r = (x * x + y * y).sqrt();
+        let sector = std::f32::consts::TAU / self.teeth as f32;
+        let a = y.atan2(x) - z * self.twist;
+        let t = ((a + 0.5 * sector).rem_euclid(sector) - 0.5 * sector).abs();
+        let flank = if r > self.rb {
+            let alpha = (self.rb / r).acos();
+            self.rb * (t + involute(alpha) - self.psib)
+        } else {
+            r * (t - self.psib).sin()
+        };
+        let d = flank.max(r - self.ra).min(r - self.rf) * self.lip;
+        let d = d.max(z.abs() - self.thickness / 2.0);
+        if self.bore > 0.0 {
+            d.max(self.bore / 2.0 - r)
+        } else {
+            d
+        }
+    }
+    fn gradient(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let r = (x * x + y * y).sqrt().max(f32::MIN_POSITIVE);
+        let sector = std::f32::consts::TAU / self.teeth as f32;
+        let a = y.atan2(x) - z * self.twist;
+        let u = (a + 0.5 * sector).rem_euclid(sector) - 0.5 * sector;
+        let t = u.abs();
+        let grad_r = [x / r, y / r, 0.0];
+        let grad_t = [-y / (r * r), x / (r * r), -self.twist].map(|v| v * u.signum());
+        // ∂inv(α)/∂r = sqrt(r² - rb²) / (r·rb)
+        let (flank, df_dr, df_dt) = if r > self.rb {
+            let alpha = (self.rb / r).acos();
+            let s = (r * r - self.rb * self.rb).sqrt();
+            (self.rb * (t + involute(alpha) - self.psib), s / r, self.rb)
+        } else {
+            let (s, c) = (t - self.psib).sin_cos();
+            (r * s, s, r * c)
+        };
+        let flank = (
+            flank,
+            std::array::from_fn(|i| df_dr * grad_r[i] + df_dt * grad_t[i]),
+        );
+        let tip = (r - self.ra, grad_r);
+        let root = (r - self.rf, grad_r);
+        let (d, g) = min_grad(max_grad(flank, tip), root);
+        let profile = (d * self.lip, g.map(|v| v * self.lip));
+        let faces = (z.abs() - self.thickness / 2.0, [0.0, 0.0, z.signum()]);
+        let mut result = max_grad(profile, faces);
+        if self.bore > 0.0 {
+            result = max_grad(result, (self.bore / 2.0 - r, grad_r.map(|v| -v)));
+        }
+        result.1
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        // The field is 1-Lipschitz apart from the small step where the flank
+        // switches from the involute to the radial line at the base circle.
+        let a = self.psib.max(std::f32::consts::PI / self.teeth as f32);
+        let slack = self.rb * (a - a.sin());
+        let d = self.eval(bbox.center());
+        let r = bbox.radius() + slack;
+        (d - r, d + r)
+    }
+    fn bbox(&self) -> Bbox {
+        let r = self.ra;
+        let h = self.thickness / 2.0;
+        Bbox {
+            min: [-r, -r, -h],
+            max: [r, r, h],
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(
+            state,
+            "Gear",
+            &[
+                self.module,
+                self.teeth as f32,
+                self.pressure_angle,
+                self.thickness,
+                self.helix_angle,
+                self.bore,
+            ],
+        );
+    }
+    fn clone_box(&self) -> Box<dyn Primitive> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,63 @@
+use crate::primitive::{Bbox, GlslCtx, Mark, Primitive, hash_node};
+use crate::tape::{Reg, Tape};
+use std::hash::Hasher;
+
+// ── Marker ────────────────────────────────────────────────────────────────────
+// The inner object unchanged, marked for the preview: drawn as an overlay even
+// where a difference removes it, or drawn alone. Meshing and export ignore the
+// mark.
+
+#[derive(Clone)]
+pub struct Marker {
+    inner: Box<dyn Primitive>,
+    pub mark: Mark,
+}
+
+impl Marker {
+    pub fn new(inner: Box<dyn Primitive>, mark: Mark) -> Self {
+        Marker { inner, mark }
+    }
+}
+
+impl Primitive for Marker {
+    fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
+        ctx.mark_marker();
+        let d = ctx.child(self.inner.as_ref(), p);
+        ctx.record_marker(self.mark, &d);
+        d
+    }
+    fn eval(&self, p: [f32; 3]) -> f32 {
+        self.inner.eval(p)
+    }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        self.inner.eval_many(points, out);
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        self.inner.compile(p, tape)
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        self.inner.eval_interval(bbox)
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        self.inner.gradient(p)
+    }
+    fn bbox(&self) -> Bbox {
+        self.inner.bbox()
+    }
+    fn bbox_within(&self, clip: &Bbox) -> Bbox {
+        self.inner.bbox_within(clip)
+    }
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        self.inner.half_space()
+    }
+    fn complement_bbox(&self) -> Bbox {
+        self.inner.complement_bbox()
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Marker", &[self.mark as u8 as f32]);
+        self.inner.hash_structure(state);
+    }
+    fn clone_box(&self) -> Box<dyn Primitive> {
+        Box::new(self.clone())
+    }
+}
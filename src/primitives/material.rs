@@ -0,0 +1,83 @@
+use crate::primitive::{Bbox, GlslCtx, Primitive, hash_node};
+use crate::tape::{Reg, Tape};
+use std::hash::Hasher;
+
+// ── Material ──────────────────────────────────────────────────────────────────
+// The inner object unchanged, with the colour and specular strength the preview
+// shades its surface with. A material covers its whole subtree: materials set
+// further in are not emitted.
+
+#[derive(Clone)]
+pub struct Material {
+    inner: Box<dyn Primitive>,
+    /// sRGB colour, components in [0, 1].
+    pub color: [f32; 3],
+    /// Strength of the specular highlight, in [0, 1].
+    pub specular: f32,
+}
+
+impl Material {
+    pub fn new(inner: Box<dyn Primitive>, color: [f32; 3], specular: f32) -> Self {
+        Material {
+            inner,
+            color,
+            specular,
+        }
+    }
+}
+
+impl Primitive for Material {
+    fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
+        ctx.mark_material();
+        if !ctx.tracking_materials() {
+            return ctx.child(self.inner.as_ref(), p);
+        }
+        // The distance is the same as without tracking, so a repeated material
+        // node still calls its shared function.
+        let d = ctx.without_materials(|ctx| ctx.child(self, p));
+        // The renderer shades in linear light.
+        let [r, g, b] = self.color.map(|c| c.powf(2.2));
+        let m = ctx.fresh_material();
+        ctx.push(format!(
+            "vec4 {m} = vec4({r:.8}, {g:.8}, {b:.8}, {:.8});",
+            self.specular
+        ));
+        ctx.set_material(&d, m);
+        d
+    }
+    fn eval(&self, p: [f32; 3]) -> f32 {
+        self.inner.eval(p)
+    }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        self.inner.eval_many(points, out);
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        self.inner.compile(p, tape)
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        self.inner.eval_interval(bbox)
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        self.inner.gradient(p)
+    }
+    fn bbox(&self) -> Bbox {
+        self.inner.bbox()
+    }
+    fn bbox_within(&self, clip: &Bbox) -> Bbox {
+        self.inner.bbox_within(clip)
+    }
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        self.inner.half_space()
+    }
+    fn complement_bbox(&self) -> Bbox {
+        self.inner.complement_bbox()
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        let [r, g, b] = self.color;
+        hash_node(state, "Material", &[r, g, b, self.specular]);
+        self.inner.hash_structure(state);
+    }
+    fn clone_box(&self) -> Box<dyn Primitive> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,315 @@
+use crate::interval::Interval;
+use crate::primitive::{Bbox, DEFAULT_MATERIAL, GlslCtx, Primitive, hash_node, scaled_distance};
+use crate::tape::{Op, Reg, Tape};
+use std::hash::Hasher;
+
+// ── Minkowski sum ─────────────────────────────────────────────────────────────
+// Sum of an object A with a convex kernel K centred on the origin. The distance
+// to A ⊕ K is min over q ∈ K of dist(p - q, A); each kernel evaluates that
+// minimum differently:
+//
+//   Ball     — d(p) - r. Exact wherever the inner field is exact.
+//   Box      — elongation: evaluate A at p - clamp(p - c, -h, h), with c the
+//              centre of A's bbox. Exact when A is convex and mirror-symmetric
+//              about c on each axis (boxes, spheres, cylinders, …); otherwise
+//              the result is contained in the true sum.
+//   Cylinder — elongation along Z as for Box, then a disc in XY. For points
+//              outside the sum the minimum over a disc lies on its rim, so the
+//              rim is sampled at RIM_SAMPLES angles (plus the centre for points
+//              inside). For convex A the surface deviates from the exact sum by
+//              at most r·(1 - cos(π / RIM_SAMPLES)).
+
+const RIM_SAMPLES: usize = 16;
+
+#[derive(Clone, Copy)]
+pub enum MinkowskiKernel {
+    Ball {
+        radius: f32,
+    },
+    /// Half extents along X, Y, Z.
+    Box {
+        half: [f32; 3],
+    },
+    /// Disc of `radius` in XY swept over Z ∈ [-half_height, half_height].
+    Cylinder {
+        radius: f32,
+        half_height: f32,
+    },
+}
+
+#[derive(Clone)]
+pub struct Minkowski {
+    inner: Box<dyn Primitive>,
+    pub kernel: MinkowskiKernel,
+    centre: [f32; 3],
+}
+
+impl Minkowski {
+    pub fn new(inner: Box<dyn Primitive>, kernel: MinkowskiKernel) -> Self {
+        let centre = inner.bbox().center();
+        Minkowski {
+            inner,
+            kernel,
+            centre,
+        }
+    }
+}
+
+impl Primitive for Minkowski {
+    fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
+        let [cx, cy, cz] = self.centre;
+        match self.kernel {
+            MinkowskiKernel::Ball { radius } => {
+                let d_inner = ctx.child(self.inner.as_ref(), p);
+                let d = ctx.fresh_float();
+                ctx.push(format!("float {d} = {d_inner} - {radius:.8};"));
+                d
+            }
+            MinkowskiKernel::Box { half: [hx, hy, hz] } => {
+                let p1 = ctx.fresh_point();
+                ctx.push(format!(
+                    "vec3 {p1} = {p} - clamp({p} - vec3({cx:.8}, {cy:.8}, {cz:.8}), \
+                     -vec3({hx:.8}, {hy:.8}, {hz:.8}), vec3({hx:.8}, {hy:.8}, {hz:.8}));"
+                ));
+                ctx.child(self.inner.as_ref(), &p1)
+            }
+            MinkowskiKernel::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let p1 = ctx.fresh_point();
+                let p2 = ctx.fresh_point();
+                let d = ctx.fresh_float();
+                let step = std::f32::consts::TAU / RIM_SAMPLES as f32;
+                ctx.push(format!(
+                    "vec3 {p1} = vec3({p}.xy, {p}.z - clamp({p}.z - {cz:.8}, \
+                     -{half_height:.8}, {half_height:.8}));"
+                ));
+                ctx.push(format!("float {d} = 1e10;"));
+                let at = ctx.statements.len();
+                // Sample RIM_SAMPLES points on the rim; the last iteration is the centre.
+                ctx.push(format!(
+                    "for (int i_{d} = 0; i_{d} <= {RIM_SAMPLES}; i_{d}++) {{\
+                     \nfloat _a_{d} = float(i_{d}) * {step:.8};\
+                     \nvec3 {p2} = {p1} - float(i_{d} < {RIM_SAMPLES}) * \
+                     vec3({radius:.8} * cos(_a_{d}), {radius:.8} * sin(_a_{d}), 0.0);"
+                ));
+                let (d_inner, marked) =
+                    ctx.markers_within(|ctx| ctx.child(self.inner.as_ref(), &p2));
+                // The inner distances and materials are declared in the loop;
+                // keep the nearest sample's in variables declared before it.
+                if let Some(mi) = ctx.material_of(&d_inner) {
+                    let m = ctx.fresh_material();
+                    ctx.statements
+                        .insert(at, format!("vec4 {m} = {DEFAULT_MATERIAL};"));
+                    ctx.push(format!("if ({d_inner} < {d}) {m} = {mi};"));
+                    ctx.set_material(&d, m);
+                }
+                for (mark, dm_inner, factor) in marked {
+                    let dm = ctx.fresh_float();
+                    ctx.statements.insert(at, format!("float {dm} = 1e10;"));
+                    let sample = scaled_distance(&dm_inner, factor);
+                    if let Some(mi) = ctx.material_of(&dm_inner) {
+                        let m = ctx.fresh_material();
+                        ctx.statements
+                            .insert(at, format!("vec4 {m} = {DEFAULT_MATERIAL};"));
+                        ctx.push(format!("if ({sample} < {dm}) {m} = {mi};"));
+                        ctx.set_material(&dm, m);
+                    }
+                    ctx.push(format!("{dm} = min({dm}, {sample});"));
+                    ctx.record_marker(mark, &dm);
+                }
+                ctx.push(format!("{d} = min({d}, {d_inner});\n}}"));
+                d
+            }
+        }
+    }
+    fn eval(&self, p: [f32; 3]) -> f32 {
+        let c = self.centre;
+        match self.kernel {
+            MinkowskiKernel::Ball { radius } => self.inner.eval(p) - radius,
+            MinkowskiKernel::Box { half } => {
+                let q: [f32; 3] =
+                    std::array::from_fn(|i| p[i] - (p[i] - c[i]).clamp(-half[i], half[i]));
+                self.inner.eval(q)
+            }
+            MinkowskiKernel::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let z = p[2] - (p[2] - c[2]).clamp(-half_height, half_height);
+                let step = std::f32::consts::TAU / RIM_SAMPLES as f32;
+                let rim = (0..RIM_SAMPLES).map(|i| {
+                    let (s, co) = (i as f32 * step).sin_cos();
+                    self.inner.eval([p[0] - radius * co, p[1] - radius * s, z])
+                });
+                rim.fold(self.inner.eval([p[0], p[1], z]), f32::min)
+            }
+        }
+    }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let c = self.centre;
+        match self.kernel {
+            MinkowskiKernel::Ball { radius } => {
+                self.inner.eval_many(points, out);
+                for o in out.iter_mut() {
+                    *o -= radius;
+                }
+            }
+            MinkowskiKernel::Box { half } => {
+                let q: Vec<[f32; 3]> = points
+                    .iter()
+                    .map(|p| std::array::from_fn(|i| p[i] - (p[i] - c[i]).clamp(-half[i], half[i])))
+                    .collect();
+                self.inner.eval_many(&q, out);
+            }
+            MinkowskiKernel::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let axis: Vec<[f32; 3]> = points
+                    .iter()
+                    .map(|&[x, y, z]| [x, y, z - (z - c[2]).clamp(-half_height, half_height)])
+                    .collect();
+                self.inner.eval_many(&axis, out);
+                let step = std::f32::consts::TAU / RIM_SAMPLES as f32;
+                let mut q = axis.clone();
+                let mut tmp = vec![0.0; points.len()];
+                for i in 0..RIM_SAMPLES {
+                    let (s, co) = (i as f32 * step).sin_cos();
+                    for (qj, aj) in q.iter_mut().zip(&axis) {
+                        *qj = [aj[0] - radius * co, aj[1] - radius * s, aj[2]];
+                    }
+                    self.inner.eval_many(&q, &mut tmp);
+                    for (o, &v) in out.iter_mut().zip(&tmp) {
+                        *o = o.min(v);
+                    }
+                }
+            }
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        match self.kernel {
+            MinkowskiKernel::Ball { radius } => {
+                let d = self.inner.compile(p, tape);
+                tape.push(Op::AddImm(d, -radius))
+            }
+            MinkowskiKernel::Box { half } => {
+                let q = [0, 1, 2].map(|i| {
+                    let t = tape.push(Op::AddImm(p[i], -self.centre[i]));
+                    let t = tape.push(Op::MaxImm(t, -half[i]));
+                    let t = tape.push(Op::MinImm(t, half[i]));
+                    tape.push(Op::Sub(p[i], t))
+                });
+                self.inner.compile(q, tape)
+            }
+            // The rim samples would repeat the inner tape RIM_SAMPLES + 1 times.
+            MinkowskiKernel::Cylinder { .. } => tape.push(Op::Opaque(self.clone_box(), p)),
+        }
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        // Along an elongated axis the clamp absorbs motion inside the kernel.
+        let c = self.centre;
+        let passes = |i: usize, h: f32| if (p[i] - c[i]).abs() > h { 1.0 } else { 0.0 };
+        match self.kernel {
+            MinkowskiKernel::Ball { .. } => self.inner.gradient(p),
+            MinkowskiKernel::Box { half } => {
+                let q: [f32; 3] =
+                    std::array::from_fn(|i| p[i] - (p[i] - c[i]).clamp(-half[i], half[i]));
+                let g = self.inner.gradient(q);
+                std::array::from_fn(|i| g[i] * passes(i, half[i]))
+            }
+            MinkowskiKernel::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let z = p[2] - (p[2] - c[2]).clamp(-half_height, half_height);
+                let step = std::f32::consts::TAU / RIM_SAMPLES as f32;
+                let centre = [p[0], p[1], z];
+                let closest = (0..RIM_SAMPLES)
+                    .map(|i| {
+                        let (s, co) = (i as f32 * step).sin_cos();
+                        [p[0] - radius * co, p[1] - radius * s, z]
+                    })
+                    .fold((self.inner.eval(centre), centre), |best, q| {
+                        let v = self.inner.eval(q);
+                        if v < best.0 { (v, q) } else { best }
+                    });
+                let g = self.inner.gradient(closest.1);
+                [g[0], g[1], g[2] * passes(2, half_height)]
+            }
+        }
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        // The elongation x - clamp(x - c, -h, h) is monotone, so it maps the
+        // interval ends directly.
+        let elongate = |a: Interval, c: f32, h: f32| {
+            let g = |x: f32| x - (x - c).clamp(-h, h);
+            Interval::new(g(a.lo), g(a.hi))
+        };
+        let [x, y, z] = bbox.axes();
+        let c = self.centre;
+        match self.kernel {
+            MinkowskiKernel::Ball { radius } => {
+                (Interval::from(self.inner.eval_interval(bbox)) - radius).into()
+            }
+            MinkowskiKernel::Box { half } => {
+                let q = [
+                    elongate(x, c[0], half[0]),
+                    elongate(y, c[1], half[1]),
+                    elongate(z, c[2], half[2]),
+                ];
+                self.inner.eval_interval(&Bbox::from_axes(q))
+            }
+            MinkowskiKernel::Cylinder {
+                radius,
+                half_height,
+            } => {
+                let z = elongate(z, c[2], half_height);
+                let step = std::f32::consts::TAU / RIM_SAMPLES as f32;
+                let at = |dx: f32, dy: f32| {
+                    Interval::from(
+                        self.inner
+                            .eval_interval(&Bbox::from_axes([x - dx, y - dy, z])),
+                    )
+                };
+                (0..RIM_SAMPLES)
+                    .map(|i| {
+                        let (s, co) = (i as f32 * step).sin_cos();
+                        at(radius * co, radius * s)
+                    })
+                    .fold(at(0.0, 0.0), Interval::min)
+                    .into()
+            }
+        }
+    }
+    fn bbox(&self) -> Bbox {
+        let b = self.inner.bbox();
+        let grow = match self.kernel {
+            MinkowskiKernel::Ball { radius } => [radius; 3],
+            MinkowskiKernel::Box { half } => half,
+            MinkowskiKernel::Cylinder {
+                radius,
+                half_height,
+            } => [radius, radius, half_height],
+        };
+        Bbox {
+            min: std::array::from_fn(|i| b.min[i] - grow[i]),
+            max: std::array::from_fn(|i| b.max[i] + grow[i]),
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        match self.kernel {
+            MinkowskiKernel::Ball { radius } => hash_node(state, "MinkowskiBall", &[radius]),
+            MinkowskiKernel::Box { half } => hash_node(state, "MinkowskiBox", &half),
+            MinkowskiKernel::Cylinder {
+                radius,
+                half_height,
+            } => hash_node(state, "MinkowskiCylinder", &[radius, half_height]),
+        }
+        self.inner.hash_structure(state);
+    }
+    fn clone_box(&self) -> Box<dyn Primitive> {
+        Box::new(self.clone())
+    }
+}
@@ -1,15 +1,27 @@
+pub mod blobs;
 pub mod cone;
 pub mod csg;
 pub mod cylinder;
 pub mod deform;
+pub mod gear;
+pub mod marker;
+pub mod material;
+pub mod minkowski;
 pub mod planes;
 pub mod sphere;
 pub mod transforms;
+pub mod voxel;
 
+pub use blobs::Blobs;
 pub use cone::InfCone;
-pub use csg::{Difference, Intersection, Union};
+pub use csg::{Complement, Difference, Intersection, Union};
 pub use cylinder::InfCylinder;
 pub use deform::{Bender, Twister};
+pub use gear::Gear;
+pub use marker::Marker;
+pub use material::Material;
+pub use minkowski::{Minkowski, MinkowskiKernel};
 pub use planes::{NormalPlane, PlaneNegX, PlaneNegY, PlaneNegZ, PlaneX, PlaneY, PlaneZ};
 pub use sphere::Sphere;
-pub use transforms::{Rotate, Scale, Translate};
+pub use transforms::{Affine, Rotate, Scale, Translate};
+pub use voxel::VoxelField;
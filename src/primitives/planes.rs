@@ -1,4 +1,7 @@
-use crate::primitive::{Bbox, GlslCtx, Primitive};
+use crate::param::Scalar;
+use crate::primitive::{Bbox, GlslCtx, Primitive, hash_node};
+use crate::tape::{Op, Reg, Tape};
+use std::hash::Hasher;
 
 const INF: f32 = 1e10;
 
@@ -13,17 +16,19 @@ macro_rules! axis_plane {
         $name:ident,
         $glsl_expr:literal,       // e.g. "{p}.x"  or  "-{p}.x"
         $eval_fn:expr,            // closure |x,y,z| -> f32 (before subtracting d)
+        $axis:literal,            // axis the plane is normal to
+        $sign:literal,            // +1.0 for PlaneX, -1.0 for PlaneNegX
         $bbox_min:expr,           // closure |d| -> [f32;3]
         $bbox_max:expr            // closure |d| -> [f32;3]
     ) => {
         #[derive(Clone)]
         pub struct $name {
-            pub d: f32,
+            pub d: Scalar,
         }
 
         impl $name {
-            pub fn new(d: f32) -> Self {
-                $name { d }
+            pub fn new(d: impl Into<Scalar>) -> Self {
+                $name { d: d.into() }
             }
         }
 
@@ -31,18 +36,45 @@ macro_rules! axis_plane {
             fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
                 let v = ctx.fresh_float();
                 let expr = format!($glsl_expr, p = p);
-                ctx.push(format!("float {v} = {expr} - {:.8};", self.d));
+                let d = ctx.scalar(&self.d);
+                ctx.push(format!("float {v} = {expr} - {d};"));
                 v
             }
             fn eval(&self, [x, y, z]: [f32; 3]) -> f32 {
-                ($eval_fn)(x, y, z) - self.d
+                ($eval_fn)(x, y, z) - self.d.value
+            }
+            fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+                for (o, &[x, y, z]) in out.iter_mut().zip(points) {
+                    *o = ($eval_fn)(x, y, z) - self.d.value;
+                }
+            }
+            fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+                let v = tape.push(Op::MulImm(p[$axis], $sign));
+                tape.push(Op::AddImm(v, -self.d.value))
+            }
+            fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+                (bbox.axis($axis) * $sign - self.d.value).into()
+            }
+            fn gradient(&self, _p: [f32; 3]) -> [f32; 3] {
+                let mut g = [0.0; 3];
+                g[$axis] = $sign;
+                g
+            }
+            fn half_space(&self) -> Option<([f32; 3], f32)> {
+                let mut n = [0.0; 3];
+                n[$axis] = $sign;
+                Some((n, self.d.value))
             }
             fn bbox(&self) -> Bbox {
                 Bbox {
-                    min: ($bbox_min)(self.d),
-                    max: ($bbox_max)(self.d),
+                    min: ($bbox_min)(self.d.value),
+                    max: ($bbox_max)(self.d.value),
                 }
             }
+            fn hash_structure(&self, state: &mut dyn Hasher) {
+                hash_node(state, stringify!($name), &[]);
+                self.d.hash_into(state);
+            }
             fn clone_box(&self) -> Box<dyn Primitive> {
                 Box::new(self.clone())
             }
@@ -50,12 +82,60 @@ macro_rules! axis_plane {
     };
 }
 
-axis_plane!(PlaneX,    "{p}.x",  |x: f32, _y: f32, _z: f32| x,  |_d: f32| [-INF, -INF, -INF], |d: f32| [d, INF, INF]);
-axis_plane!(PlaneNegX, "-{p}.x", |x: f32, _y: f32, _z: f32| -x, |d: f32| [-d, -INF, -INF],    |_d: f32| [INF, INF, INF]);
-axis_plane!(PlaneY,    "{p}.y",  |_x: f32, y: f32, _z: f32| y,  |_d: f32| [-INF, -INF, -INF], |d: f32| [INF, d, INF]);
-axis_plane!(PlaneNegY, "-{p}.y", |_x: f32, y: f32, _z: f32| -y, |d: f32| [-INF, -d, -INF],    |_d: f32| [INF, INF, INF]);
-axis_plane!(PlaneZ,    "{p}.z",  |_x: f32, _y: f32, z: f32| z,  |_d: f32| [-INF, -INF, -INF], |d: f32| [INF, INF, d]);
-axis_plane!(PlaneNegZ, "-{p}.z", |_x: f32, _y: f32, z: f32| -z, |d: f32| [-INF, -INF, -d],    |_d: f32| [INF, INF, INF]);
+axis_plane!(
+    PlaneX,
+    "{p}.x",
+    |x: f32, _y: f32, _z: f32| x,
+    0,
+    1.0,
+    |_d: f32| [-INF, -INF, -INF],
+    |d: f32| [d, INF, INF]
+);
+axis_plane!(
+    PlaneNegX,
+    "-{p}.x",
+    |x: f32, _y: f32, _z: f32| -x,
+    0,
+    -1.0,
+    |d: f32| [-d, -INF, -INF],
+    |_d: f32| [INF, INF, INF]
+);
+axis_plane!(
+    PlaneY,
+    "{p}.y",
+    |_x: f32, y: f32, _z: f32| y,
+    1,
+    1.0,
+    |_d: f32| [-INF, -INF, -INF],
+    |d: f32| [INF, d, INF]
+);
+axis_plane!(
+    PlaneNegY,
+    "-{p}.y",
+    |_x: f32, y: f32, _z: f32| -y,
+    1,
+    -1.0,
+    |d: f32| [-INF, -d, -INF],
+    |_d: f32| [INF, INF, INF]
+);
+axis_plane!(
+    PlaneZ,
+    "{p}.z",
+    |_x: f32, _y: f32, z: f32| z,
+    2,
+    1.0,
+    |_d: f32| [-INF, -INF, -INF],
+    |d: f32| [INF, INF, d]
+);
+axis_plane!(
+    PlaneNegZ,
+    "-{p}.z",
+    |_x: f32, _y: f32, z: f32| -z,
+    2,
+    -1.0,
+    |d: f32| [-INF, -INF, -d],
+    |_d: f32| [INF, INF, INF]
+);
 
 // ── Arbitrary normal plane ────────────────────────────────────────────────────
 // Hessian form: SDF = dot(normal, p) - p_offset
@@ -97,8 +177,38 @@ impl Primitive for NormalPlane {
     fn eval(&self, [x, y, z]: [f32; 3]) -> f32 {
         self.normal[0] * x + self.normal[1] * y + self.normal[2] * z - self.p_offset
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let [nx, ny, nz] = self.normal;
+        for (o, &[x, y, z]) in out.iter_mut().zip(points) {
+            *o = nx * x + ny * y + nz * z - self.p_offset;
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let terms = [0, 1, 2].map(|i| tape.push(Op::MulImm(p[i], self.normal[i])));
+        let xy = tape.push(Op::Add(terms[0], terms[1]));
+        let xyz = tape.push(Op::Add(xy, terms[2]));
+        tape.push(Op::AddImm(xyz, -self.p_offset))
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let [x, y, z] = bbox.axes();
+        let [nx, ny, nz] = self.normal;
+        (x * nx + y * ny + z * nz - self.p_offset).into()
+    }
+    fn gradient(&self, _p: [f32; 3]) -> [f32; 3] {
+        self.normal
+    }
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        Some((self.normal, self.p_offset))
+    }
     fn bbox(&self) -> Bbox {
-        Bbox { min: [-INF, -INF, -INF], max: [INF, INF, INF] }
+        Bbox {
+            min: [-INF, -INF, -INF],
+            max: [INF, INF, INF],
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        let [nx, ny, nz] = self.normal;
+        hash_node(state, "NormalPlane", &[nx, ny, nz, self.p_offset]);
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
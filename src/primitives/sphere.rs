@@ -1,28 +1,57 @@
-use crate::primitive::{Bbox, GlslCtx, Primitive};
+use crate::interval::length;
+use crate::param::Scalar;
+use crate::primitive::{Bbox, GlslCtx, Primitive, hash_node, normalize};
+use crate::tape::{Op, Reg, Tape};
+use std::hash::Hasher;
 
 #[derive(Clone)]
 pub struct Sphere {
-    pub radius: f32,
+    pub radius: Scalar,
 }
 
 impl Sphere {
-    pub fn new(radius: f32) -> Self {
-        Sphere { radius }
+    pub fn new(radius: impl Into<Scalar>) -> Self {
+        Sphere {
+            radius: radius.into(),
+        }
     }
 }
 
 impl Primitive for Sphere {
     fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
         let d = ctx.fresh_float();
-        ctx.push(format!("float {d} = length({p}) - {:.8};", self.radius));
+        let r = ctx.scalar(&self.radius);
+        ctx.push(format!("float {d} = length({p}) - {r};"));
         d
     }
     fn eval(&self, [x, y, z]: [f32; 3]) -> f32 {
-        (x * x + y * y + z * z).sqrt() - self.radius
+        (x * x + y * y + z * z).sqrt() - self.radius.value
+    }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        for (o, &[x, y, z]) in out.iter_mut().zip(points) {
+            *o = (x * x + y * y + z * z).sqrt() - self.radius.value;
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let l = tape.length(&p);
+        tape.push(Op::AddImm(l, -self.radius.value))
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        (length(&bbox.axes()) - self.radius.value).into()
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        normalize(p)
     }
     fn bbox(&self) -> Bbox {
-        let r = self.radius;
-        Bbox { min: [-r, -r, -r], max: [r, r, r] }
+        let r = self.radius.value;
+        Bbox {
+            min: [-r, -r, -r],
+            max: [r, r, r],
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Sphere", &[]);
+        self.radius.hash_into(state);
     }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
@@ -1,5 +1,9 @@
-use crate::primitive::{Bbox, GlslCtx, Primitive};
+use crate::interval::Interval;
+use crate::param::Scalar;
+use crate::primitive::{Bbox, GlslCtx, Primitive, hash_node};
+use crate::tape::{Op, Reg, Tape};
 use nalgebra as na;
+use std::hash::Hasher;
 
 const INF: f32 = 1e10;
 
@@ -8,32 +12,89 @@ const INF: f32 = 1e10;
 #[derive(Clone)]
 pub struct Translate {
     inner: Box<dyn Primitive>,
-    pub t: [f32; 3],
+    pub t: [Scalar; 3],
 }
 
 impl Translate {
-    pub fn new(inner: Box<dyn Primitive>, t: [f32; 3]) -> Self {
-        Translate { inner, t }
+    pub fn new(inner: Box<dyn Primitive>, t: [impl Into<Scalar>; 3]) -> Self {
+        Translate {
+            inner,
+            t: t.map(Into::into),
+        }
+    }
+
+    fn offset(&self) -> [f32; 3] {
+        self.t.each_ref().map(|s| s.value)
     }
 }
 
 impl Primitive for Translate {
     fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
         let p1 = ctx.fresh_point();
-        let [tx, ty, tz] = self.t;
-        ctx.push(format!("vec3 {p1} = {p} - vec3({tx:.8}, {ty:.8}, {tz:.8});"));
-        self.inner.expression(&p1, ctx)
+        let [tx, ty, tz] = self.t.each_ref().map(|s| ctx.scalar(s));
+        ctx.push(format!("vec3 {p1} = {p} - vec3({tx}, {ty}, {tz});"));
+        ctx.child(self.inner.as_ref(), &p1)
     }
     fn eval(&self, [x, y, z]: [f32; 3]) -> f32 {
-        self.inner.eval([x - self.t[0], y - self.t[1], z - self.t[2]])
+        let [tx, ty, tz] = self.offset();
+        self.inner.eval([x - tx, y - ty, z - tz])
+    }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let [tx, ty, tz] = self.offset();
+        let q: Vec<[f32; 3]> = points
+            .iter()
+            .map(|&[x, y, z]| [x - tx, y - ty, z - tz])
+            .collect();
+        self.inner.eval_many(&q, out);
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let t = self.offset();
+        let q = [0, 1, 2].map(|i| tape.push(Op::AddImm(p[i], -t[i])));
+        self.inner.compile(q, tape)
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let [x, y, z] = bbox.axes();
+        let [tx, ty, tz] = self.offset();
+        self.inner
+            .eval_interval(&Bbox::from_axes([x - tx, y - ty, z - tz]))
+    }
+    fn gradient(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let [tx, ty, tz] = self.offset();
+        self.inner.gradient([x - tx, y - ty, z - tz])
     }
     fn bbox(&self) -> Bbox {
         let b = self.inner.bbox();
+        let t = self.offset();
+        Bbox {
+            min: std::array::from_fn(|i| b.min[i] + t[i]),
+            max: std::array::from_fn(|i| b.max[i] + t[i]),
+        }
+    }
+    fn bbox_within(&self, clip: &Bbox) -> Bbox {
+        let t = self.offset();
+        let local = Bbox {
+            min: std::array::from_fn(|i| clip.min[i] - t[i]),
+            max: std::array::from_fn(|i| clip.max[i] - t[i]),
+        };
+        let b = self.inner.bbox_within(&local);
         Bbox {
-            min: [b.min[0] + self.t[0], b.min[1] + self.t[1], b.min[2] + self.t[2]],
-            max: [b.max[0] + self.t[0], b.max[1] + self.t[1], b.max[2] + self.t[2]],
+            min: std::array::from_fn(|i| b.min[i] + t[i]),
+            max: std::array::from_fn(|i| b.max[i] + t[i]),
         }
     }
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        // n · (p - t) <= d  ⇔  n · p <= d + n · t
+        let (n, d) = self.inner.half_space()?;
+        let t = self.offset();
+        Some((n, d + n[0] * t[0] + n[1] * t[1] + n[2] * t[2]))
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Translate", &[]);
+        for s in &self.t {
+            s.hash_into(state);
+        }
+        self.inner.hash_structure(state);
+    }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
     }
@@ -96,11 +157,9 @@ impl Primitive for Rotate {
             {:.8},{:.8},{:.8}, \
             {:.8},{:.8},{:.8}, \
             {:.8},{:.8},{:.8}) * {p};",
-            m[0][0], m[1][0], m[2][0],
-            m[0][1], m[1][1], m[2][1],
-            m[0][2], m[1][2], m[2][2],
+            m[0][0], m[1][0], m[2][0], m[0][1], m[1][1], m[2][1], m[0][2], m[1][2], m[2][2],
         ));
-        self.inner.expression(&p1, ctx)
+        ctx.child(self.inner.as_ref(), &p1)
     }
     fn eval(&self, [x, y, z]: [f32; 3]) -> f32 {
         let m = &self.mat;
@@ -109,6 +168,47 @@ impl Primitive for Rotate {
         let pz = m[2][0] * x + m[2][1] * y + m[2][2] * z;
         self.inner.eval([px, py, pz])
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let m = &self.mat;
+        let q: Vec<[f32; 3]> = points
+            .iter()
+            .map(|&[x, y, z]| std::array::from_fn(|r| m[r][0] * x + m[r][1] * y + m[r][2] * z))
+            .collect();
+        self.inner.eval_many(&q, out);
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let m = self.mat;
+        let q = m.map(|row| {
+            let [x, y, z] = [0, 1, 2].map(|c| tape.push(Op::MulImm(p[c], row[c])));
+            let xy = tape.push(Op::Add(x, y));
+            tape.push(Op::Add(xy, z))
+        });
+        self.inner.compile(q, tape)
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let [x, y, z] = bbox.axes();
+        let m = &self.mat;
+        let rotated = std::array::from_fn(|i| x * m[i][0] + y * m[i][1] + z * m[i][2]);
+        self.inner.eval_interval(&Bbox::from_axes(rotated))
+    }
+    fn gradient(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let m = &self.mat;
+        let px = m[0][0] * x + m[0][1] * y + m[0][2] * z;
+        let py = m[1][0] * x + m[1][1] * y + m[1][2] * z;
+        let pz = m[2][0] * x + m[2][1] * y + m[2][2] * z;
+        let g = self.inner.gradient([px, py, pz]);
+        // Chain rule: the Jacobian is mat, so the gradient maps through its transpose.
+        std::array::from_fn(|i| m[0][i] * g[0] + m[1][i] * g[1] + m[2][i] * g[2])
+    }
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        // n · (mat p) = (matᵀ n) · p
+        let (n, d) = self.inner.half_space()?;
+        let m = &self.mat;
+        Some((
+            std::array::from_fn(|i| m[0][i] * n[0] + m[1][i] * n[1] + m[2][i] * n[2]),
+            d,
+        ))
+    }
     fn bbox(&self) -> Bbox {
         // Conservative: untransform the inner bbox corners and re-bound
         let b = self.inner.bbox();
@@ -145,6 +245,10 @@ impl Primitive for Rotate {
         }
         Bbox { min, max }
     }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Rotate", self.mat.as_flattened());
+        self.inner.hash_structure(state);
+    }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
     }
@@ -169,10 +273,12 @@ impl Primitive for Scale {
     fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
         let p1 = ctx.fresh_point();
         let [sx, sy, sz] = self.s;
-        ctx.push(format!("vec3 {p1} = {p} / vec3({sx:.8}, {sy:.8}, {sz:.8});"));
-        let d_inner = self.inner.expression(&p1, ctx);
-        let d = ctx.fresh_float();
+        ctx.push(format!(
+            "vec3 {p1} = {p} / vec3({sx:.8}, {sy:.8}, {sz:.8});"
+        ));
         let min_s = sx.min(sy).min(sz);
+        let d_inner = ctx.scaled(min_s, |ctx| ctx.child(self.inner.as_ref(), &p1));
+        let d = ctx.fresh_float();
         ctx.push(format!("float {d} = {d_inner} * {min_s:.8};"));
         d
     }
@@ -181,6 +287,44 @@ impl Primitive for Scale {
         let inner_d = self.inner.eval([x / sx, y / sy, z / sz]);
         inner_d * sx.min(sy).min(sz)
     }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let [sx, sy, sz] = self.s;
+        let q: Vec<[f32; 3]> = points
+            .iter()
+            .map(|&[x, y, z]| [x / sx, y / sy, z / sz])
+            .collect();
+        self.inner.eval_many(&q, out);
+        let min_s = sx.min(sy).min(sz);
+        for o in out.iter_mut() {
+            *o *= min_s;
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let q = [0, 1, 2].map(|i| tape.push(Op::MulImm(p[i], 1.0 / self.s[i])));
+        let d = self.inner.compile(q, tape);
+        let [sx, sy, sz] = self.s;
+        tape.push(Op::MulImm(d, sx.min(sy).min(sz)))
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let [x, y, z] = bbox.axes();
+        let [sx, sy, sz] = self.s;
+        let inner = self.inner.eval_interval(&Bbox::from_axes([
+            x * (1.0 / sx),
+            y * (1.0 / sy),
+            z * (1.0 / sz),
+        ]));
+        (Interval::from(inner) * sx.min(sy).min(sz)).into()
+    }
+    fn gradient(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let [sx, sy, sz] = self.s;
+        let g = self.inner.gradient([x / sx, y / sy, z / sz]);
+        let min_s = sx.min(sy).min(sz);
+        [g[0] * min_s / sx, g[1] * min_s / sy, g[2] * min_s / sz]
+    }
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        let (n, d) = self.inner.half_space()?;
+        Some(([n[0] / self.s[0], n[1] / self.s[1], n[2] / self.s[2]], d))
+    }
     fn bbox(&self) -> Bbox {
         let b = self.inner.bbox();
         let [sx, sy, sz] = self.s;
@@ -189,6 +333,160 @@ impl Primitive for Scale {
             max: [b.max[0] * sx, b.max[1] * sy, b.max[2] * sz],
         }
     }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Scale", &self.s);
+        self.inner.hash_structure(state);
+    }
+    fn clone_box(&self) -> Box<dyn Primitive> {
+        Box::new(self.clone())
+    }
+}
+
+// ── Affine ────────────────────────────────────────────────────────────────────
+// A general affine map p ↦ L p + t from a 4×4 matrix. Evaluation maps p back
+// through the inverse, then multiplies by the smallest singular value of L so
+// the result stays a lower bound on the distance under stretch and shear.
+
+#[derive(Clone)]
+pub struct Affine {
+    inner: Box<dyn Primitive>,
+    forward: [[f32; 4]; 3], // rows of [L | t]
+    inverse: [[f32; 4]; 3], // rows of [L⁻¹ | -L⁻¹ t]
+    scale: f32,
+}
+
+impl Affine {
+    /// None unless `m` is an invertible affine map (last row 0, 0, 0, 1).
+    pub fn new(inner: Box<dyn Primitive>, m: na::Matrix4<f64>) -> Option<Self> {
+        if m.fixed_view::<1, 4>(3, 0) != na::RowVector4::new(0.0, 0.0, 0.0, 1.0) {
+            return None;
+        }
+        let l = m.fixed_view::<3, 3>(0, 0).into_owned();
+        let t = m.fixed_view::<3, 1>(0, 3).into_owned();
+        let scale = l.svd(false, false).singular_values.min();
+        if scale.is_nan() || scale <= 1e-9 {
+            return None;
+        }
+        let inv = l.try_inverse()?;
+        let offset = -(inv * t);
+        let rows = |l: &na::Matrix3<f64>, t: &na::Vector3<f64>| {
+            std::array::from_fn(|r| {
+                [
+                    l[(r, 0)] as f32,
+                    l[(r, 1)] as f32,
+                    l[(r, 2)] as f32,
+                    t[r] as f32,
+                ]
+            })
+        };
+        Some(Affine {
+            inner,
+            forward: rows(&l, &t),
+            inverse: rows(&inv, &offset),
+            scale: scale as f32,
+        })
+    }
+
+    fn local(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        self.inverse.map(|m| m[0] * x + m[1] * y + m[2] * z + m[3])
+    }
+}
+
+impl Primitive for Affine {
+    fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
+        let p1 = ctx.fresh_point();
+        let m = &self.inverse;
+        ctx.push(format!(
+            "vec3 {p1} = mat3(\
+            {:.8},{:.8},{:.8}, \
+            {:.8},{:.8},{:.8}, \
+            {:.8},{:.8},{:.8}) * {p} + vec3({:.8},{:.8},{:.8});",
+            m[0][0],
+            m[1][0],
+            m[2][0],
+            m[0][1],
+            m[1][1],
+            m[2][1],
+            m[0][2],
+            m[1][2],
+            m[2][2],
+            m[0][3],
+            m[1][3],
+            m[2][3],
+        ));
+        let d_inner = ctx.scaled(self.scale, |ctx| ctx.child(self.inner.as_ref(), &p1));
+        let d = ctx.fresh_float();
+        ctx.push(format!("float {d} = {d_inner} * {:.8};", self.scale));
+        d
+    }
+    fn eval(&self, p: [f32; 3]) -> f32 {
+        self.inner.eval(self.local(p)) * self.scale
+    }
+    fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        let q: Vec<[f32; 3]> = points.iter().map(|&p| self.local(p)).collect();
+        self.inner.eval_many(&q, out);
+        for o in out.iter_mut() {
+            *o *= self.scale;
+        }
+    }
+    fn compile(&self, p: [Reg; 3], tape: &mut Tape) -> Reg {
+        let q = self.inverse.map(|row| {
+            let [x, y, z] = [0, 1, 2].map(|c| tape.push(Op::MulImm(p[c], row[c])));
+            let xy = tape.push(Op::Add(x, y));
+            let xyz = tape.push(Op::Add(xy, z));
+            tape.push(Op::AddImm(xyz, row[3]))
+        });
+        let d = self.inner.compile(q, tape);
+        tape.push(Op::MulImm(d, self.scale))
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        let [x, y, z] = bbox.axes();
+        let local = self.inverse.map(|m| x * m[0] + y * m[1] + z * m[2] + m[3]);
+        let inner = self.inner.eval_interval(&Bbox::from_axes(local));
+        (Interval::from(inner) * self.scale).into()
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        let g = self.inner.gradient(self.local(p));
+        // The Jacobian is L⁻¹, so the gradient maps through its transpose.
+        let m = &self.inverse;
+        std::array::from_fn(|i| (m[0][i] * g[0] + m[1][i] * g[1] + m[2][i] * g[2]) * self.scale)
+    }
+    fn half_space(&self) -> Option<([f32; 3], f32)> {
+        // n · (L⁻¹ p + o) <= d  ⇔  (L⁻ᵀ n) · p <= d - n · o
+        let (n, d) = self.inner.half_space()?;
+        let m = &self.inverse;
+        let normal = std::array::from_fn(|i| m[0][i] * n[0] + m[1][i] * n[1] + m[2][i] * n[2]);
+        Some((
+            normal,
+            d - (n[0] * m[0][3] + n[1] * m[1][3] + n[2] * m[2][3]),
+        ))
+    }
+    fn bbox(&self) -> Bbox {
+        // Conservative: map the inner bbox corners forward and re-bound
+        let b = self.inner.bbox();
+        let mut min = [INF; 3];
+        let mut max = [-INF; 3];
+        for corner in 0..8 {
+            let c: [f32; 3] = std::array::from_fn(|i| {
+                if corner >> i & 1 == 0 {
+                    b.min[i]
+                } else {
+                    b.max[i]
+                }
+            });
+            for (i, m) in self.forward.iter().enumerate() {
+                let v = m[0] * c[0] + m[1] * c[1] + m[2] * c[2] + m[3];
+                min[i] = min[i].min(v);
+                max[i] = max[i].max(v);
+            }
+        }
+        Bbox { min, max }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        hash_node(state, "Affine", self.inverse.as_flattened());
+        state.write_u32(self.scale.to_bits());
+        self.inner.hash_structure(state);
+    }
     fn clone_box(&self) -> Box<dyn Primitive> {
         Box::new(self.clone())
     }
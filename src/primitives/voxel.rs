@@ -0,0 +1,468 @@
+use crate::interval::{Interval, length};
+use crate::primitive::{Bbox, GlslCtx, Primitive, ShaderData, hash_node};
+use crate::tape::Tape;
+use std::hash::Hasher;
+
+// ── Voxel field ───────────────────────────────────────────────────────────────
+// A regular grid of signed distance samples, evaluated by trilinear
+// interpolation. Voxel (i, j, k) sits at origin + (i, j, k) * voxel_size.
+// Outside the grid the distance to the grid box is added, so the field stays a
+// lower bound far away.
+//
+// Sparse storage splits the grid into BRICK³ bricks; bricks that lie entirely
+// further from the surface than their own diagonal are collapsed to a single
+// value (the smallest magnitude they contained).
+//
+// Binary format (little endian):
+//   b"TSVX", version: u8 = 1, storage: u8 (0 dense, 1 sparse),
+//   dims: 3 × u32, origin: 3 × f32, voxel_size: f32, then
+//   dense:  dims[0]·dims[1]·dims[2] × f32, x fastest
+//   sparse: per brick (x fastest): tag u8 (0 uniform, 1 data) followed by
+//           one f32 or BRICK³ × f32
+
+pub const BRICK: usize = 8;
+/// Samples per axis at most: the 3D texture size WebGL2 guarantees.
+pub const MAX_SAMPLES: usize = 256;
+/// Voxels along the longest axis at most, leaving room for the padding.
+pub const MAX_RESOLUTION: usize = MAX_SAMPLES - 2 * PADDING - 2;
+
+const MAGIC: &[u8; 4] = b"TSVX";
+const VERSION: u8 = 1;
+const PADDING: usize = 2;
+
+#[derive(Clone)]
+pub enum Brick {
+    Uniform(f32),
+    Data(Vec<f32>),
+}
+
+#[derive(Clone)]
+pub enum VoxelStorage {
+    Dense(Vec<f32>),
+    Sparse(Vec<Brick>),
+}
+
+#[derive(Clone)]
+pub struct VoxelField {
+    pub origin: [f32; 3],
+    pub voxel_size: f32,
+    pub dims: [usize; 3],
+    storage: VoxelStorage,
+}
+
+fn brick_dims(dims: [usize; 3]) -> [usize; 3] {
+    dims.map(|n| n.div_ceil(BRICK))
+}
+
+impl VoxelField {
+    /// Sample `obj` over its bbox with `resolution` voxels along the longest axis.
+    pub fn from_primitive(
+        obj: &dyn Primitive,
+        resolution: usize,
+        sparse: bool,
+    ) -> Result<Self, String> {
+        if !(1..=MAX_RESOLUTION).contains(&resolution) {
+            return Err(format!(
+                "voxel resolution must be between 1 and {MAX_RESOLUTION}"
+            ));
+        }
+        let b = obj
+            .bbox()
+            .finite()
+            .map_err(|e| format!("cannot voxelize: {e}"))?;
+        let voxel_size = b.width() / resolution as f32;
+        if voxel_size <= 0.0 {
+            return Err("cannot voxelize an empty object".to_string());
+        }
+        let pad = PADDING as f32 * voxel_size;
+        let origin = b.min.map(|v| v - pad);
+        let dims: [usize; 3] = std::array::from_fn(|i| {
+            ((b.max[i] - b.min[i]) / voxel_size).ceil() as usize + 1 + 2 * PADDING
+        });
+        // Sample brick by brick on the tape pruned to each brick's box.
+        let tape = Tape::compile(obj);
+        let bd = brick_dims(dims);
+        let mut rows = Vec::new();
+        let mut sample_brick = |bx: usize, by: usize, bz: usize| {
+            let corner = [bx, by, bz].map(|b| b * BRICK);
+            let at = |i: usize, n: usize| origin[i] + (corner[i] + n) as f32 * voxel_size;
+            let bounds = Bbox {
+                min: std::array::from_fn(|i| at(i, 0)),
+                max: std::array::from_fn(|i| at(i, BRICK - 1)),
+            };
+            let (brick_tape, _) = tape.prune(&bounds.expand(1e-3 * voxel_size));
+            let mut points = Vec::with_capacity(BRICK * BRICK * BRICK);
+            for z in 0..BRICK {
+                for y in 0..BRICK {
+                    for x in 0..BRICK {
+                        points.push([at(0, x), at(1, y), at(2, z)]);
+                    }
+                }
+            }
+            let mut data = vec![0.0; points.len()];
+            brick_tape.eval_many_with(&points, &mut data, &mut rows);
+            // Voxels past the end of the grid stay zero.
+            for (n, v) in data.iter_mut().enumerate() {
+                let idx = [n % BRICK, n / BRICK % BRICK, n / (BRICK * BRICK)];
+                if (0..3).any(|i| corner[i] + idx[i] >= dims[i]) {
+                    *v = 0.0;
+                }
+            }
+            data
+        };
+        let storage = if sparse {
+            let far = BRICK as f32 * voxel_size * 3f32.sqrt();
+            let mut bricks = Vec::with_capacity(bd[0] * bd[1] * bd[2]);
+            for bz in 0..bd[2] {
+                for by in 0..bd[1] {
+                    for bx in 0..bd[0] {
+                        let data = sample_brick(bx, by, bz);
+                        let nearest = data
+                            .iter()
+                            .copied()
+                            .reduce(|a, b| if b.abs() < a.abs() { b } else { a })
+                            .unwrap();
+                        let uniform = data
+                            .iter()
+                            .all(|v| v.abs() > far && v.signum() == nearest.signum());
+                        bricks.push(if uniform {
+                            Brick::Uniform(nearest)
+                        } else {
+                            Brick::Data(data)
+                        });
+                    }
+                }
+            }
+            VoxelStorage::Sparse(bricks)
+        } else {
+            let mut data = vec![0.0; dims[0] * dims[1] * dims[2]];
+            for bz in 0..bd[2] {
+                for by in 0..bd[1] {
+                    for bx in 0..bd[0] {
+                        let brick = sample_brick(bx, by, bz);
+                        for (n, &v) in brick.iter().enumerate() {
+                            let (i, j, k) = (
+                                bx * BRICK + n % BRICK,
+                                by * BRICK + n / BRICK % BRICK,
+                                bz * BRICK + n / (BRICK * BRICK),
+                            );
+                            if i < dims[0] && j < dims[1] && k < dims[2] {
+                                data[(k * dims[1] + j) * dims[0] + i] = v;
+                            }
+                        }
+                    }
+                }
+            }
+            VoxelStorage::Dense(data)
+        };
+        Ok(VoxelField {
+            origin,
+            voxel_size,
+            dims,
+            storage,
+        })
+    }
+
+    fn sample(&self, i: usize, j: usize, k: usize) -> f32 {
+        match &self.storage {
+            VoxelStorage::Dense(data) => data[(k * self.dims[1] + j) * self.dims[0] + i],
+            VoxelStorage::Sparse(bricks) => {
+                let bd = brick_dims(self.dims);
+                let brick = &bricks[((k / BRICK) * bd[1] + j / BRICK) * bd[0] + i / BRICK];
+                match brick {
+                    Brick::Uniform(v) => *v,
+                    Brick::Data(data) => {
+                        data[((k % BRICK) * BRICK + j % BRICK) * BRICK + i % BRICK]
+                    }
+                }
+            }
+        }
+    }
+
+    /// Trilinear value and gradient at a point inside the grid box.
+    fn trilinear(&self, q: [f32; 3]) -> (f32, [f32; 3]) {
+        let u: [f32; 3] = std::array::from_fn(|i| (q[i] - self.origin[i]) / self.voxel_size);
+        let i0: [usize; 3] = std::array::from_fn(|i| (u[i].floor() as usize).min(self.dims[i] - 2));
+        let t: [f32; 3] = std::array::from_fn(|i| u[i] - i0[i] as f32);
+        let s = |dx: usize, dy: usize, dz: usize| self.sample(i0[0] + dx, i0[1] + dy, i0[2] + dz);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let c = [[s(0, 0, 0), s(1, 0, 0)], [s(0, 1, 0), s(1, 1, 0)]];
+        let c1 = [[s(0, 0, 1), s(1, 0, 1)], [s(0, 1, 1), s(1, 1, 1)]];
+        let c00 = lerp(c[0][0], c[0][1], t[0]);
+        let c10 = lerp(c[1][0], c[1][1], t[0]);
+        let c01 = lerp(c1[0][0], c1[0][1], t[0]);
+        let c11 = lerp(c1[1][0], c1[1][1], t[0]);
+        let value = lerp(lerp(c00, c10, t[1]), lerp(c01, c11, t[1]), t[2]);
+        let dx = lerp(
+            lerp(c[0][1] - c[0][0], c[1][1] - c[1][0], t[1]),
+            lerp(c1[0][1] - c1[0][0], c1[1][1] - c1[1][0], t[1]),
+            t[2],
+        );
+        let dy = lerp(c10 - c00, c11 - c01, t[2]);
+        let dz = lerp(c01, c11, t[1]) - lerp(c00, c10, t[1]);
+        (value, [dx, dy, dz].map(|d| d / self.voxel_size))
+    }
+
+    fn max_corner(&self) -> [f32; 3] {
+        std::array::from_fn(|i| self.origin[i] + (self.dims[i] - 1) as f32 * self.voxel_size)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(match self.storage {
+            VoxelStorage::Dense(_) => 0,
+            VoxelStorage::Sparse(_) => 1,
+        });
+        for n in self.dims {
+            out.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+        for v in self.origin {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        out.extend_from_slice(&self.voxel_size.to_le_bytes());
+        let push_all = |out: &mut Vec<u8>, data: &[f32]| {
+            for v in data {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        };
+        match &self.storage {
+            VoxelStorage::Dense(data) => push_all(&mut out, data),
+            VoxelStorage::Sparse(bricks) => {
+                for brick in bricks {
+                    match brick {
+                        Brick::Uniform(v) => {
+                            out.push(0);
+                            out.extend_from_slice(&v.to_le_bytes());
+                        }
+                        Brick::Data(data) => {
+                            out.push(1);
+                            push_all(&mut out, data);
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut r = Reader { rest: bytes };
+        if r.take(4)? != MAGIC {
+            return Err("not a voxel file (bad magic)".to_string());
+        }
+        if r.u8()? != VERSION {
+            return Err("unsupported voxel file version".to_string());
+        }
+        let sparse = match r.u8()? {
+            0 => false,
+            1 => true,
+            _ => return Err("unknown voxel storage type".to_string()),
+        };
+        let mut dims = [0usize; 3];
+        for n in &mut dims {
+            *n = r.u32()? as usize;
+            if *n < 2 {
+                return Err("voxel grid needs at least 2 samples per axis".to_string());
+            }
+        }
+        // The sizes come from untrusted bytes: bound them before allocating.
+        let samples = dims
+            .iter()
+            .try_fold(1usize, |total, &n| total.checked_mul(n));
+        if dims.iter().any(|&n| n > MAX_SAMPLES) || samples.is_none() {
+            return Err(format!("voxel grid exceeds {MAX_SAMPLES} samples per axis"));
+        }
+        let origin = [r.f32()?, r.f32()?, r.f32()?];
+        let voxel_size = r.f32()?;
+        if voxel_size.is_nan() || voxel_size <= 0.0 {
+            return Err("voxel size must be positive".to_string());
+        }
+        let storage = if sparse {
+            let bd = brick_dims(dims);
+            let mut bricks = Vec::new();
+            for _ in 0..bd[0] * bd[1] * bd[2] {
+                bricks.push(match r.u8()? {
+                    0 => Brick::Uniform(r.f32()?),
+                    1 => Brick::Data(r.f32s(BRICK * BRICK * BRICK)?),
+                    _ => return Err("unknown voxel brick type".to_string()),
+                });
+            }
+            VoxelStorage::Sparse(bricks)
+        } else {
+            VoxelStorage::Dense(r.f32s(samples.unwrap())?)
+        };
+        Ok(VoxelField {
+            origin,
+            voxel_size,
+            dims,
+            storage,
+        })
+    }
+}
+
+struct Reader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.rest.len() < n {
+            return Err("truncated voxel data".to_string());
+        }
+        let (head, tail) = self.rest.split_at(n);
+        self.rest = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32s(&mut self, n: usize) -> Result<Vec<f32>, String> {
+        (0..n).map(|_| self.f32()).collect()
+    }
+}
+
+impl Primitive for VoxelField {
+    fn expression(&self, p: &str, ctx: &mut GlslCtx) -> String {
+        // The GPU gets the grid expanded to a dense 3D texture, which it reads
+        // texel by texel: float textures cannot be filtered.
+        let [nx, ny, nz] = self.dims;
+        let mut values = Vec::with_capacity(nx * ny * nz);
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    values.push(self.sample(i, j, k));
+                }
+            }
+        }
+        let n = ctx.upload(
+            "voxels",
+            ShaderData::Texture3d {
+                dims: self.dims,
+                values,
+            },
+        );
+        let fetch =
+            |x: u8, y: u8, z: u8| format!("texelFetch(voxels{n}, i + ivec3({x}, {y}, {z}), 0).r");
+        ctx.add_helper(&format!(
+            "float sdVoxels{n}(vec3 p, vec3 lo, vec3 hi) {{
+    const ivec3 n = ivec3({nx}, {ny}, {nz});
+    vec3 q = clamp(p, lo, hi);
+    vec3 u = (q - lo) / (hi - lo) * vec3(n - 1);
+    ivec3 i = min(ivec3(floor(u)), n - 2);
+    vec3 t = u - vec3(i);
+    float c00 = mix({}, {}, t.x);
+    float c10 = mix({}, {}, t.x);
+    float c01 = mix({}, {}, t.x);
+    float c11 = mix({}, {}, t.x);
+    return mix(mix(c00, c10, t.y), mix(c01, c11, t.y), t.z) + length(p - q);
+}}",
+            fetch(0, 0, 0),
+            fetch(1, 0, 0),
+            fetch(0, 1, 0),
+            fetch(1, 1, 0),
+            fetch(0, 0, 1),
+            fetch(1, 0, 1),
+            fetch(0, 1, 1),
+            fetch(1, 1, 1),
+        ));
+        let [ox, oy, oz] = self.origin;
+        let [mx, my, mz] = self.max_corner();
+        let d = ctx.fresh_float();
+        ctx.push(format!(
+            "float {d} = sdVoxels{n}({p}, vec3({ox:.8}, {oy:.8}, {oz:.8}), vec3({mx:.8}, {my:.8}, {mz:.8}));"
+        ));
+        d
+    }
+    fn eval(&self, p: [f32; 3]) -> f32 {
+        let hi = self.max_corner();
+        let q: [f32; 3] = std::array::from_fn(|i| p[i].clamp(self.origin[i], hi[i]));
+        let outside: f32 = (0..3)
+            .map(|i| (p[i] - q[i]) * (p[i] - q[i]))
+            .sum::<f32>()
+            .sqrt();
+        self.trilinear(q).0 + outside
+    }
+    fn gradient(&self, p: [f32; 3]) -> [f32; 3] {
+        let hi = self.max_corner();
+        let q: [f32; 3] = std::array::from_fn(|i| p[i].clamp(self.origin[i], hi[i]));
+        let (_, g) = self.trilinear(q);
+        let out = [p[0] - q[0], p[1] - q[1], p[2] - q[2]];
+        let len = (out[0] * out[0] + out[1] * out[1] + out[2] * out[2]).sqrt();
+        // Clamped axes contribute through the outside distance only.
+        std::array::from_fn(|i| if out[i] != 0.0 { out[i] / len } else { g[i] })
+    }
+    fn eval_interval(&self, bbox: &Bbox) -> (f32, f32) {
+        // Trilinear values are convex combinations of the voxels covering the
+        // clamped box; the outside term is the distance to the grid box, which
+        // is smallest at the nearest point and largest at a corner.
+        let hi = self.max_corner();
+        let mut range = [0usize; 6];
+        let mut outside = [Interval::point(0.0); 3];
+        for i in 0..3 {
+            let lo = bbox.min[i].clamp(self.origin[i], hi[i]);
+            let up = bbox.max[i].clamp(self.origin[i], hi[i]);
+            range[2 * i] = ((lo - self.origin[i]) / self.voxel_size).floor() as usize;
+            range[2 * i + 1] =
+                (((up - self.origin[i]) / self.voxel_size).ceil() as usize).min(self.dims[i] - 1);
+            let below = Interval::new(self.origin[i], self.origin[i]) - bbox.axis(i);
+            let above = bbox.axis(i) - hi[i];
+            outside[i] = Interval::new(
+                below.lo.max(above.lo).max(0.0),
+                below.hi.max(above.hi).max(0.0),
+            );
+        }
+        let (mut vmin, mut vmax) = (f32::INFINITY, f32::NEG_INFINITY);
+        for k in range[4]..=range[5] {
+            for j in range[2]..=range[3] {
+                for i in range[0]..=range[1] {
+                    let v = self.sample(i, j, k);
+                    vmin = vmin.min(v);
+                    vmax = vmax.max(v);
+                }
+            }
+        }
+        let out = length(&outside);
+        (vmin + out.lo, vmax + out.hi)
+    }
+    fn bbox(&self) -> Bbox {
+        Bbox {
+            min: self.origin,
+            max: self.max_corner(),
+        }
+    }
+    fn hash_structure(&self, state: &mut dyn Hasher) {
+        let [ox, oy, oz] = self.origin;
+        let [nx, ny, nz] = self.dims.map(|n| n as f32);
+        hash_node(
+            state,
+            "VoxelField",
+            &[ox, oy, oz, self.voxel_size, nx, ny, nz],
+        );
+        match &self.storage {
+            VoxelStorage::Dense(values) => hash_node(state, "dense", values),
+            VoxelStorage::Sparse(bricks) => {
+                for brick in bricks {
+                    match brick {
+                        Brick::Uniform(v) => hash_node(state, "uniform", &[*v]),
+                        Brick::Data(values) => hash_node(state, "data", values),
+                    }
+                }
+            }
+        }
+    }
+    fn clone_box(&self) -> Box<dyn Primitive> {
+        Box::new(self.clone())
+    }
+}
@@ -1,12 +1,13 @@
 // calcNormal, softShadow, main — uniforms are declared in shader.rs above map()
+// and mapMaterial()
 
 vec3 calcNormal(vec3 p) {
-    float e = 0.0001;
-    return normalize(vec3(
-        map(p + vec3(e, 0.0, 0.0)) - map(p - vec3(e, 0.0, 0.0)),
-        map(p + vec3(0.0, e, 0.0)) - map(p - vec3(0.0, e, 0.0)),
-        map(p + vec3(0.0, 0.0, e)) - map(p - vec3(0.0, 0.0, e))
-    ));
+    // Tetrahedral differences: four samples instead of six. Scale the step
+    // with the scene so tiny and huge models both shade cleanly.
+    float e = 2.5e-6 * iCameraZ;
+    vec2 k = vec2(1.0, -1.0);
+    return normalize(k.xyy * map(p + k.xyy * e) + k.yyx * map(p + k.yyx * e) +
+                     k.yxy * map(p + k.yxy * e) + k.xxx * map(p + k.xxx * e));
 }
 
 float softShadow(vec3 ro, vec3 rd, float mint, float maxt, float k) {
@@ -22,6 +23,22 @@ float softShadow(vec3 ro, vec3 rd, float mint, float maxt, float k) {
     return clamp(res, 0.0, 1.0);
 }
 
+#ifdef OVERLAY
+// Debug and ghost subtrees, from mapOverlay(): x is the distance to the debug
+// ones, y to the ghost ones.
+float overlay(vec3 p) {
+    vec2 o = mapOverlay(p);
+    return min(o.x, o.y);
+}
+
+vec3 overlayNormal(vec3 p) {
+    float e = 2.5e-6 * iCameraZ;
+    vec2 k = vec2(1.0, -1.0);
+    return normalize(k.xyy * overlay(p + k.xyy * e) + k.yyx * overlay(p + k.yyx * e) +
+                     k.yxy * overlay(p + k.yxy * e) + k.xxx * overlay(p + k.xxx * e));
+}
+#endif
+
 void main() {
     vec2 uv = (gl_FragCoord.xy - 0.5 * iResolution) / min(iResolution.x, iResolution.y);
 
@@ -51,9 +68,35 @@ void main() {
         float amb  = 0.5 + 0.5 * n.y;
         float sha  = softShadow(pos + n * 0.002, ld, 0.01, iCameraZ * 2.0, 16.0);
 
-        col = vec3(0.7) * (diff * sha + 0.3 * amb);
+        vec4 mat  = mapMaterial(pos);
+        float spe = pow(clamp(dot(reflect(-ld, n), -rd), 0.0, 1.0), 32.0);
+
+        col = mat.rgb * (diff * sha + 0.3 * amb) + mat.a * spe * sha;
         col = pow(col, vec3(0.4545)); // gamma
     }
 
+#ifdef OVERLAY
+    // Marked subtrees are drawn translucent over the scene, fainter where the
+    // scene hides them, so subtracted ones show too.
+    float to = 0.0;
+    bool ohit = false;
+    for (int i = 0; i < 128; i++) {
+        float h = overlay(ro + rd * to);
+        if (h < 0.0002 * to) { ohit = true; break; }
+        if (to > tmax) break;
+        to += h;
+    }
+    if (ohit) {
+        vec3 opos = ro + rd * to;
+        vec2 o = mapOverlay(opos);
+        bool isDebug = o.x <= o.y;
+        vec3 tint = isDebug ? vec3(1.0, 0.35, 0.55) : vec3(0.8);
+        float alpha = isDebug ? 0.5 : 0.25;
+        if (hit && to > t * 1.001) alpha *= 0.5;
+        float lit = abs(dot(overlayNormal(opos), normalize(vec3(1.0, 2.0, 1.5))));
+        col = mix(col, tint * (0.6 + 0.4 * lit), alpha);
+    }
+#endif
+
     gl_FragColor = vec4(col, 1.0);
 }
@@ -1,4 +1,4 @@
-use crate::primitive::{GlslCtx, Primitive};
+use crate::primitive::{DEFAULT_MATERIAL, GlslCtx, Mark, Primitive, ShaderData};
 
 const RENDERER_TEMPLATE: &str = include_str!("renderer.glsl");
 
@@ -8,27 +8,122 @@ uniform vec2  iResolution;\n\
 uniform mat4  iWorldTransform;\n\
 uniform float iCameraZ;";
 
+/// A fragment shader and the data the host uploads for it.
+pub struct Shader {
+    pub source: String,
+    /// The data uniforms the source declares, by name.
+    pub data: Vec<(String, ShaderData)>,
+}
+
 pub fn build_fragment_shader(obj: &dyn Primitive) -> String {
+    build_shader(obj).source
+}
+
+pub fn build_shader(obj: &dyn Primitive) -> Shader {
     let mut ctx = GlslCtx::new();
-    let result = obj.expression("p", &mut ctx);
+    ctx.count_uses(obj);
+
+    // Scenes without materials and markers need the distance alone. The others
+    // are emitted once, tracking them, into mapScene(), which map(),
+    // mapMaterial() and mapOverlay() call for the part they return.
+    let scene_fns = if ctx.has_materials() || ctx.has_markers() {
+        let annotations = ctx.annotate(obj, "p");
+        let mut stmts = indent(std::mem::take(&mut ctx.statements));
+        let marked = |mark| {
+            annotations
+                .marked
+                .iter()
+                .filter(move |(m, _, _)| *m == mark)
+        };
 
+        // With `only` marks, the scene is the marked subtrees alone.
+        let only: Vec<_> = marked(Mark::Only).collect();
+        let (mut d, mut m) = (annotations.distance.clone(), annotations.material.clone());
+        if let Some((_, d0, m0)) = only.first() {
+            (d, m) = (d0.clone(), m0.clone());
+            for (i, (_, dn, mn)) in only.iter().enumerate().skip(1) {
+                stmts.push_str(&format!("    vec4 only_m{i} = {d} <= {dn} ? {m} : {mn};\n"));
+                stmts.push_str(&format!("    float only_d{i} = min({d}, {dn});\n"));
+                (d, m) = (format!("only_d{i}"), format!("only_m{i}"));
+            }
+        }
+
+        // The overlay holds the distances to the debug and the ghost subtrees.
+        let nearest = |mark| {
+            marked(mark).fold("1e10".to_string(), |acc, (_, d, _)| {
+                format!("min({acc}, {d})")
+            })
+        };
+        let overlay = marked(Mark::Debug)
+            .chain(marked(Mark::Ghost))
+            .next()
+            .is_some();
+        stmts.push_str(&format!("    material = {m};\n"));
+        stmts.push_str(&format!(
+            "    overlay = vec2({}, {});\n",
+            nearest(Mark::Debug),
+            nearest(Mark::Ghost)
+        ));
+        let scene = function(
+            "float mapScene",
+            ", out vec4 material, out vec2 overlay",
+            &stmts,
+            &d,
+        );
+        let part = |signature: &str, result: &str| {
+            format!(
+                "{signature}(vec3 p) {{\n    vec4 m;\n    vec2 o;\n    float d = mapScene(p, m, o);\n    return {result};\n}}"
+            )
+        };
+        let overlay_fn = if overlay {
+            format!("#define OVERLAY\n{}\n\n", part("vec2 mapOverlay", "o"))
+        } else {
+            String::new()
+        };
+        format!(
+            "{scene}\n\n{}\n\n{}\n\n{overlay_fn}",
+            part("float map", "d"),
+            part("vec4 mapMaterial", "m")
+        )
+    } else {
+        let result = ctx.child(obj, "p");
+        let stmts = indent(std::mem::take(&mut ctx.statements));
+        format!(
+            "{}\n\n{}\n\n",
+            function("float map", "", &stmts, &result),
+            function("vec4 mapMaterial", "", "", DEFAULT_MATERIAL)
+        )
+    };
+
+    let params = ctx.uniform_declarations();
     let helpers = ctx.helpers.join("\n\n");
+    let functions = ctx.functions.join("\n\n");
 
-    let stmts: String = ctx
-        .statements
-        .iter()
-        .map(|s| format!("    {s}\n"))
-        .collect();
+    // Order: uniforms → parameters → helpers → shared subtrees → mapScene() →
+    // map() → mapMaterial() → mapOverlay() → renderer body (calcNormal, main, …)
+    let source =
+        format!("{UNIFORMS}\n{params}\n{helpers}\n\n{functions}\n\n{scene_fns}{RENDERER_TEMPLATE}");
+    Shader {
+        source,
+        data: ctx.data,
+    }
+}
 
-    // map() transforms p by the world matrix first, then evaluates the SDF.
-    let map_fn = format!(
-        "float map(vec3 p) {{\n\
+/// A function of the point `p` and `params` returning `result` after
+/// `stmts`, which see p transformed by the world matrix.
+fn function(signature: &str, params: &str, stmts: &str, result: &str) -> String {
+    if stmts.is_empty() {
+        return format!("{signature}(vec3 p{params}) {{\n    return {result};\n}}");
+    }
+    format!(
+        "{signature}(vec3 p{params}) {{\n\
              p = (iWorldTransform * vec4(p, 1.0)).xyz;\n\
          {stmts}\
          \n    return {result};\n\
          }}"
-    );
+    )
+}
 
-    // Order: uniforms → helpers → map() → renderer body (calcNormal, main, …)
-    format!("{UNIFORMS}\n\n{helpers}\n\n{map_fn}\n\n{RENDERER_TEMPLATE}")
+fn indent(statements: Vec<String>) -> String {
+    statements.iter().map(|s| format!("    {s}\n")).collect()
 }
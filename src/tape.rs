@@ -0,0 +1,392 @@
+use crate::interval::Interval;
+use crate::primitive::{Bbox, Primitive};
+use crate::primitives::csg::{cpu_smax, cpu_smin};
+use std::sync::OnceLock;
+
+// ── Evaluation tape ───────────────────────────────────────────────────────────
+// A primitive tree flattened into a list of register instructions, the CPU
+// counterpart of the GLSL that `expression` emits. Instruction `i` writes
+// register `i`; the first three registers hold the x, y and z of the point.
+// Batch evaluation keeps one row of values per register, but rows are reused
+// once their register is dead, so the working set stays small for long tapes.
+//
+// `prune` evaluates the tape over a box in interval arithmetic and drops every
+// min/max branch that cannot win inside it, so evaluation in that region only
+// pays for the parts of the tree that matter there.
+
+pub type Reg = u32;
+
+#[derive(Clone)]
+pub enum Op {
+    Input(u8),
+    Add(Reg, Reg),
+    Sub(Reg, Reg),
+    Mul(Reg, Reg),
+    AddImm(Reg, f32),
+    MulImm(Reg, f32),
+    MinImm(Reg, f32),
+    MaxImm(Reg, f32),
+    Neg(Reg),
+    Abs(Reg),
+    Sqrt(Reg),
+    Sin(Reg),
+    Cos(Reg),
+    Min(Reg, Reg),
+    Max(Reg, Reg),
+    SMin(Reg, Reg, f32),
+    SMax(Reg, Reg, f32),
+    /// A node without its own instructions, evaluated through `Primitive::eval`.
+    Opaque(Box<dyn Primitive>, [Reg; 3]),
+}
+
+#[derive(Clone)]
+pub struct Tape {
+    ops: Vec<Op>,
+    out: Reg,
+    /// Row of each register in batch evaluation and the number of rows.
+    rows: OnceLock<(Vec<u32>, usize)>,
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tape {
+    /// An empty tape holding just the input registers.
+    pub fn new() -> Self {
+        Tape::from_ops(vec![Op::Input(0), Op::Input(1), Op::Input(2)], 0)
+    }
+
+    fn from_ops(ops: Vec<Op>, out: Reg) -> Self {
+        Tape {
+            ops,
+            out,
+            rows: OnceLock::new(),
+        }
+    }
+
+    /// Compile `obj` into a tape.
+    pub fn compile(obj: &dyn Primitive) -> Self {
+        let mut tape = Tape::new();
+        tape.out = obj.compile(Tape::POINT, &mut tape);
+        tape
+    }
+
+    /// Registers of the input point.
+    pub const POINT: [Reg; 3] = [0, 1, 2];
+
+    /// Append `op` and return the register it writes.
+    pub fn push(&mut self, op: Op) -> Reg {
+        self.rows = OnceLock::new();
+        self.ops.push(op);
+        (self.ops.len() - 1) as Reg
+    }
+
+    /// √(Σ vᵢ²), summed left to right like the scalar code.
+    pub fn length(&mut self, v: &[Reg]) -> Reg {
+        let mut sum = self.push(Op::Mul(v[0], v[0]));
+        for &c in &v[1..] {
+            let sq = self.push(Op::Mul(c, c));
+            sum = self.push(Op::Add(sum, sq));
+        }
+        self.push(Op::Sqrt(sum))
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Register holding the result.
+    pub fn output(&self) -> Reg {
+        self.out
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.len() <= 3
+    }
+
+    /// Evaluate at one point, using `regs` as scratch space.
+    pub fn eval_with(&self, p: [f32; 3], regs: &mut Vec<f32>) -> f32 {
+        regs.clear();
+        for op in &self.ops {
+            let r = |i: &Reg| regs[*i as usize];
+            let v = match op {
+                Op::Input(a) => p[*a as usize],
+                Op::Add(a, b) => r(a) + r(b),
+                Op::Sub(a, b) => r(a) - r(b),
+                Op::Mul(a, b) => r(a) * r(b),
+                Op::AddImm(a, c) => r(a) + c,
+                Op::MulImm(a, c) => r(a) * c,
+                Op::MinImm(a, c) => r(a).min(*c),
+                Op::MaxImm(a, c) => r(a).max(*c),
+                Op::Neg(a) => -r(a),
+                Op::Abs(a) => r(a).abs(),
+                Op::Sqrt(a) => r(a).sqrt(),
+                Op::Sin(a) => r(a).sin(),
+                Op::Cos(a) => r(a).cos(),
+                Op::Min(a, b) => r(a).min(r(b)),
+                Op::Max(a, b) => r(a).max(r(b)),
+                Op::SMin(a, b, k) => cpu_smin(r(a), r(b), *k),
+                Op::SMax(a, b, k) => cpu_smax(r(a), r(b), *k),
+                Op::Opaque(prim, [x, y, z]) => prim.eval([r(x), r(y), r(z)]),
+            };
+            regs.push(v);
+        }
+        regs[self.out as usize]
+    }
+
+    pub fn eval(&self, p: [f32; 3]) -> f32 {
+        self.eval_with(p, &mut Vec::with_capacity(self.ops.len()))
+    }
+
+    /// Evaluate a batch. Registers are laid out one row of `points.len()`
+    /// values each, so every instruction is a flat loop over the batch.
+    pub fn eval_many(&self, points: &[[f32; 3]], out: &mut [f32]) {
+        self.eval_many_with(points, out, &mut Vec::new())
+    }
+
+    /// `eval_many` using `rows` as scratch space, so repeated batches do not
+    /// allocate once the rows have grown to the batch size.
+    pub fn eval_many_with(&self, points: &[[f32; 3]], out: &mut [f32], rows: &mut Vec<Vec<f32>>) {
+        let n = points.len();
+        let (row_of, count) = self.rows.get_or_init(|| self.allocate());
+        rows.resize_with((*count).max(rows.len()), Vec::new);
+        for row in &mut rows[..*count] {
+            row.resize(n, 0.0);
+        }
+        for (op, &d) in self.ops.iter().zip(row_of) {
+            // The destination row never holds an operand, so it can be taken
+            // out while the operands are read in place.
+            let mut dst = std::mem::take(&mut rows[d as usize]);
+            let row = |r: Reg| &rows[row_of[r as usize] as usize][..];
+            // Immediates are copied out of the tape so the loops vectorize.
+            match *op {
+                Op::Input(a) => {
+                    for (d, p) in dst.iter_mut().zip(points) {
+                        *d = p[a as usize];
+                    }
+                }
+                Op::Add(a, b) => binary(&mut dst, row(a), row(b), |x, y| x + y),
+                Op::Sub(a, b) => binary(&mut dst, row(a), row(b), |x, y| x - y),
+                Op::Mul(a, b) => binary(&mut dst, row(a), row(b), |x, y| x * y),
+                Op::AddImm(a, c) => unary(&mut dst, row(a), |x| x + c),
+                Op::MulImm(a, c) => unary(&mut dst, row(a), |x| x * c),
+                Op::MinImm(a, c) => unary(&mut dst, row(a), |x| x.min(c)),
+                Op::MaxImm(a, c) => unary(&mut dst, row(a), |x| x.max(c)),
+                Op::Neg(a) => unary(&mut dst, row(a), |x| -x),
+                Op::Abs(a) => unary(&mut dst, row(a), f32::abs),
+                Op::Sqrt(a) => unary(&mut dst, row(a), f32::sqrt),
+                Op::Sin(a) => unary(&mut dst, row(a), f32::sin),
+                Op::Cos(a) => unary(&mut dst, row(a), f32::cos),
+                Op::Min(a, b) => binary(&mut dst, row(a), row(b), f32::min),
+                Op::Max(a, b) => binary(&mut dst, row(a), row(b), f32::max),
+                Op::SMin(a, b, k) => binary(&mut dst, row(a), row(b), |x, y| cpu_smin(x, y, k)),
+                Op::SMax(a, b, k) => binary(&mut dst, row(a), row(b), |x, y| cpu_smax(x, y, k)),
+                Op::Opaque(ref prim, [x, y, z]) => {
+                    let (x, y, z) = (row(x), row(y), row(z));
+                    let q: Vec<[f32; 3]> = (0..n).map(|j| [x[j], y[j], z[j]]).collect();
+                    prim.eval_many(&q, &mut dst);
+                }
+            }
+            rows[d as usize] = dst;
+        }
+        out.copy_from_slice(&rows[row_of[self.out as usize] as usize]);
+    }
+
+    /// Assign registers to rows, reusing the row of a register after its last
+    /// read. A destination is assigned before its operands are released.
+    fn allocate(&self) -> (Vec<u32>, usize) {
+        let len = self.ops.len();
+        let mut last_use = vec![0; len];
+        for (i, op) in self.ops.iter().enumerate() {
+            for r in operands(op) {
+                last_use[r as usize] = i;
+            }
+        }
+        last_use[self.out as usize] = len;
+        let mut row_of = vec![0; len];
+        let mut free = Vec::new();
+        let mut count = 0;
+        for (i, op) in self.ops.iter().enumerate() {
+            row_of[i] = free.pop().unwrap_or_else(|| {
+                count += 1;
+                count - 1
+            });
+            for r in operands(op) {
+                if last_use[r as usize] == i {
+                    last_use[r as usize] = len + 1;
+                    free.push(row_of[r as usize]);
+                }
+            }
+            if last_use[i] < i {
+                free.push(row_of[i]);
+            }
+        }
+        (row_of, count as usize)
+    }
+
+    /// Range of every register over `bbox`.
+    fn intervals(&self, bbox: &Bbox) -> Vec<Interval> {
+        let mut iv: Vec<Interval> = Vec::with_capacity(self.ops.len());
+        for op in &self.ops {
+            let r = |i: &Reg| iv[*i as usize];
+            let v = match op {
+                Op::Input(a) => bbox.axis(*a as usize),
+                Op::Add(a, b) => r(a) + r(b),
+                Op::Sub(a, b) => r(a) - r(b),
+                Op::Mul(a, b) => r(a) * r(b),
+                Op::AddImm(a, c) => r(a) + *c,
+                Op::MulImm(a, c) => r(a) * *c,
+                Op::MinImm(a, c) => r(a).min(Interval::point(*c)),
+                Op::MaxImm(a, c) => r(a).max(Interval::point(*c)),
+                Op::Neg(a) => -r(a),
+                Op::Abs(a) => r(a).abs(),
+                Op::Sqrt(a) => r(a).sqrt(),
+                Op::Sin(a) => r(a).sin(),
+                Op::Cos(a) => r(a).cos(),
+                Op::Min(a, b) => r(a).min(r(b)),
+                Op::Max(a, b) => r(a).max(r(b)),
+                // smin undershoots min by at most k/4; smax overshoots max likewise.
+                Op::SMin(a, b, k) => {
+                    let m = r(a).min(r(b));
+                    Interval::new(m.lo - k / 4.0, m.hi)
+                }
+                Op::SMax(a, b, k) => {
+                    let m = r(a).max(r(b));
+                    Interval::new(m.lo, m.hi + k / 4.0)
+                }
+                Op::Opaque(prim, [x, y, z]) => prim
+                    .eval_interval(&Bbox::from_axes([r(x), r(y), r(z)]))
+                    .into(),
+            };
+            iv.push(v);
+        }
+        iv
+    }
+
+    /// Range of the result over `bbox`.
+    pub fn eval_interval(&self, bbox: &Bbox) -> Interval {
+        self.intervals(bbox)[self.out as usize]
+    }
+
+    /// A tape equal to this one everywhere inside `bbox`, together with the
+    /// range of the result there. Min/max instructions whose operands do not
+    /// overlap over the box become the winning operand (for the smooth
+    /// variants the gap must exceed k, where the blend is exactly one side),
+    /// and instructions that no longer feed the result are removed.
+    pub fn prune(&self, bbox: &Bbox) -> (Tape, Interval) {
+        let iv = self.intervals(bbox);
+        // alias[i]: the register instruction i reduces to inside the box.
+        let mut alias: Vec<Reg> = (0..self.ops.len() as Reg).collect();
+        for (i, op) in self.ops.iter().enumerate() {
+            let pick = |a: Reg, b: Reg, gap: f32, lower: bool| {
+                let (ia, ib) = (iv[a as usize], iv[b as usize]);
+                if ia.hi + gap <= ib.lo {
+                    Some(if lower { a } else { b })
+                } else if ib.hi + gap <= ia.lo {
+                    Some(if lower { b } else { a })
+                } else {
+                    None
+                }
+            };
+            let chosen = match *op {
+                Op::Min(a, b) => pick(a, b, 0.0, true),
+                Op::Max(a, b) => pick(a, b, 0.0, false),
+                Op::SMin(a, b, k) => pick(a, b, k, true),
+                Op::SMax(a, b, k) => pick(a, b, k, false),
+                _ => None,
+            };
+            if let Some(r) = chosen {
+                alias[i] = alias[r as usize];
+            }
+        }
+
+        let resolve = |r: Reg| alias[r as usize];
+        let mut live = vec![false; self.ops.len()];
+        live[..3].fill(true);
+        live[resolve(self.out) as usize] = true;
+        for i in (3..self.ops.len()).rev() {
+            if !live[i] || alias[i] != i as Reg {
+                continue;
+            }
+            for r in operands(&self.ops[i]) {
+                live[resolve(r) as usize] = true;
+            }
+        }
+
+        let mut renumber = vec![0 as Reg; self.ops.len()];
+        let mut pruned = Tape::from_ops(Vec::new(), 0);
+        for (i, op) in self.ops.iter().enumerate() {
+            if !live[i] || alias[i] != i as Reg {
+                continue;
+            }
+            let m = |r: Reg| renumber[resolve(r) as usize];
+            let op = match op.clone() {
+                Op::Add(a, b) => Op::Add(m(a), m(b)),
+                Op::Sub(a, b) => Op::Sub(m(a), m(b)),
+                Op::Mul(a, b) => Op::Mul(m(a), m(b)),
+                Op::AddImm(a, c) => Op::AddImm(m(a), c),
+                Op::MulImm(a, c) => Op::MulImm(m(a), c),
+                Op::MinImm(a, c) => Op::MinImm(m(a), c),
+                Op::MaxImm(a, c) => Op::MaxImm(m(a), c),
+                Op::Neg(a) => Op::Neg(m(a)),
+                Op::Abs(a) => Op::Abs(m(a)),
+                Op::Sqrt(a) => Op::Sqrt(m(a)),
+                Op::Sin(a) => Op::Sin(m(a)),
+                Op::Cos(a) => Op::Cos(m(a)),
+                Op::Min(a, b) => Op::Min(m(a), m(b)),
+                Op::Max(a, b) => Op::Max(m(a), m(b)),
+                Op::SMin(a, b, k) => Op::SMin(m(a), m(b), k),
+                Op::SMax(a, b, k) => Op::SMax(m(a), m(b), k),
+                Op::Opaque(prim, p) => Op::Opaque(prim, p.map(m)),
+                input @ Op::Input(_) => input,
+            };
+            renumber[i] = pruned.push(op);
+        }
+        pruned.out = renumber[resolve(self.out) as usize];
+        (pruned, iv[self.out as usize])
+    }
+}
+
+#[inline(always)]
+fn unary(dst: &mut [f32], a: &[f32], f: impl Fn(f32) -> f32) {
+    for (d, &x) in dst.iter_mut().zip(a) {
+        *d = f(x);
+    }
+}
+
+#[inline(always)]
+fn binary(dst: &mut [f32], a: &[f32], b: &[f32], f: impl Fn(f32, f32) -> f32) {
+    for ((d, &x), &y) in dst.iter_mut().zip(a).zip(b) {
+        *d = f(x, y);
+    }
+}
+
+fn operands(op: &Op) -> Vec<Reg> {
+    match *op {
+        Op::Input(_) => vec![],
+        Op::AddImm(a, _)
+        | Op::MulImm(a, _)
+        | Op::MinImm(a, _)
+        | Op::MaxImm(a, _)
+        | Op::Neg(a)
+        | Op::Abs(a)
+        | Op::Sqrt(a)
+        | Op::Sin(a)
+        | Op::Cos(a) => vec![a],
+        Op::Add(a, b)
+        | Op::Sub(a, b)
+        | Op::Mul(a, b)
+        | Op::Min(a, b)
+        | Op::Max(a, b)
+        | Op::SMin(a, b, _)
+        | Op::SMax(a, b, _) => vec![a, b],
+        Op::Opaque(_, p) => p.to_vec(),
+    }
+}
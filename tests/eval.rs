@@ -1,11 +1,23 @@
-use truescad::luascad::eval;
-use truescad::primitive::Primitive;
+use std::collections::HashMap;
+use std::task::Poll;
+
+use truescad::luascad::{
+    Diagnostic, EvalError, EvalResult, Evaluation, Limits, Modules, eval, eval_with_modules,
+    eval_with_params, merge_bodies,
+};
+use truescad::param::ParamKind;
+use truescad::param::ParamValue::{Boolean, Number, Text};
+use truescad::primitive::{Bbox, Primitive, ShaderData};
+use truescad::primitives::{Blobs, VoxelField};
+use truescad::shader::{build_fragment_shader, build_shader};
+use truescad::tape::Tape;
+use truescad::{TessellationError, tessellate_stl};
 
 // ── helpers ──────────────────────────────────────────────────────────────────
 
 fn eval_obj(script: &str) -> Box<dyn Primitive> {
-    let (_, obj) = eval(script).expect("eval failed");
-    obj.expect("script did not call build()")
+    let (_, bodies, _) = eval(script).expect("eval failed");
+    merge_bodies(&bodies).expect("script did not call build()")
 }
 
 fn val(obj: &dyn Primitive, x: f32, y: f32, z: f32) -> f32 {
@@ -16,20 +28,20 @@ fn val(obj: &dyn Primitive, x: f32, y: f32, z: f32) -> f32 {
 
 #[test]
 fn eval_empty_script_returns_no_object() {
-    let (output, obj) = eval("").expect("eval failed");
-    assert!(obj.is_none());
+    let (output, bodies, _) = eval("").expect("eval failed");
+    assert!(bodies.is_empty());
     assert!(output.is_empty());
 }
 
 #[test]
 fn eval_captures_print_output() {
-    let (output, _) = eval(r#"print("hello world")"#).expect("eval failed");
+    let (output, _, _) = eval(r#"print("hello world")"#).expect("eval failed");
     assert!(output.contains("hello world"), "output was: {output:?}");
 }
 
 #[test]
 fn eval_captures_multiple_print_lines() {
-    let (output, _) = eval("print(\"line1\")\nprint(\"line2\")").expect("eval failed");
+    let (output, _, _) = eval("print(\"line1\")\nprint(\"line2\")").expect("eval failed");
     assert!(output.contains("line1"));
     assert!(output.contains("line2"));
 }
@@ -46,8 +58,8 @@ fn eval_runtime_error_returns_err() {
 
 #[test]
 fn eval_no_build_call_gives_no_object() {
-    let (_, obj) = eval("local s = Sphere(1.0)").expect("eval failed");
-    assert!(obj.is_none());
+    let (_, bodies, _) = eval("local s = Sphere(1.0)").expect("eval failed");
+    assert!(bodies.is_empty());
 }
 
 // ── primitives ────────────────────────────────────────────────────────────────
@@ -142,6 +154,81 @@ fn eval_icone() {
     assert!(val(obj.as_ref(), 0., 0., 0.).abs() < 1e-6);
 }
 
+#[test]
+fn eval_spur_gear() {
+    // module 1, 20 teeth → pitch radius 10, tip radius 11, root radius 8.75.
+    let obj = eval_obj("build(Gear({module=1, teeth=20, pressure_angle=tau*20/360, thickness=2}))");
+    // inside the root circle
+    assert!(val(obj.as_ref(), 8., 0., 0.) < 0.);
+    // tooth centre on the pitch circle (tooth 0 is centred on +x)
+    assert!(val(obj.as_ref(), 10., 0., 0.) < 0.);
+    // gap centre on the pitch circle
+    let gap = std::f32::consts::PI / 20.;
+    assert!(val(obj.as_ref(), 10. * gap.cos(), 10. * gap.sin(), 0.) > 0.);
+    // beyond the tip circle and above the face
+    assert!(val(obj.as_ref(), 12., 0., 0.) > 0.);
+    assert!(val(obj.as_ref(), 8., 0., 2.) > 0.);
+    let bb = obj.bbox();
+    assert!(bb.max[0] >= 11. && bb.max[2] >= 1.);
+}
+
+#[test]
+fn eval_helical_gear_with_bore() {
+    let obj = eval_obj(
+        "build(Gear({module=1, teeth=20, pressure_angle=tau*20/360, thickness=10, \
+         helix_angle=tau/16, bore=3}))",
+    );
+    // bore is carved out
+    assert!(val(obj.as_ref(), 0., 0., 0.) > 0.);
+    assert!(val(obj.as_ref(), 8., 0., 0.) < 0.);
+    // the profile rotates with z: half a tooth pitch up, a gap sits on +x
+    assert!(val(obj.as_ref(), 10., 0., 0.) < 0.);
+    let gap = std::f32::consts::PI / 20.;
+    let z = gap * 10. / (std::f32::consts::TAU / 16.).tan();
+    assert!(val(obj.as_ref(), 10., 0., z) > 0.);
+}
+
+#[test]
+fn eval_gear_rejects_bad_teeth() {
+    assert!(eval("Gear({module=1, teeth=2.5, pressure_angle=0.35, thickness=1})").is_err());
+}
+
+#[test]
+fn eval_blobs_merge() {
+    // Two balls whose supports overlap: the midpoint is only inside the merged blob.
+    let obj = eval_obj("build(Blobs({{-1,0,0,2}, {1,0,0,2}}, 0.5))");
+    assert!(val(obj.as_ref(), -1., 0., 0.) < 0.);
+    assert!(val(obj.as_ref(), 0., 0., 0.) < 0.);
+    let single = eval_obj("build(Blobs({{-1,0,0,2}}, 0.5))");
+    assert!(val(single.as_ref(), 0., 0., 0.) > 0.);
+    // outside every support the estimate is the distance to the nearest support
+    assert!((val(obj.as_ref(), 5., 0., 0.) - 2.).abs() < 1e-5);
+}
+
+#[test]
+fn eval_blobs_distance_is_conservative() {
+    // Single ball: surface at r·sqrt(1 - t^(1/3)); the estimate must not overshoot it.
+    let obj = eval_obj("build(Blobs({{0,0,0,2}}, 0.5))");
+    let surface = 2. * (1. - 0.5f32.cbrt()).sqrt();
+    for i in 0..40 {
+        let x = i as f32 * 0.1;
+        let d = val(obj.as_ref(), x, 0., 0.);
+        assert!(d.abs() <= (x - surface).abs() + 1e-5, "x={x} d={d}");
+        assert_eq!(d < 0., x < surface);
+    }
+}
+
+#[test]
+fn eval_blobs_rejects_bad_threshold() {
+    assert!(eval("Blobs({{0,0,0,1}}, 2)").is_err());
+}
+
+#[test]
+fn blobs_need_balls() {
+    assert!(Blobs::new(Vec::new(), 0.5).is_err());
+    assert!(Blobs::new(vec![[0.0, 0.0, 0.0, 0.0]], 0.5).is_err());
+}
+
 // ── transformations ───────────────────────────────────────────────────────────
 
 #[test]
@@ -171,13 +258,111 @@ fn eval_rotate_returns_object() {
     eval_obj("build(Box(2,2,2):rotate(0.5, 0.5, 0))");
 }
 
+#[test]
+fn eval_minkowski_ball_offsets_surface() {
+    let obj = eval_obj("build(Minkowski(Box(2, 2, 2), {ball=0.5}))");
+    assert!(val(obj.as_ref(), 1.5, 0., 0.).abs() < 1e-5);
+    assert!(val(obj.as_ref(), 1.4, 0., 0.) < 0.);
+    assert!(obj.bbox().max[0] >= 1.5);
+}
+
+#[test]
+fn eval_minkowski_box_is_exact_for_sphere() {
+    // Sphere ⊕ box = box of half extents (1+1, 1+2, 1+3) with rounded edges.
+    let obj = eval_obj("build(Minkowski(Sphere(1), {box={2, 4, 6}}))");
+    assert!(val(obj.as_ref(), 2., 0., 0.).abs() < 1e-5);
+    assert!(val(obj.as_ref(), 0., 3., 0.).abs() < 1e-5);
+    assert!(val(obj.as_ref(), 0., 0., 4.).abs() < 1e-5);
+    // rounded corner: distance from the inner box corner minus the sphere radius
+    let d = val(obj.as_ref(), 2., 3., 4.);
+    assert!((d - (3f32.sqrt() - 1.)).abs() < 1e-5);
+}
+
+#[test]
+fn eval_minkowski_cylinder_rounds_vertical_edges() {
+    let obj = eval_obj("build(Minkowski(Box(2, 2, 2), {cylinder={l=1, r=0.5}}))");
+    // faces move out by r in XY and by l/2 in Z
+    assert!(val(obj.as_ref(), 1.5, 0., 0.).abs() < 1e-2);
+    assert!(val(obj.as_ref(), 0., 0., 1.5).abs() < 1e-5);
+    // vertical edge is rounded with radius r, horizontal edges stay sharp
+    let e = 0.5 / 2f32.sqrt();
+    assert!(val(obj.as_ref(), 1. + e, 1. + e, 0.).abs() < 2e-2);
+    assert!(val(obj.as_ref(), 1.45, 0., 1.45) < 0.);
+    assert!(val(obj.as_ref(), 0., 0., 0.) < 0.);
+}
+
+#[test]
+fn eval_minkowski_rejects_unknown_kernel() {
+    assert!(eval("Minkowski(Sphere(1), {cone=1})").is_err());
+}
+
+#[test]
+fn eval_voxelize_approximates_source() {
+    let obj = eval_obj("build(Voxelize(Sphere(2.0), 32))");
+    assert!(val(obj.as_ref(), 0., 0., 0.) < 0.);
+    assert!(val(obj.as_ref(), 5., 0., 0.) > 0.);
+    // one voxel is 4/32 wide; trilinear error on a sphere stays well below that
+    for &x in &[0.3f32, 1.1, 1.9, 2.3] {
+        let d = val(obj.as_ref(), x, 0.2, -0.1);
+        let exact = (x * x + 0.05).sqrt() - 2.;
+        assert!((d - exact).abs() < 0.125, "x={x} d={d} exact={exact}");
+    }
+}
+
+#[test]
+fn eval_voxel_bytes_round_trip() {
+    for sparse in ["true", "false"] {
+        let script = format!(
+            "local a = Voxelize(Box(2, 3, 4), 16, {sparse})\n\
+             local b = VoxelField(VoxelBytes(Box(2, 3, 4), 16, {sparse}))\n\
+             print(#VoxelBytes(Box(2, 3, 4), 16, {sparse}))\n\
+             build(Union({{a:translate(10, 0, 0), b}}))"
+        );
+        let obj = eval_obj(&script);
+        for &p in &[[0.2f32, 0.3, 0.4], [0.9, 1.4, 1.9], [1.5, 0., 0.]] {
+            let a = val(obj.as_ref(), p[0] + 10., p[1], p[2]);
+            let b = val(obj.as_ref(), p[0], p[1], p[2]);
+            assert!((a - b).abs() < 1e-6, "sparse={sparse} p={p:?}");
+        }
+    }
+}
+
+#[test]
+fn eval_sparse_voxels_are_smaller() {
+    // Two small spheres far apart: most bricks are empty space.
+    let scene = "Union({Sphere(1), Sphere(1):translate(20, 20, 20)})";
+    let (sparse, _, _) =
+        eval(&format!("print(#VoxelBytes({scene}, 64, true))")).expect("eval failed");
+    let (dense, _, _) =
+        eval(&format!("print(#VoxelBytes({scene}, 64, false))")).expect("eval failed");
+    let n = |s: String| s.trim().parse::<i64>().unwrap();
+    assert!(n(sparse) < n(dense));
+}
+
+#[test]
+fn eval_voxelize_rejects_unbounded() {
+    assert!(eval("Voxelize(PlaneX(1))").is_err());
+    assert!(eval("VoxelField('not voxels')").is_err());
+}
+
+#[test]
+fn voxel_grids_are_limited() {
+    assert!(eval("Voxelize(Sphere(1), 1000)").is_err());
+    assert!(eval("Voxelize(Sphere(1), -1)").is_err());
+    // A header claiming a grid of 2^96 samples, followed by no data.
+    let mut bytes = b"TSVX\x01\x00".to_vec();
+    bytes.extend([u32::MAX; 3].iter().flat_map(|n| n.to_le_bytes()));
+    bytes.extend([0f32, 0.0, 0.0, 1.0].iter().flat_map(|v| v.to_le_bytes()));
+    let err = VoxelField::from_bytes(&bytes).err().unwrap();
+    assert!(err.contains("samples per axis"), "{err}");
+}
+
 // ── boolean operations ────────────────────────────────────────────────────────
 
 #[test]
 fn eval_union_of_two_spheres() {
-    let obj = eval_obj(
-        "build(Union({Sphere(1.0):translate(2,0,0), Sphere(1.0):translate(-2,0,0)}))",
-    );
+    let obj =
+        eval_obj("build(Union({Sphere(1.0):translate(2,0,0), Sphere(1.0):translate(-2,0,0)}))");
     // both sphere centres inside the union
     assert!(val(obj.as_ref(), 2., 0., 0.) < 0.);
     assert!(val(obj.as_ref(), -2., 0., 0.) < 0.);
@@ -203,6 +388,705 @@ fn eval_difference() {
     assert!(val(obj.as_ref(), 5., 0., 0.) > 0.);
 }
 
+#[test]
+fn eval_add_is_union() {
+    let obj = eval_obj("build(Sphere(1.0):translate(2,0,0) + Sphere(1.0):translate(-2,0,0))");
+    assert!(val(obj.as_ref(), 2., 0., 0.) < 0.);
+    assert!(val(obj.as_ref(), -2., 0., 0.) < 0.);
+    assert!(val(obj.as_ref(), 10., 0., 0.) > 0.);
+}
+
+#[test]
+fn eval_mul_is_intersection() {
+    let obj = eval_obj("build(PlaneX(1.0) * PlaneNegX(1.0))");
+    assert!(val(obj.as_ref(), 0., 0., 0.) < 0.);
+    assert!(val(obj.as_ref(), 5., 0., 0.) > 0.);
+}
+
+#[test]
+fn eval_sub_is_difference() {
+    let obj = eval_obj("build(Sphere(3.0) - Sphere(1.0):translate(5,0,0))");
+    assert!(val(obj.as_ref(), 0., 0., 0.) < 0.);
+    assert!(val(obj.as_ref(), 5., 0., 0.) > 0.);
+}
+
+#[test]
+fn eval_unm_is_complement() {
+    let obj = eval_obj("build(-Sphere(1.0))");
+    assert!(val(obj.as_ref(), 0., 0., 0.) > 0.);
+    assert!(val(obj.as_ref(), 5., 0., 0.) < 0.);
+}
+
+#[test]
+fn eval_complement() {
+    let obj = eval_obj("build(Complement(Sphere(1.0)))");
+    assert!(val(obj.as_ref(), 0., 0., 0.) > 0.);
+    assert!(val(obj.as_ref(), 3., 0., 0.) < 0.);
+    assert!(
+        obj.bbox().finite().is_err(),
+        "the outside of a sphere is unbounded"
+    );
+    // inverting twice gives back the sphere and its bounds
+    let obj = eval_obj("build(Sphere(1.0):invert():invert())");
+    assert_bbox_near(obj.bbox(), [-1.0; 3], [1.0; 3]);
+}
+
+#[test]
+fn eval_xor() {
+    let obj = eval_obj("build(Xor(Sphere(1.0), Sphere(1.0):translate(1,0,0)))");
+    assert!(val(obj.as_ref(), -0.5, 0., 0.) < 0.);
+    assert!(
+        val(obj.as_ref(), 0.5, 0., 0.) > 0.,
+        "the overlap is removed"
+    );
+    assert!(val(obj.as_ref(), 1.5, 0., 0.) < 0.);
+    assert_bbox_near(obj.bbox(), [-1.0, -1.0, -1.0], [2.0, 1.0, 1.0]);
+}
+
+#[test]
+fn clip_bounds_unbounded_objects() {
+    // a mold cavity: the outside of a part, cut down to a block
+    let obj = eval_obj("build(Clip(-Sphere(1.0), {min={-2,-2,-2}, max={2,2,2}}))");
+    assert!(val(obj.as_ref(), 0., 0., 0.) > 0.);
+    assert!(val(obj.as_ref(), 1.5, 0., 0.) < 0.);
+    assert_bbox_near(obj.bbox(), [-2.0; 3], [2.0; 3]);
+
+    let obj = eval_obj("build(Intersection({Box(4, 4, 4), Complement(Sphere(1.0))}))");
+    assert_bbox_near(obj.bbox(), [-2.0; 3], [2.0; 3]);
+    let obj = eval_obj("build(Complement(PlaneX(1.0)) * PlaneX(2.0))");
+    assert_eq!((obj.bbox().min[0], obj.bbox().max[0]), (1.0, 2.0));
+
+    assert!(eval("build(Clip(Sphere(1), {min={0,0,0}, max={2,2}}))").is_err());
+}
+
+#[test]
+fn operators_match_the_functions() {
+    let pairs = [
+        ("a + b", "Union({a, b})"),
+        ("a - b - c", "Difference({Difference({a, b}), c})"),
+        ("a * (b + c)", "Intersection({a, Union({b, c})})"),
+        ("a:smooth(0.5) + b", "Union({a, b}, 0.5)"),
+        ("a - b:smooth(0.3)", "Difference({a, b}, 0.3)"),
+    ];
+    let setup = "local a, b, c = Box(2, 2, 2), Sphere(1.2):translate(1, 0, 0), Sphere(0.5)\n";
+    for (ops, calls) in pairs {
+        let x = eval_obj(&format!("{setup}build({ops})"));
+        let y = eval_obj(&format!("{setup}build({calls})"));
+        assert_eq!(
+            build_fragment_shader(x.as_ref()),
+            build_fragment_shader(y.as_ref()),
+            "{ops}"
+        );
+    }
+}
+
+#[test]
+fn arithmetic_still_works() {
+    let script = "local x, y = 3, 4\n\
+        local function two() return 1, 2 end\n\
+        local t = setmetatable({}, {__add = function() return 'meta' end})\n\
+        print(x + y, x - y, x * y, -x, x + y * 2, (x + y) * 2, 10 - x - y, x + two(), t + x, '3' * y)";
+    let (output, _, _) = eval(script).unwrap();
+    assert_eq!(output, "7\t-1\t12\t-3\t11\t14\t3\t4\tmeta\t12\n");
+
+    // A local is only left to native arithmetic while nothing else can reach
+    // it, here through b.
+    let script = "local t = setmetatable({}, {__add = function() return 'meta' end})\n\
+        local a, b, c = 0, 0, 0\n\
+        for i = 1, 3 do c = b + 1 b = a a = t end\n\
+        local __arith_add = 2\n\
+        print(c, __arith_add + 1)";
+    let (output, _, _) = eval(script).unwrap();
+    assert_eq!(output, "meta\t3\n");
+}
+
+#[test]
+fn numeric_arithmetic_stays_native() {
+    // About five instructions an iteration; a callback per operator takes
+    // more than ten times that.
+    let script = "local n, x = 0, 1.5\nfor i = 1, 100000 do n = n + i * x - i end\nprint(n)";
+    let (output, _, _) = eval_limited(script, 1_000_000, usize::MAX).unwrap();
+    assert_eq!(output, "2500025000\n");
+}
+
+#[test]
+fn operators_reject_non_objects() {
+    let err = eval("local s, n = Sphere(1), nil\nbuild(s + n)")
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "script:2: cannot combine an object with a nil value"
+    );
+    assert!(eval("build(Sphere(1):smooth(0) + Sphere(2))").is_err());
+}
+
+// ── vectors ───────────────────────────────────────────────────────────────────
+
+fn printed(script: &str) -> String {
+    let (output, _, _) = eval(script).expect("eval failed");
+    output
+}
+
+#[test]
+fn vector_arithmetic() {
+    let script = "local a, b = vec3(1, 2, 3), vec3({4, 5, 6})\n\
+        print(a + b, a - b, a * 2, 2 * a, a / 2, -a, a * b)\n\
+        print(a:dot(b), a:cross(b), a.x, a.y, a.z, a:unpack())\n\
+        print(vec2(3, 4):length(), vec3(0, 3, 4):normalize(), vec2(1, 2) + vec2(1, 1))\n\
+        print(a == vec3(1, 2, 3), a == b, tostring(vec2(0.5, -1)))";
+    assert_eq!(
+        printed(script),
+        "vec3(5, 7, 9)\tvec3(-3, -3, -3)\tvec3(2, 4, 6)\tvec3(2, 4, 6)\tvec3(0.5, 1, 1.5)\t\
+         vec3(-1, -2, -3)\tvec3(4, 10, 18)\n\
+         32\tvec3(-3, 6, -3)\t1\t2\t3\t1\t2\t3\n\
+         5\tvec3(0, 0.6, 0.8)\tvec2(2, 3)\n\
+         true\tfalse\tvec2(0.5, -1)\n"
+    );
+}
+
+#[test]
+fn matrix_arithmetic() {
+    let script = "local m = mat4.translation(vec3(1, 2, 3)) * mat4.scaling(2)\n\
+        local p = vec3(1, 1, 1)\n\
+        print(m)\n\
+        print(m * p, m:transform_vector(p), m:inverse() * (m * p), m:transpose():unpack())\n\
+        print(mat4.axis_angle(vec3(0, 0, 1), math.pi / 2) * vec3(1, 0, 0))\n\
+        print(mat4(1,0,0,0, 0,1,0,0, 0,0,1,0, 0,0,0,1) == mat4.identity())";
+    let output = printed(script);
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(
+        lines[0],
+        "mat4(2, 0, 0, 1; 0, 2, 0, 2; 0, 0, 2, 3; 0, 0, 0, 1)"
+    );
+    assert!(
+        lines[1].starts_with("vec3(3, 4, 5)\tvec3(2, 2, 2)\tvec3(1, 1, 1)\t2\t0\t0\t0\t"),
+        "{}",
+        lines[1]
+    );
+    let rotated: Vec<f64> = lines[2]
+        .trim_start_matches("vec3(")
+        .trim_end_matches(')')
+        .split(", ")
+        .map(|x| x.parse().unwrap())
+        .collect();
+    assert!(
+        rotated[0].abs() < 1e-9 && (rotated[1] - 1.0).abs() < 1e-9,
+        "{rotated:?}"
+    );
+    assert_eq!(lines[3], "true");
+}
+
+#[test]
+fn transforms_take_vectors() {
+    let pairs = [
+        ("a:translate(vec3(1, 2, 3))", "a:translate(1, 2, 3)"),
+        ("a:rotate(vec3(0.1, 0.2, 0.3))", "a:rotate(0.1, 0.2, 0.3)"),
+        ("a:scale(vec3(1, 2, 3))", "a:scale(1, 2, 3)"),
+        (
+            "PlaneHessian(vec3(0, 0.6, 0.8), 1)",
+            "PlaneHessian({0, 0.6, 0.8}, 1)",
+        ),
+        (
+            "Plane3Points(vec3(0,0,1), vec3(1,0,1), vec3(0,1,1))",
+            "Plane3Points({0,0,1}, {1,0,1}, {0,1,1})",
+        ),
+        (
+            "Blobs({{vec3(0, 0, 1), 2}, {1, 0, 0, 1}}, 0.5)",
+            "Blobs({{0, 0, 1, 2}, {1, 0, 0, 1}}, 0.5)",
+        ),
+        (
+            "Minkowski(a, {box=vec3(1, 2, 3)})",
+            "Minkowski(a, {box={1, 2, 3}})",
+        ),
+        (
+            "Clip(-a, {min=vec3(-2, -2, -2), max={2, 2, 2}})",
+            "Clip(-a, {min={-2, -2, -2}, max={2, 2, 2}})",
+        ),
+    ];
+    let setup = "local a = Box(1, 2, 3)\n";
+    for (vectors, numbers) in pairs {
+        let x = eval_obj(&format!("{setup}build({vectors})"));
+        let y = eval_obj(&format!("{setup}build({numbers})"));
+        assert_eq!(
+            build_fragment_shader(x.as_ref()),
+            build_fragment_shader(y.as_ref()),
+            "{vectors}"
+        );
+    }
+}
+
+#[test]
+fn transform_applies_matrices() {
+    // mat4.rotation turns an object the way obj:rotate does
+    let rotated = eval_obj("build(Box(4, 0.2, 0.2):rotate(0.3, 0.5, 0.7))");
+    let transformed = eval_obj("build(Box(4, 0.2, 0.2):transform(mat4.rotation(0.3, 0.5, 0.7)))");
+    let mut rng = Rng(0x51ed_270b);
+    for p in (0..64).map(|_| rng.point(4.)) {
+        assert!(
+            (rotated.eval(p) - transformed.eval(p)).abs() < 1e-4,
+            "at {p:?}"
+        );
+    }
+
+    let obj =
+        eval_obj("build(Sphere(1):transform(mat4.translation(3, 0, 0) * mat4.scaling(1, 2, 1)))");
+    assert!(val(obj.as_ref(), 3., 1.9, 0.) < 0.);
+    assert!(val(obj.as_ref(), 4.1, 0., 0.) > 0.);
+    assert!(
+        (val(obj.as_ref(), 5., 0., 0.) - 1.).abs() < 1e-5,
+        "distance stays exact along the short axis"
+    );
+    assert_bbox_near(obj.bbox(), [2., -2., -1.], [4., 2., 1.]);
+
+    let err = eval("build(Sphere(1):transform(mat4.scaling(1, 0, 1)))")
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "script:1: matrix must be an invertible affine transform"
+    );
+}
+
+#[test]
+fn vector_type_errors() {
+    let cases = [
+        (
+            "local v = vec3(1, 2)",
+            "script:1: vec3 expects 3 numbers or a table of 3 numbers",
+        ),
+        (
+            "local v = vec3(1, {}, 2, 3)",
+            "script:1: vec3 expects a number as argument 2",
+        ),
+        (
+            "local v = vec3(1, nil, 2, 3)",
+            "script:1: vec3 expects a number as argument 2",
+        ),
+        (
+            "local v = vec2({1, \"x\", 2})",
+            "script:1: vec2 expects a number at position 2 in list",
+        ),
+        (
+            "local v = vec2(1, 2, 3)",
+            "script:1: vec2 expects 2 numbers or a table of 2 numbers",
+        ),
+        (
+            "local m = mat4(1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 0, 1, \"junk\")",
+            "script:1: mat4 expects a number as argument 17",
+        ),
+        (
+            "local v = vec3(1, 2, 3):cross(vec2(1, 2))",
+            "script:1: cannot take the cross product of vec3 and vec2",
+        ),
+        (
+            "local v = vec3(0, 0, 0):normalize()",
+            "script:1: cannot normalize a zero-length vector",
+        ),
+        (
+            "build(Sphere(1):translate(\"x\"))",
+            "script:1: translate expects a vec3 or three numbers",
+        ),
+        (
+            "build(Sphere(1):transform(vec3(1, 2, 3)))",
+            "script:1: transform expects a mat4, got vec3",
+        ),
+        (
+            "local v = vec3(1, 2, 3) + vec2(1, 2)",
+            "script:1: cannot add vec3 and vec2",
+        ),
+        (
+            "local v = mat4.identity() / 2",
+            "script:1: cannot divide mat4 and number",
+        ),
+        (
+            "build(Sphere(1) + vec3(1, 2, 3))",
+            "script:1: cannot combine an object with a vec3 value",
+        ),
+    ];
+    for (script, message) in cases {
+        let err = eval(script)
+            .err()
+            .unwrap_or_else(|| panic!("{script} should fail"));
+        assert_eq!(err.to_string(), message);
+    }
+    let err = eval("build(PlaneHessian(vec2(1, 0), 1))").err().unwrap();
+    assert!(
+        err.to_string()
+            .contains("must be a vec3 or a table of 3 numbers"),
+        "{err}"
+    );
+    let d = diagnose("local v = vec3(1, {}, 2)");
+    assert_eq!((d.function.as_deref(), d.argument), (Some("vec3"), Some(2)));
+}
+
+#[test]
+fn objects_report_their_bounds() {
+    let script = "local b = Box(2, 4, 6):translate(1, 0, 0)\n\
+        local bb = b:bbox()\n\
+        print(bb.min, bb.max, bb.min[1], bb.max.z, #bb.min)\n\
+        print(b:size(), b:center())";
+    assert_eq!(
+        printed(script),
+        "vec3(0, -2, -3)\tvec3(2, 2, 3)\t0\t3\t3\nvec3(2, 4, 6)\tvec3(1, 0, 0)\n"
+    );
+    // sides the object does not close are at infinity
+    let script = "local bb = PlaneZ(1):bbox()\n\
+        print(bb.max.z, bb.max.x == math.huge, bb.min.z == -math.huge, PlaneZ(1):size().x)";
+    assert_eq!(printed(script), "1\ttrue\ttrue\tinf\n");
+    let err = eval("print(PlaneZ(1):center())").err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "script:1: object is unbounded along X, Y, Z"
+    );
+}
+
+#[test]
+fn objects_answer_point_queries() {
+    let script = "local s = Sphere(1):translate(2, 0, 0)\n\
+        print(s:distance(2, 0, 0), s:distance(vec3(5, 0, 0)), s:inside(2.5, 0, 0), s:inside(0, 0, 0))";
+    assert_eq!(printed(script), "-1\t2\ttrue\tfalse\n");
+    let err = eval("print(Sphere(1):distance(1, 2))").err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "script:1: distance expects a vec3 or three numbers"
+    );
+}
+
+#[test]
+fn layout_can_use_bounds() {
+    // stand a sphere on top of a box, then clip to the bounds of the pair
+    let obj = eval_obj(
+        "local base, ball = Box(2, 2, 1), Sphere(0.5)\n\
+         local top = ball:translate(0, 0, base:bbox().max.z - ball:bbox().min.z)\n\
+         local pair = base + top\n\
+         build(pair, {bounds=pair:bbox()})",
+    );
+    assert_bbox_near(obj.bbox(), [-1., -1., -0.5], [1., 1., 1.5]);
+    assert!(val(obj.as_ref(), 0., 0., 1.4) < 0.);
+}
+
+// ── layout ────────────────────────────────────────────────────────────────────
+
+#[test]
+fn align_to_matches_sides() {
+    let obj = eval_obj(
+        "build(Box(2, 2, 2):translate(5, 5, 5):align_to(Box(4, 4, 4), {x=\"center\", z=\"top\"}))",
+    );
+    assert_bbox_near(obj.bbox(), [-1., 4., 0.], [1., 6., 2.]);
+    let obj =
+        eval_obj("build(Sphere(1):translate(5, 5, 5):align_to(Box(4, 4, 4):translate(1, 2, 3)))");
+    assert_bbox_near(obj.bbox(), [0., 1., 2.], [2., 3., 4.]);
+    // only the sides that are used need to be finite
+    let obj = eval_obj("build(Box(2, 2, 2):align_to(PlaneZ(1), {z=\"top\"}))");
+    assert_bbox_near(obj.bbox(), [-1., -1., -1.], [1., 1., 1.]);
+    let obj = eval_obj(
+        "build(Box(2, 2, 2):align_to(Box(4, 4, 4), {x=\"right\", y=\"front\", z=\"min\"}))",
+    );
+    assert_bbox_near(obj.bbox(), [0., -2., -2.], [2., 0., 0.]);
+}
+
+#[test]
+fn place_on_follows_nested_transforms() {
+    let base =
+        "local base = Box(2, 2, 2):rotate(0, 0, math.pi / 4):translate(3, 0, 1):scale(1, 1, 2)\n";
+    let obj = eval_obj(&format!("{base}build(Sphere(1):place_on(base))"));
+    assert_bbox_near(obj.bbox(), [2., -1., 4.], [4., 1., 6.]);
+    let obj = eval_obj(&format!("{base}build(Sphere(1):place_on(base, 0.5))"));
+    assert_bbox_near(obj.bbox(), [2., -1., 4.5], [4., 1., 6.5]);
+
+    // a floor is unbounded across: only the height changes
+    let obj = eval_obj("build(Box(1, 1, 1):translate(3, 4, 0):place_on(PlaneZ(1)))");
+    assert_bbox_near(obj.bbox(), [2.5, 3.5, 1.], [3.5, 4.5, 2.]);
+}
+
+#[test]
+fn layout_uses_rotated_bounds() {
+    // a bar turned 45° about Z is as wide as its diagonal extent
+    let half = 2.5 / 2f32.sqrt();
+    let obj = eval_obj(
+        "local bar = Box(4, 1, 1):rotate(0, 0, math.pi / 4)\n\
+         build(bar:align_to(Box(1, 1, 1):translate(10, 0, 0), {x=\"left\"}))",
+    );
+    assert_bbox_near(obj.bbox(), [9.5, -half, -0.5], [9.5 + 2. * half, half, 0.5]);
+}
+
+#[test]
+fn stack_and_distribute() {
+    let obj = eval_obj(
+        "build(Stack({Box(2, 2, 2), Sphere(0.5), Box(1, 1, 1):translate(5, 5, 5)}, \"z\", 0.1))",
+    );
+    assert_bbox_near(obj.bbox(), [-1., -1., -1.], [1., 1., 3.2]);
+    assert!(
+        val(obj.as_ref(), 0., 0., 1.6) < 0.,
+        "the sphere sits on the box"
+    );
+    assert!(
+        val(obj.as_ref(), 0., 0., 2.7) < 0.,
+        "the small box sits on the sphere"
+    );
+    assert!(val(obj.as_ref(), 0., 0., 2.15) > 0., "the gap is kept");
+
+    let obj = eval_obj("build(Stack({Box(2, 2, 2), Box(2, 2, 2)}, \"x\"))");
+    assert_bbox_near(obj.bbox(), [-1., -1., -1.], [3., 1., 1.]);
+
+    let obj = eval_obj(
+        "build(Distribute({Sphere(1), Box(1, 1, 1):translate(0, 2, 0), Sphere(0.5)}, \"x\", 3))",
+    );
+    assert_bbox_near(obj.bbox(), [-1., -1., -1.], [6.5, 2.5, 1.]);
+    for (x, y) in [(0., 0.), (3., 2.), (6., 0.)] {
+        assert!(val(obj.as_ref(), x, y, 0.) < 0., "nothing at {x}, {y}");
+    }
+}
+
+#[test]
+fn layout_errors() {
+    let cases = [
+        (
+            "build(Sphere(1):align_to(Sphere(2), {w=\"top\"}))",
+            "script:1: anchors must be keyed by x, y or z",
+        ),
+        (
+            "build(Sphere(1):align_to(Sphere(2), {z=\"left\"}))",
+            "script:1: anchor z must be \"min\", \"center\", \"max\", \"bottom\" or \"top\"",
+        ),
+        (
+            "build(Box(1, 1, 1):place_on(PlaneX(0)))",
+            "script:1: base is unbounded along Z",
+        ),
+        (
+            "build(PlaneZ(0):place_on(Box(1, 1, 1)))",
+            "script:1: object is unbounded along Z",
+        ),
+        (
+            "build(PlaneZ(0):align_to(Sphere(1), {z=\"bottom\"}))",
+            "script:1: object is unbounded along Z",
+        ),
+        (
+            "build(Stack({Sphere(1)}, \"w\"))",
+            "script:1: Stack axis must be \"x\", \"y\" or \"z\"",
+        ),
+        (
+            "build(Distribute({}, \"x\", 1))",
+            "script:1: Distribute requires at least one object",
+        ),
+    ];
+    for (script, message) in cases {
+        let err = eval(script)
+            .err()
+            .unwrap_or_else(|| panic!("{script} should fail"));
+        assert_eq!(err.to_string(), message);
+    }
+}
+
+// ── bounding boxes ────────────────────────────────────────────────────────────
+
+fn assert_bbox_near(bb: Bbox, min: [f32; 3], max: [f32; 3]) {
+    for i in 0..3 {
+        assert!(
+            (bb.min[i] - min[i]).abs() < 1e-3,
+            "min {:?} != {min:?}",
+            bb.min
+        );
+        assert!(
+            (bb.max[i] - max[i]).abs() < 1e-3,
+            "max {:?} != {max:?}",
+            bb.max
+        );
+    }
+}
+
+#[test]
+fn bbox_tapered_cylinder_is_tight() {
+    // A cone cut by two planes used to report a 2e10 wide box.
+    let obj = eval_obj("build(Cylinder({l=4, r1=1, r2=2}))");
+    let bb = obj.bbox().finite().expect("cylinder should be bounded");
+    assert!(bb.max[0] <= 2.0 + 1e-3 && bb.max[0] >= 2.0 - 1e-3);
+    assert!((bb.max[2] - 2.0).abs() < 1e-3 && (bb.min[2] + 2.0).abs() < 1e-3);
+}
+
+#[test]
+fn bbox_clips_oblique_half_spaces() {
+    // Tetrahedron x, y, z >= 0, x + y + z <= 1.
+    let obj = eval_obj(
+        "local s = 1 / math.sqrt(3) \
+         build(Intersection({PlaneNegX(0), PlaneNegY(0), PlaneNegZ(0), PlaneHessian({s, s, s}, s)}))",
+    );
+    assert!(val(obj.as_ref(), 0.2, 0.2, 0.2) < 0.);
+    assert_bbox_near(obj.bbox(), [0.0; 3], [1.0; 3]);
+}
+
+#[test]
+fn bbox_rotated_planes_are_clipped() {
+    let obj = eval_obj(
+        "build(Intersection({PlaneX(1), PlaneNegX(1), PlaneY(1), PlaneNegY(1), \
+         PlaneZ(1), PlaneNegZ(1)}):rotate(0, 0, math.pi / 4))",
+    );
+    let h = 2f32.sqrt();
+    assert_bbox_near(obj.bbox(), [-h, -h, -1.0], [h, h, 1.0]);
+}
+
+#[test]
+fn bbox_difference_with_half_space() {
+    let obj = eval_obj("build(Difference({Sphere(1), PlaneX(0)}))");
+    assert_bbox_near(obj.bbox(), [0.0, -1.0, -1.0], [1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn bbox_smooth_union_grows() {
+    let sharp = eval_obj("build(Union({Sphere(1), Sphere(1):translate(1.5, 0, 0)}))");
+    let smooth = eval_obj("build(Union({Sphere(1), Sphere(1):translate(1.5, 0, 0)}, 0.8))");
+    assert!(smooth.bbox().max[1] >= sharp.bbox().max[1] + 0.2 - 1e-4);
+    // The smoothed surface bulges out between the spheres, but stays inside.
+    let bb = smooth.bbox();
+    for i in 0..=20 {
+        let x = -1.0 + 3.5 * i as f32 / 20.0;
+        assert!(val(smooth.as_ref(), x, bb.max[1] + 1e-3, 0.) > 0.);
+    }
+}
+
+#[test]
+fn bbox_twist_covers_rotated_corners() {
+    let obj = eval_obj("build(Twist(Box(2, 2, 4), 16.0))");
+    let bb = obj.bbox();
+    let r = 2f32.sqrt();
+    assert!(bb.max[0] >= r - 1e-4 && bb.max[1] >= r - 1e-4);
+}
+
+#[test]
+fn bbox_bend_maps_axes() {
+    // Bend sends the inner Z extent to Y.
+    let obj = eval_obj("build(Bend(Box(1, 1, 6), 4.0))");
+    let bb = obj.bbox();
+    assert!((bb.max[1] - 3.0).abs() < 1e-3);
+    for i in 0..=40 {
+        let y = -2.9 + 5.8 * i as f32 / 40.0;
+        for x in [-bb.max[0] - 0.01, bb.max[0] + 0.01] {
+            assert!(val(obj.as_ref(), x, y, 0.) > 0.);
+        }
+    }
+}
+
+#[test]
+fn bbox_unbounded_is_an_error() {
+    let err = eval_obj("build(PlaneX(1))").bbox().finite().unwrap_err();
+    assert!(err.contains('X') && err.contains('Y'), "{err}");
+    let err = eval_obj("build(iCylinder(2))").bbox().finite().unwrap_err();
+    assert!(err.ends_with('Z'), "{err}");
+}
+
+// ── tessellation ──────────────────────────────────────────────────────────────
+
+#[test]
+fn tessellate_rejects_unbounded_objects() {
+    for script in ["build(PlaneX(1))", "build(iCylinder(2))", "build(iCone(1))"] {
+        let obj = eval_obj(script);
+        match tessellate_stl(obj.as_ref()) {
+            Err(TessellationError::Unbounded(msg)) => assert!(msg.contains("unbounded"), "{msg}"),
+            other => panic!(
+                "{script}: expected Unbounded, got {:?}",
+                other.map(|b| b.len())
+            ),
+        }
+    }
+}
+
+#[test]
+fn tessellate_rejects_huge_objects() {
+    let obj = eval_obj("build(Sphere(1e5))");
+    let err = tessellate_stl(obj.as_ref()).unwrap_err();
+    assert_eq!(err.kind(), "too_large");
+}
+
+#[test]
+fn tessellate_sphere_produces_stl() {
+    let stl = tessellate_stl(eval_obj("build(Sphere(1))").as_ref()).expect("tessellation failed");
+    // 80-byte header, triangle count, 50 bytes per triangle.
+    let n = u32::from_le_bytes(stl[80..84].try_into().unwrap()) as usize;
+    assert!(n > 0);
+    assert_eq!(stl.len(), 84 + 50 * n);
+}
+
+#[test]
+fn build_bounds_clip_infinite_geometry() {
+    let obj = eval_obj("build(iCylinder(1), {bounds={min={-2, -2, -1}, max={2, 2, 3}}})");
+    let bb = obj
+        .bbox()
+        .finite()
+        .expect("bounds should make the object finite");
+    assert_eq!(bb.min[2], -1.0);
+    assert_eq!(bb.max[2], 3.0);
+    assert!(val(obj.as_ref(), 0., 0., 0.) < 0.);
+    assert!(val(obj.as_ref(), 0., 0., 4.) > 0.);
+    assert!(tessellate_stl(obj.as_ref()).is_ok());
+}
+
+#[test]
+fn build_bounds_are_validated() {
+    assert!(eval("build(Sphere(1), {bounds={min={0, 0, 0}, max={1, 1}}})").is_err());
+    assert!(eval("build(Sphere(1), {bounds={min={1, 0, 0}, max={0, 1, 1}}})").is_err());
+    assert!(eval("build(Sphere(1), 5)").is_err());
+}
+
+#[test]
+fn build_collects_named_bodies() {
+    let (_, bodies, _) = eval(
+        "build(Box(2, 2, 1), {name='base', color=vec3(0.2, 0.4, 0.6)})
+         build(Sphere(1):translate(0, 0, 3), {name='lid'})
+         build(Sphere(5), {name='base', color={1, 0, 0}})",
+    )
+    .unwrap();
+    let names: Vec<_> = bodies.iter().map(|b| b.name.as_str()).collect();
+    assert_eq!(names, ["base", "lid"]);
+    // Building a name again replaces that body in place.
+    assert_eq!(bodies[0].color, Some([1.0, 0.0, 0.0]));
+    assert!(val(bodies[0].object.as_ref(), 0., 0., 4.) < 0.);
+    assert_eq!(bodies[1].color, None);
+    let scene = merge_bodies(&bodies).unwrap();
+    assert!(val(scene.as_ref(), 0., 0., 0.) < 0.);
+    assert!(build_fragment_shader(scene.as_ref()).contains("map"));
+    for body in &bodies {
+        assert!(
+            tessellate_stl(body.object.as_ref()).is_ok(),
+            "{}",
+            body.name
+        );
+    }
+}
+
+#[test]
+fn build_without_name_uses_default_body() {
+    let (_, bodies, _) = eval("build(Sphere(1)) build(Sphere(2))").unwrap();
+    assert_eq!(bodies.len(), 1);
+    assert_eq!(bodies[0].name, truescad::luascad::DEFAULT_BODY);
+    assert!(val(bodies[0].object.as_ref(), 1.5, 0., 0.) < 0.);
+}
+
+#[test]
+fn build_options_are_validated() {
+    for (script, msg) in [
+        (
+            "build(Sphere(1), {name=3})",
+            "name must be a non-empty string",
+        ),
+        (
+            "build(Sphere(1), {name=''})",
+            "name must be a non-empty string",
+        ),
+        (
+            "build(Sphere(1), {color={1, 0}})",
+            "color must be a vec3 or a table {r,g,b}",
+        ),
+        (
+            "build(Sphere(1), {color={2, 0, 0}})",
+            "color components must be between 0 and 1",
+        ),
+    ] {
+        let err = eval(script).err().unwrap().to_string();
+        assert!(err.contains(msg), "{script}: {err}");
+    }
+}
+
 // ── deformations ──────────────────────────────────────────────────────────────
 
 #[test]
@@ -220,6 +1104,979 @@ fn eval_twist() {
     assert!(val(obj.as_ref(), 0., 0., 0.) < 0.);
 }
 
+// ── sampled checks ────────────────────────────────────────────────────────────
+// Properties checked against `eval` on the same scenes, at deterministic
+// pseudo-random points.
+
+const SCENES: &[&str] = &[
+    "build(Sphere(1.5))",
+    "build(Box(2, 3, 1))",
+    "build(Cylinder({l=3, r1=0.5, r2=1.5}))",
+    "build(iCylinder(1))",
+    "build(iCone(0.5))",
+    "build(PlaneNegY(0.5))",
+    "build(PlaneHessian({0.6, 0, 0.8}, 0.3))",
+    "build(Gear({module=0.3, teeth=12, pressure_angle=0.35, thickness=1, helix_angle=0.3, bore=0.5}))",
+    "build(Blobs({{0,0,0,1.5}, {1,0.5,0,1}, {-1,1,1,2}}, 0.4))",
+    "build(Voxelize(Box(2, 2, 2, 0.3), 16))",
+    "build(Voxelize(Sphere(2), 16))",
+    "build(Union({Sphere(1), Box(1, 2, 1):translate(1, 0, 0)}, 0.5))",
+    "build(Intersection({Sphere(1.5), Box(2, 2, 2)}, 0.3))",
+    "build(Difference({Box(3, 3, 3), Sphere(1.8)}, 0.2))",
+    "build(Difference({Box(3, 3, 3), Sphere(1.8), iCylinder(0.5)}, 0.2))",
+    "build(Box(1, 2, 3):rotate(0.3, 0.7, 1.1):scale(1, 2, 0.5))",
+    "build(Box(1, 2, 3):transform(mat4(1, 0.5, 0, 1,  0, 2, 0, 0,  0.3, 0, 1, -1,  0, 0, 0, 1)))",
+    "build(Sphere(1):color(1, 0, 0):smooth(0.3) + Box(1, 2, 3):material{specular=0.4})",
+    "build(Box(2, 2, 2) - Sphere(1.2):debug() + Sphere(0.5):translate(2, 0, 0):only())",
+    "build(Bend(Box(4, 1, 1), 3))",
+    "build(Twist(Box(1, 2, 4), 5))",
+    "build(Bend(Twist(Box(1, 2, 4), 5), 3))",
+    "build(Minkowski(Box(1, 1, 1), {ball=0.5}))",
+    "build(Minkowski(Sphere(0.5), {box={1, 2, 1}}))",
+    "build(Minkowski(Box(1, 1, 1), {cylinder={l=1, r=0.5}}))",
+    "build(Minkowski(Sphere(0.5), {cylinder={l=1, r=0.5}}))",
+];
+
+/// Deterministic pseudo-random numbers and points.
+struct Rng(u64);
+
+impl Rng {
+    /// Uniform in [0, 1).
+    fn unit(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in `b`.
+    fn point_in(&mut self, b: &Bbox) -> [f32; 3] {
+        std::array::from_fn(|i| b.min[i] + self.unit() * (b.max[i] - b.min[i]))
+    }
+
+    /// Uniform in the cube of half-size `r` about the origin.
+    fn point(&mut self, r: f32) -> [f32; 3] {
+        self.point_in(&Bbox {
+            min: [-r; 3],
+            max: [r; 3],
+        })
+    }
+}
+
+/// Run `check` on the object each of `SCENES` builds, with a generator seeded
+/// with `seed` for each.
+fn check_scenes(seed: u64, check: impl Fn(&str, &dyn Primitive, &mut Rng)) {
+    for script in SCENES {
+        let obj = eval_obj(script);
+        check(script, obj.as_ref(), &mut Rng(seed));
+    }
+}
+
+// ── interval evaluation ───────────────────────────────────────────────────────
+
+#[test]
+fn eval_stays_in_interval_bounds() {
+    check_scenes(0x1234_5678, |script, obj, rng| {
+        for _ in 0..200 {
+            let c = rng.point(4.);
+            let h: [f32; 3] = std::array::from_fn(|_| rng.unit() * 2.);
+            let bbox = Bbox {
+                min: std::array::from_fn(|i| c[i] - h[i]),
+                max: std::array::from_fn(|i| c[i] + h[i]),
+            };
+            let (lo, hi) = obj.eval_interval(&bbox);
+            assert!(lo <= hi, "{script}: empty interval");
+            for _ in 0..20 {
+                let p = rng.point_in(&bbox);
+                let v = obj.eval(p);
+                let tol = 1e-4 * (1. + v.abs());
+                assert!(
+                    lo - tol <= v && v <= hi + tol,
+                    "{script}: {v} not in [{lo}, {hi}] at {p:?}"
+                );
+            }
+        }
+    });
+}
+
+#[test]
+fn interval_proves_empty_and_full_regions() {
+    let obj = eval_obj("build(Sphere(1))");
+    let far = Bbox {
+        min: [3., 3., 3.],
+        max: [4., 4., 4.],
+    };
+    assert!(obj.eval_interval(&far).0 > 0.);
+    let core = Bbox {
+        min: [-0.2, -0.2, -0.2],
+        max: [0.2, 0.2, 0.2],
+    };
+    assert!(obj.eval_interval(&core).1 < 0.);
+}
+
+// ── gradients ─────────────────────────────────────────────────────────────────
+
+fn one_sided_difference(obj: &dyn Primitive, p: [f32; 3], e: f32) -> [f32; 3] {
+    std::array::from_fn(|i| {
+        let mut a = p;
+        a[i] += e;
+        (obj.eval(a) - obj.eval(p)) / e
+    })
+}
+
+/// `gradient` agrees with finite differences wherever the field is smooth
+/// (forward and backward differences agree), skipping points near a crease.
+#[test]
+fn gradient_matches_differences() {
+    check_scenes(0x9e37_79b9, |script, obj, rng| {
+        let mut checked = 0;
+        for _ in 0..300 {
+            let p = rng.point(3.);
+            let forward = one_sided_difference(obj, p, 1e-3);
+            let backward = one_sided_difference(obj, p, -1e-3);
+            if (0..3).any(|i| (forward[i] - backward[i]).abs() > 1e-2) {
+                continue;
+            }
+            let fine: [f32; 3] = std::array::from_fn(|i| 0.5 * (forward[i] + backward[i]));
+            let g = obj.gradient(p);
+            for i in 0..3 {
+                assert!(
+                    (g[i] - fine[i]).abs() < 2e-2,
+                    "{script}: {g:?} vs {fine:?} at {p:?}"
+                );
+            }
+            checked += 1;
+        }
+        assert!(checked > 100, "{script}: only {checked} smooth samples");
+    });
+}
+
+// ── batch evaluation ──────────────────────────────────────────────────────────
+
+#[test]
+fn eval_many_matches_eval() {
+    check_scenes(0x51ed_270b, |script, obj, rng| {
+        let points: Vec<_> = (0..257).map(|_| rng.point(4.)).collect();
+        let mut out = vec![0.0; points.len()];
+        obj.eval_many(&points, &mut out);
+        for (p, v) in points.iter().zip(&out) {
+            let e = obj.eval(*p);
+            assert!(
+                (v - e).abs() <= 1e-5 * (1. + e.abs()),
+                "{script}: {v} != {e} at {p:?}"
+            );
+        }
+    });
+}
+
+// ── tape ──────────────────────────────────────────────────────────────────────
+
+#[test]
+fn tape_matches_eval() {
+    check_scenes(0x51ed_270b, |script, obj, rng| {
+        let tape = Tape::compile(obj);
+        let points: Vec<_> = (0..257).map(|_| rng.point(4.)).collect();
+        let mut out = vec![0.0; points.len()];
+        tape.eval_many(&points, &mut out);
+        for (p, v) in points.iter().zip(&out) {
+            let e = obj.eval(*p);
+            let t = tape.eval(*p);
+            assert!(
+                (t - e).abs() <= 1e-5 * (1. + e.abs()),
+                "{script}: {t} != {e} at {p:?}"
+            );
+            assert!(
+                (v - e).abs() <= 1e-5 * (1. + e.abs()),
+                "{script}: {v} != {e} at {p:?}"
+            );
+        }
+    });
+}
+
+#[test]
+fn pruned_tape_matches_inside_region() {
+    let obj = eval_obj(
+        "local parts = {} \
+         for i = 1, 20 do parts[i] = Sphere(0.5):translate(i * 2, 0, 0) end \
+         build(Union(parts, 0.2))",
+    );
+    let tape = Tape::compile(obj.as_ref());
+    let region = Bbox {
+        min: [3.5, -1., -1.],
+        max: [4.5, 1., 1.],
+    };
+    let (pruned, range) = tape.prune(&region);
+    assert!(
+        pruned.len() * 4 < tape.len(),
+        "{} of {} ops kept",
+        pruned.len(),
+        tape.len()
+    );
+    let mut rng = Rng(7);
+    for _ in 0..200 {
+        let p = rng.point_in(&region);
+        let (e, v) = (tape.eval(p), pruned.eval(p));
+        assert!((e - v).abs() < 1e-6, "{e} != {v} at {p:?}");
+        assert!(range.lo <= e && e <= range.hi);
+    }
+}
+
+#[test]
+fn voxelize_matches_tree_eval() {
+    let obj = eval_obj("build(Difference({Box(3, 3, 3), Sphere(1.8)}, 0.2))");
+    let field = VoxelField::from_primitive(obj.as_ref(), 24, true).unwrap();
+    let b = field.bbox();
+    let c = b.center();
+    assert!((field.eval(c) - obj.eval(c)).abs() < 0.05);
+    let q = [b.min[0] + 0.3 * (b.max[0] - b.min[0]), c[1], c[2]];
+    assert!((field.eval(q) - obj.eval(q)).abs() < 0.05);
+}
+
+// ── shader ────────────────────────────────────────────────────────────────────
+
+#[test]
+fn shader_shares_repeated_subtrees() {
+    let bolt = "local bolt = Union({Cylinder({l=4, r=0.5}), Cylinder({l=1, r=1}):translate(0, 0, 2)}, 0.2) ";
+    let once = build_fragment_shader(eval_obj(&format!("{bolt} build(bolt)")).as_ref());
+    let many = build_fragment_shader(
+        eval_obj(&format!(
+            "{bolt} local parts = {{}} \
+             for i = 1, 20 do parts[i] = bolt:translate(i * 3, 0, 0) end \
+             build(Union(parts))"
+        ))
+        .as_ref(),
+    );
+    assert_eq!(many.matches("float sdShared0(vec3 p)").count(), 1);
+    assert_eq!(many.matches("sdShared0(p").count(), 20);
+    assert!(!many.contains("sdShared1"));
+    // Each further copy costs a translation and a call, not the whole bolt.
+    assert!(
+        many.len() < once.len() + 20 * 150,
+        "{} vs {}",
+        many.len(),
+        once.len()
+    );
+}
+
+#[test]
+fn shader_inlines_distinct_subtrees() {
+    let shader = build_fragment_shader(
+        eval_obj("build(Union({Box(1, 2, 3), Box(1, 2, 3):translate(3, 0, 0), Box(1, 2, 4)}))")
+            .as_ref(),
+    );
+    // The two equal boxes share a function, the third one is different.
+    assert_eq!(shader.matches("float sdShared").count(), 1);
+    assert_eq!(shader.matches("sdShared0(").count(), 3);
+}
+
+#[test]
+fn shader_defines_each_function_once() {
+    let shader = build_shader(
+        eval_obj(
+            "local part = Union({Blobs({{0,0,0,1}, {1,0,0,1}}, 0.5), Sphere(0.5)}, 0.2) \
+             build(Union({part, part:translate(4, 0, 0), Blobs({{0,0,0,2}}, 0.5):translate(0, 5, 0)}))",
+        )
+        .as_ref(),
+    );
+    let mut names: Vec<&str> = shader
+        .source
+        .lines()
+        .filter(|l| l.starts_with("float ") && l.ends_with('{'))
+        .map(|l| &l[6..l.find('(').unwrap()])
+        .collect();
+    let defined = names.len();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), defined, "{names:?}");
+    for name in ["sdShared0", "sdBlobs0", "sdBlobs1"] {
+        assert!(names.contains(&name), "{name} missing from {names:?}");
+    }
+    assert_eq!(shader.data.len(), 2);
+}
+
+#[test]
+fn shader_reads_blob_balls_from_a_uniform() {
+    let shader =
+        |balls: &str| build_shader(eval_obj(&format!("build(Blobs({balls}, 0.5))")).as_ref());
+    let a = shader("{{0,0,0,1.5}, {1,0,0,1}}");
+    let b = shader("{{0,0,0,1.5}, {2,0,0,1}}");
+    assert_eq!(a.source, b.source, "moving a ball keeps the source");
+    assert!(a.source.contains("uniform vec4 blobs0[2];"));
+    let balls = vec![[0.0, 0.0, 0.0, 1.5], [1.0, 0.0, 0.0, 1.0]];
+    assert_eq!(
+        a.data,
+        [("blobs0".to_string(), ShaderData::Vec4Array(balls))]
+    );
+}
+
+#[test]
+fn shader_reads_voxels_from_a_texture() {
+    let shader = build_shader(eval_obj("build(Voxelize(Sphere(2), 32))").as_ref());
+    assert!(shader.source.contains("uniform highp sampler3D voxels0;"));
+    assert!(
+        shader.source.len() < 20_000,
+        "{} bytes",
+        shader.source.len()
+    );
+    let [(name, ShaderData::Texture3d { dims, values })] = &shader.data[..] else {
+        panic!("expected one texture");
+    };
+    assert_eq!(name, "voxels0");
+    assert_eq!(values.len(), dims.iter().product::<usize>());
+}
+
+#[test]
+fn materials_keep_the_geometry() {
+    let plain = eval_obj("build(Sphere(1) + Box(1, 1, 3))");
+    let colored = eval_obj(
+        "build(Sphere(1):color(1, 0, 0) + Box(1, 1, 3):material{color=vec3(0, 0, 1), specular=0.5})",
+    );
+    for p in [[0., 0., 0.], [0.9, 0., 0.], [0., 0., 1.4], [2., 2., 2.]] {
+        assert_eq!(
+            val(plain.as_ref(), p[0], p[1], p[2]),
+            val(colored.as_ref(), p[0], p[1], p[2])
+        );
+    }
+    let shader = build_fragment_shader(colored.as_ref());
+    assert!(shader.contains("vec4 mapMaterial(vec3 p)"));
+    assert!(shader.contains("vec4(1.00000000, 0.00000000, 0.00000000, 0.00000000)"));
+    assert!(shader.contains("vec4(0.00000000, 0.00000000, 1.00000000, 0.50000000)"));
+}
+
+#[test]
+fn shader_material_defaults_without_colors() {
+    let shader = build_fragment_shader(eval_obj("build(Sphere(1) - Box(1, 1, 1))").as_ref());
+    assert!(shader.contains("vec4 mapMaterial(vec3 p) {\n    return vec4(0.7, 0.7, 0.7, 0.0);\n}"));
+    assert!(!shader.contains("mixMaterial"));
+}
+
+#[test]
+fn shader_blends_materials() {
+    let smooth = build_fragment_shader(
+        eval_obj("build(Sphere(1):color(1, 0, 0):smooth(0.5) + Sphere(1):translate(1, 0, 0))")
+            .as_ref(),
+    );
+    assert!(smooth.contains("mixMaterial("));
+    let sharp = build_fragment_shader(
+        eval_obj("build(Box(2, 2, 2) - Sphere(1):translate(1, 0, 0):color(0, 1, 0))").as_ref(),
+    );
+    assert!(!sharp.contains("mixMaterial("));
+    assert!(sharp.contains("? vec4(0.7, 0.7, 0.7, 0.0) :"));
+}
+
+#[test]
+fn shader_shares_repeated_colored_subtrees() {
+    let shader = build_fragment_shader(
+        eval_obj(
+            "local bolt = Union({Cylinder({l=4, r=0.5}), Cylinder({l=1, r=1}):translate(0, 0, 2)}):color(1, 0, 0)
+             local parts = {}
+             for i = 1, 5 do parts[i] = bolt:translate(i * 3, 0, 0) end
+             build(Union(parts))",
+        )
+        .as_ref(),
+    );
+    // Once for each copy, in mapScene(), which map() and mapMaterial() share.
+    assert_eq!(shader.matches("float sdShared0(vec3 p)").count(), 1);
+    assert_eq!(shader.matches("sdShared0(p").count(), 5);
+    assert_eq!(shader.matches("mapScene(p, m, o)").count(), 2);
+}
+
+#[test]
+fn body_colors_reach_the_shader() {
+    let (_, bodies, _) =
+        eval("build(Sphere(1), {name='a', color={0, 1, 0}}) build(Sphere(1):translate(3, 0, 0), {name='b'})")
+            .unwrap();
+    let shader = build_fragment_shader(merge_bodies(&bodies).unwrap().as_ref());
+    assert!(shader.contains("vec4(0.00000000, 1.00000000, 0.00000000, 0.00000000)"));
+}
+
+#[test]
+fn material_arguments_are_validated() {
+    for (script, msg) in [
+        (
+            "build(Sphere(1):color(2, 0, 0))",
+            "color components must be between 0 and 1",
+        ),
+        (
+            "build(Sphere(1):color('red'))",
+            "color expects a vec3 or three numbers",
+        ),
+        (
+            "build(Sphere(1):material{color={1, 0}})",
+            "material color must be a vec3 or a table {r,g,b}",
+        ),
+        (
+            "build(Sphere(1):material{specular=2})",
+            "specular must be a number between 0 and 1",
+        ),
+        (
+            "build(Sphere(1):material{shine=1})",
+            "material keys must be color or specular",
+        ),
+    ] {
+        let err = eval(script).err().unwrap().to_string();
+        assert!(err.contains(msg), "{script}: {err}");
+    }
+}
+
+/// The body of the function computing the distance, `mapScene()` where the
+/// scene has materials or markers and `map()` otherwise, and the variable it
+/// returns.
+fn scene_function(shader: &str) -> (&str, &str) {
+    let start = shader
+        .find("float mapScene(vec3 p")
+        .or_else(|| shader.find("float map(vec3 p)"))
+        .unwrap();
+    let function = &shader[start..];
+    let (body, rest) = function.split_once("\n    return ").unwrap();
+    let body = &body[body.find('{').unwrap()..];
+    let result = rest.split(';').next().unwrap();
+    (body, result)
+}
+
+#[test]
+fn markers_keep_the_model() {
+    let plain = eval_obj("build(Box(2, 2, 2) - Sphere(1.2))");
+    for mark in ["debug", "ghost", "only"] {
+        let marked = eval_obj(&format!("build(Box(2, 2, 2) - Sphere(1.2):{mark}())"));
+        for p in [[0., 0., 0.], [0.9, 0.9, 0.9], [2., 0., 0.]] {
+            assert_eq!(
+                val(plain.as_ref(), p[0], p[1], p[2]),
+                val(marked.as_ref(), p[0], p[1], p[2])
+            );
+        }
+        let len = |o: &dyn Primitive| tessellate_stl(o).unwrap().len();
+        assert_eq!(len(plain.as_ref()), len(marked.as_ref()), "{mark}");
+    }
+}
+
+#[test]
+fn shader_overlays_subtracted_markers() {
+    let plain = build_fragment_shader(eval_obj("build(Box(2, 2, 2) - Sphere(1.2))").as_ref());
+    assert!(!plain.contains("#define OVERLAY"));
+    assert!(!plain.contains("vec2 mapOverlay(vec3 p)"));
+
+    let shader = build_fragment_shader(
+        eval_obj(
+            "build(Box(2, 2, 2) - Sphere(1.2):debug() - Sphere(1):translate(1, 0, 0):ghost())",
+        )
+        .as_ref(),
+    );
+    assert!(shader.contains("#define OVERLAY\nvec2 mapOverlay(vec3 p)"));
+    // The overlay has the spheres' own distances, not the negated ones the
+    // difference uses.
+    let (scene, result) = scene_function(&shader);
+    let ret = scene[scene.find("overlay = vec2(").unwrap()..]
+        .lines()
+        .next()
+        .unwrap();
+    let (debug, ghost) = ret
+        .trim_start_matches("overlay = vec2(min(1e10, ")
+        .split_once("), min(1e10, ")
+        .unwrap();
+    let ghost = ghost.trim_end_matches("));");
+    assert!(
+        scene.contains(&format!("float {debug} = length(p) - 1.20000005;")),
+        "{ret}"
+    );
+    assert!(scene.contains(&format!("float {ghost} = length(")), "{ret}");
+    // The scene itself is drawn as before.
+    let unmarked = build_fragment_shader(
+        eval_obj("build(Box(2, 2, 2) - Sphere(1.2) - Sphere(1):translate(1, 0, 0))").as_ref(),
+    );
+    let distance = |body: &str| {
+        let lines = body
+            .lines()
+            .skip(1)
+            .filter(|l| !l.contains("material =") && !l.contains("overlay ="));
+        lines.map(str::to_string).collect::<Vec<_>>()
+    };
+    assert_eq!(
+        (distance(scene), result),
+        (
+            distance(scene_function(&unmarked).0),
+            scene_function(&unmarked).1
+        )
+    );
+}
+
+#[test]
+fn shader_marks_repeated_subtrees() {
+    let shader = build_fragment_shader(
+        eval_obj(
+            "local part = Union({Sphere(1), Box(1, 1, 3)}):debug()
+             build(part:translate(2, 0, 0) + part:translate(-2, 0, 0))",
+        )
+        .as_ref(),
+    );
+    let (scene, _) = scene_function(&shader);
+    assert!(scene.contains("overlay = vec2(min(min(1e10, "), "{scene}");
+}
+
+#[test]
+fn shader_marks_in_scene_units() {
+    // The overlay and `only` distances take the scaling of the ancestors.
+    let shader = build_fragment_shader(
+        eval_obj("build(Sphere(1):debug():scale(0.25, 0.5, 0.5):translate(1, 0, 0))").as_ref(),
+    );
+    let (scene, _) = scene_function(&shader);
+    assert!(scene.contains(" * 0.25000000), 1e10);"), "{scene}");
+    let shader = build_fragment_shader(
+        eval_obj("build(Sphere(1):only():scale(2, 2, 2) + Sphere(1):translate(0, 3, 0):only())")
+            .as_ref(),
+    );
+    let (scene, _) = scene_function(&shader);
+    assert!(scene.contains(" * 2.00000000 <= "), "{scene}");
+
+    // Markers inside a Minkowski sum that loops over its samples show too.
+    let shader = build_fragment_shader(
+        eval_obj("build(Minkowski(Box(1, 1, 1):debug():color(1, 0, 0), {cylinder={l=1, r=0.5}}))")
+            .as_ref(),
+    );
+    let (scene, _) = scene_function(&shader);
+    let overlay = scene[scene.find("overlay = vec2(min(1e10, ").unwrap() + 25..]
+        .split(')')
+        .next()
+        .unwrap();
+    assert!(
+        scene.contains(&format!("float {overlay} = 1e10;")),
+        "{scene}"
+    );
+    assert!(
+        scene.contains(&format!("{overlay} = min({overlay}, ")),
+        "{scene}"
+    );
+}
+
+#[test]
+fn shader_only_isolates_subtrees() {
+    let shader = build_fragment_shader(
+        eval_obj("build(Box(4, 4, 1) - Sphere(1):color(1, 0, 0):only() + Box(1, 1, 1):translate(5, 0, 0))").as_ref(),
+    );
+    let (body, result) = scene_function(&shader);
+    assert!(
+        body.contains(&format!("float {result} = length(p) - 1.00000000;")),
+        "{body}"
+    );
+    assert!(shader.contains("vec4(1.00000000, 0.00000000, 0.00000000, 0.00000000)"));
+
+    let shader = build_fragment_shader(
+        eval_obj("build(Sphere(1):only() + Sphere(2):translate(5, 0, 0) + Sphere(0.5):translate(0, 3, 0):only())")
+            .as_ref(),
+    );
+    let (body, result) = scene_function(&shader);
+    assert_eq!(result, "only_d1");
+    assert!(body.contains("float only_d1 = min("));
+    assert!(!shader.contains("#define OVERLAY"));
+}
+
+// ── parameters ────────────────────────────────────────────────────────────────
+
+#[test]
+fn params_are_listed_with_overrides() {
+    let script = "local r = param('r', 1.5, {min=1, max=3, step=0.5}) \
+                  local h = param('h', 2) build(Sphere(r):translate(0, 0, h))";
+    let (_, _, params) = eval(script).unwrap();
+    assert_eq!(params.len(), 2);
+    assert_eq!(params[0].name, "r");
+    assert_eq!(
+        (&params[0].value, &params[0].default),
+        (&Number(1.5), &Number(1.5))
+    );
+    assert_eq!(
+        params[0].kind,
+        ParamKind::Number {
+            min: Some(1.0),
+            max: Some(3.0),
+            step: Some(0.5)
+        }
+    );
+    assert_eq!(
+        params[1].kind,
+        ParamKind::Number {
+            min: None,
+            max: None,
+            step: None
+        }
+    );
+
+    let overrides = HashMap::from([
+        ("r".to_string(), Number(9.0)),
+        ("h".to_string(), Number(-1.0)),
+    ]);
+    let (_, bodies, params) = eval_with_params(script, &overrides).unwrap();
+    assert_eq!(
+        (&params[0].value, &params[1].value),
+        (&Number(3.0), &Number(-1.0))
+    );
+    assert!(val(merge_bodies(&bodies).unwrap().as_ref(), 0., 0., -1.).abs() > 2.9);
+}
+
+#[test]
+fn params_become_uniforms() {
+    let script = "local w = param('w', 2, {live=true}) build(Box(w, 1, 1):translate(0, w, 0))";
+    let (_, bodies, params) = eval(script).unwrap();
+    assert!(params[0].live);
+    let shader = build_fragment_shader(merge_bodies(&bodies).unwrap().as_ref());
+    assert!(shader.contains("uniform float u_w;"));
+    assert!(shader.contains("(u_w / 2.00000000)"));
+    assert!(shader.contains("vec3(0.00000000, u_w, 0.00000000)"));
+}
+
+#[test]
+fn params_bind_by_identity() {
+    let shader = |script: &str| {
+        let (_, bodies, params) = eval(script).unwrap();
+        (
+            build_fragment_shader(merge_bodies(&bodies).unwrap().as_ref()),
+            params[0].live,
+        )
+    };
+    // A plain number equal to the parameter is not the parameter.
+    let (same_value, _) = shader(
+        "local w = param('w', 2, {live=true}) build(Sphere(w) + Sphere(2):translate(5, 0, 0))",
+    );
+    assert_eq!(same_value.matches("u_w").count(), 2, "{same_value}");
+    let (plain, live) = shader("local w = param('w', 2) build(Sphere(w))");
+    assert!(!plain.contains("u_w") && !live);
+    // Arithmetic on a live parameter stays live.
+    let (scaled, live) = shader("local w = param('w', 2, {live=true}) build(Sphere(w * 2 - 1))");
+    assert!(
+        scaled.contains("((u_w * 2.00000000) - 1.00000000)") && live,
+        "{scaled}"
+    );
+}
+
+#[test]
+fn params_used_as_plain_numbers_are_baked() {
+    let script = "local r = param('r', 1, {live=true}) local s = param('s', 0.2, {live=true}) \
+                  build(Union({Sphere(r), Sphere(tonumber(r) * 2):translate(3, 0, 0)}, tonumber(s)))";
+    let (_, bodies, params) = eval(script).unwrap();
+    assert!(!params[0].live && !params[1].live);
+    assert!(build_fragment_shader(merge_bodies(&bodies).unwrap().as_ref()).contains("u_r"));
+    assert!(eval("param('n', 1, {type='integer', live=true})").is_err());
+    assert!(eval("local w = param('w', 1, {live=true}) build(Sphere(1):rotate(w, 0, 0))").is_ok());
+}
+
+#[test]
+fn params_are_baked_where_the_script_reads_them() {
+    let live = |uses: &str| {
+        let script =
+            format!("local w = param('w', 2, {{live=true}}) local s = Sphere(w) {uses} build(s)");
+        eval(&script).unwrap().2[0].live
+    };
+    assert!(live(""));
+    assert!(live("print(w, -w) Box(1, 1, 1):bbox()"));
+    assert!(!live("local v = vec3(1, 1, 1) * w"));
+    assert!(!live("s = s:place_on(Box(4, 4, 1))"));
+    assert!(!live("s:bbox()"));
+    assert!(!live("s = Box(4, 4, 1):place_on(s)"));
+    assert!(!live("Stack({s, Box(1, 1, 1)})"));
+    assert!(!live("Voxelize(s:translate(w, 0, 0), 8)"));
+
+    // Finding that out takes no second run of the script.
+    let slices = |opts: &str| {
+        let script = format!(
+            "local w = param('w', 2, {opts}) local n = 0 for i = 1, 20000 do n = n + i end build(Sphere(w))"
+        );
+        let mut evaluation =
+            Evaluation::new(&script, &Modules::new(), &HashMap::new(), Limits::default());
+        (1..).find(|_| evaluation.step(1000).is_ready()).unwrap()
+    };
+    assert_eq!(slices("{live=true}"), slices("{}"));
+}
+
+#[test]
+fn typed_params_carry_their_schema() {
+    let script = r#"
+        local n = param("teeth", 12, {type="integer", min=6, max=40, group="Gear"})
+        local hollow = param("hollow", false, {description="Cut out the middle"})
+        local size = param("size", "M", {options={"S", "M", "L"}, group="Gear"})
+        local label = param("label", "hi")
+        print(n, hollow, size, label)
+    "#;
+    let (output, _, params) = eval(script).unwrap();
+    assert_eq!(output, "12\tfalse\tM\thi\n");
+    let kinds: Vec<&str> = params.iter().map(|p| p.kind.name()).collect();
+    assert_eq!(kinds, ["integer", "boolean", "enum", "string"]);
+    assert_eq!(
+        params[0].kind,
+        ParamKind::Integer {
+            min: Some(6),
+            max: Some(40)
+        }
+    );
+    assert_eq!(params[1].description.as_deref(), Some("Cut out the middle"));
+    assert_eq!(
+        (params[0].group.as_deref(), params[1].group.as_deref()),
+        (Some("Gear"), None)
+    );
+    let sizes = ["S", "M", "L"].map(|s| Text(s.to_string())).to_vec();
+    assert_eq!(params[2].kind, ParamKind::Enum(sizes));
+    assert!(params.iter().all(|p| !p.live));
+
+    let overrides = HashMap::from([
+        ("teeth".to_string(), Number(50.4)),
+        ("hollow".to_string(), Boolean(true)),
+        ("size".to_string(), Text("XL".to_string())),
+        ("label".to_string(), Number(3.0)),
+    ]);
+    let (output, _, _) = eval_with_params(script, &overrides).unwrap();
+    // Out-of-range numbers are clamped; values of the wrong type or outside
+    // the choices leave the default.
+    assert_eq!(output, "40\ttrue\tM\thi\n");
+}
+
+#[test]
+fn params_are_validated() {
+    assert!(eval("param('a', 1) param('a', 2)").is_err());
+    assert!(eval("param('1a', 1)").is_err());
+    assert!(eval("param('a__b', 1)").is_err());
+    assert!(eval("param('a', {})").is_err());
+    assert!(eval("param('a', 'x', {type='number'})").is_err());
+    assert!(eval("param('a', 5, {max=4})").is_err());
+    assert!(eval("param('a', 1, {step=0})").is_err());
+    assert!(eval("param('a', 1.5, {type='integer'})").is_err());
+    assert!(eval("param('a', 1, {type='integer', step=2})").is_err());
+    assert!(eval("param('a', 1, {type='boolean'})").is_err());
+    assert!(eval("param('a', 'x', {options={'y', 'z'}})").is_err());
+    assert!(eval("param('a', 'x', {options={}})").is_err());
+    assert!(eval("param('a', 'x', {options={'x', true}})").is_err());
+    assert!(eval("param('a', 1, {description=2})").is_err());
+    assert!(eval("param('a', 1, {type='color'})").is_err());
+}
+
+#[test]
+fn enum_options_are_validated_by_the_host() {
+    // The same checks hold for a script calling the host function directly.
+    let declare = |default: &str, options: &str| {
+        let script = format!("__param('a', 'enum', {default}, nil, nil, nil, nil, nil, {options})");
+        eval(&script).map(|_| ()).map_err(|e| e.to_string())
+    };
+    assert!(declare("'x'", "{'x', 2}").is_ok());
+    let err = declare("'x'", "{'x', true}").unwrap_err();
+    assert!(err.contains("option 2 must be a number or string"), "{err}");
+    let err = declare("'x'", "{'x', {}, 'y'}").unwrap_err();
+    assert!(err.contains("option 2 must be a number or string"), "{err}");
+    let err = declare("'z'", "{'x', 'y'}").unwrap_err();
+    assert!(err.contains("default must be one of the options"), "{err}");
+    let err = declare("'x'", "{}").unwrap_err();
+    assert!(err.contains("options must be a non-empty list"), "{err}");
+}
+
+// ── diagnostics ───────────────────────────────────────────────────────────────
+
+fn diagnose(script: &str) -> Diagnostic {
+    diagnose_with_modules(script, &Modules::new())
+}
+
+fn diagnose_with_modules(script: &str, modules: &Modules) -> Diagnostic {
+    match eval_with_modules(script, modules) {
+        Err(EvalError::Script(d)) => d,
+        other => panic!("expected a script error, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn syntax_errors_have_a_line() {
+    let d = diagnose("local a = 1\nx = = 2");
+    assert_eq!((d.chunk.as_deref(), d.line), (Some("script"), Some(2)));
+}
+
+#[test]
+fn factory_errors_name_the_call() {
+    let d = diagnose("local s = Sphere(1)\nlocal b = Box(1, 'a', 2)");
+    assert_eq!(d.message, "all arguments must be numbers");
+    assert_eq!((d.line, d.column), (Some(2), Some(11)));
+    assert_eq!((d.function.as_deref(), d.argument), (Some("Box"), Some(2)));
+    assert_eq!(d.to_string(), "script:2: all arguments must be numbers");
+
+    // Errors from Rust callbacks and methods are located too.
+    let d = diagnose("u = Union({1, 2})");
+    assert_eq!(
+        (d.line, d.column, d.function.as_deref()),
+        (Some(1), Some(5), Some("Union"))
+    );
+    let d = diagnose("local s = Sphere(1):translate('a', 0, 0)");
+    assert_eq!(
+        (d.column, d.function.as_deref()),
+        (Some(21), Some("translate"))
+    );
+}
+
+#[test]
+fn error_calls_carry_a_traceback() {
+    let d = diagnose("local function f()\n  error('deep')\nend\nf()");
+    assert_eq!(
+        (d.message.as_str(), d.line, d.column),
+        ("deep", Some(2), Some(3))
+    );
+    assert_eq!(
+        d.traceback,
+        ["in function 'error'", "script:2: in function 'f'"]
+    );
+
+    // A caught error does not locate a later one.
+    let d = diagnose("pcall(Box, 'a')\nlocal x = nil + 1");
+    assert_eq!((d.line, d.column, d.function), (Some(2), None, None));
+}
+
+#[test]
+fn vm_errors_are_located() {
+    // Errors the VM raises itself have the line of the statement.
+    let d = diagnose("local a = 1\nlocal y = x + 1");
+    assert_eq!(d.message, "attempt to perform arithmetic on a nil value");
+    assert_eq!(
+        (d.chunk.as_deref(), d.line, d.column),
+        (Some("script"), Some(2), None)
+    );
+    let d = diagnose("local t = {}\n\nt:nosuch()");
+    assert_eq!((d.chunk.as_deref(), d.line), (Some("script"), Some(3)));
+    let d = diagnose("local t\nwhile true do\n  t = t.next\nend");
+    assert_eq!(d.line, Some(3));
+    let d = diagnose("local n = 1\nlocal m = n // 0");
+    assert_eq!(d.line, Some(2));
+
+    // A function that returned leaves the line of its caller.
+    let d = diagnose("local function f()\n  return 1\nend\nlocal y = f() + nil");
+    assert_eq!(d.line, Some(4));
+    let d = diagnose("local function f()\n  local a = {}\n  return a\nend\nlocal y = f().b.c");
+    assert_eq!(d.line, Some(5));
+
+    // Modules are located in their own chunk.
+    let mut modules = Modules::new();
+    modules.insert(
+        "lib".to_string(),
+        "local M = {}\nfunction M.f(t)\n  return t.x.y\nend\nreturn M".to_string(),
+    );
+    let d = diagnose_with_modules("local lib = require('lib')\nlib.f({})", &modules);
+    assert_eq!((d.chunk.as_deref(), d.line), (Some("lib"), Some(3)));
+}
+
+#[test]
+fn bodies_may_end_in_a_label() {
+    // Line markers go before a label that ends a loop or function body, which a
+    // goto past a local may only reach while it is the last statement.
+    let script = "local i = 0
+        while i < 3 do
+            i = i + 1
+            if i == 2 then goto continue end
+            local y = i * 2
+            print(y)
+            ::continue::
+        end
+        local function f(x)
+            if x then goto done end
+            local y = 1
+            print(y)
+            ::done::
+        end
+        f(true)
+        f(false)";
+    let (output, _, _) = eval(script).expect("script failed");
+    assert_eq!(output, "2\n6\n1\n");
+
+    // After such a body the condition and the caller are still located.
+    let d = diagnose("local t = {n = 1}\nwhile t.n < 2 do\n  t = {}\n  ::next::\nend");
+    assert_eq!(d.line, Some(2));
+    let d = diagnose("local function f()\n  local y = 1\n  ::done::\nend\nf()\nlocal z = nil + 1");
+    assert_eq!(d.line, Some(6));
+    let d = diagnose("while true do\n  return nil + 1\nend");
+    assert_eq!(d.line, Some(2));
+}
+
+#[test]
+fn repeated_calls_have_no_column() {
+    let d = diagnose("local u = Union({Sphere(1)}) + Union({2})");
+    assert_eq!(
+        (d.line, d.column, d.function.as_deref()),
+        (Some(1), None, Some("Union"))
+    );
+    let d = diagnose("local u = Sphere(1) + Union({2})");
+    assert_eq!((d.column, d.function.as_deref()), (Some(23), Some("Union")));
+}
+
+#[test]
+fn list_errors_blame_the_list() {
+    let d = diagnose("local s = Stack({Sphere(1), 5})");
+    assert_eq!(d.message, "expected an object at position 2 in list");
+    assert_eq!(
+        (d.line, d.function.as_deref(), d.argument),
+        (Some(1), Some("Stack"), Some(1))
+    );
+    let d = diagnose("local u = Union({Sphere(1), {}}, 0.5)");
+    assert_eq!(
+        (d.function.as_deref(), d.argument),
+        (Some("Union"), Some(1))
+    );
+}
+
+// ── execution limits ──────────────────────────────────────────────────────────
+
+fn eval_limited(script: &str, fuel: u64, memory: usize) -> EvalResult {
+    Evaluation::new(
+        script,
+        &Modules::new(),
+        &HashMap::new(),
+        Limits { fuel, memory },
+    )
+    .finish()
+}
+
+#[test]
+fn endless_scripts_run_out_of_fuel() {
+    let result = eval_limited("while true do end", 100_000, usize::MAX);
+    assert!(matches!(result, Err(EvalError::OutOfFuel(100_000))));
+    let result = eval_limited(
+        "local function f() return f() + 1 end f()",
+        100_000,
+        16 << 20,
+    );
+    assert!(matches!(
+        result,
+        Err(EvalError::OutOfFuel(_) | EvalError::OutOfMemory(_))
+    ));
+    let message = eval_limited("while true do end", 1000, usize::MAX)
+        .err()
+        .unwrap()
+        .to_string();
+    assert!(message.starts_with("script exceeded budget"));
+
+    // Voxels count against the budget like instructions.
+    let script = "for i = 1, 10 do Voxelize(Sphere(1), 40) end";
+    assert!(matches!(
+        eval_limited(script, 200_000, usize::MAX),
+        Err(EvalError::OutOfFuel(_))
+    ));
+    assert!(eval_limited(script, 2_000_000, usize::MAX).is_ok());
+}
+
+#[test]
+fn scripts_have_a_memory_cap() {
+    let script = "local t = {} for i = 1, 1e9 do t[i] = {i} end";
+    let result = eval_limited(script, u64::MAX, 4 << 20);
+    assert!(matches!(result, Err(EvalError::OutOfMemory(_))));
+}
+
+#[test]
+fn scripts_run_in_slices() {
+    let script = "local n = 0 for i = 1, 20000 do n = n + i end print(n) build(Sphere(1))";
+    let mut evaluation =
+        Evaluation::new(script, &Modules::new(), &HashMap::new(), Limits::default());
+    let mut slices = 1;
+    let result = loop {
+        match evaluation.step(1000) {
+            Poll::Ready(result) => break result,
+            Poll::Pending => slices += 1,
+        }
+    };
+    assert!(slices > 10);
+    let (output, bodies, _) = result.unwrap();
+    assert_eq!(output, "200010000\n");
+    assert_eq!(bodies.len(), 1);
+}
+
 // ── sandbox security ──────────────────────────────────────────────────────────
 
 #[test]
@@ -246,3 +2103,162 @@ fn sandbox_blocks_load() {
 fn sandbox_blocks_dofile() {
     assert!(eval("dofile('/etc/passwd')").is_err());
 }
+
+// ── modules ───────────────────────────────────────────────────────────────────
+
+fn modules(sources: &[(&str, &str)]) -> Modules {
+    sources
+        .iter()
+        .map(|&(name, source)| (name.to_string(), source.to_string()))
+        .collect()
+}
+
+#[test]
+fn required_modules_load_once() {
+    let modules = modules(&[
+        (
+            "shapes",
+            "local base = require('base')\nreturn {size = base + 1}",
+        ),
+        ("base", "print('loading base')\nreturn 41"),
+    ]);
+    let script =
+        "print(require('shapes').size, require('base'), require('shapes') == require('shapes'))";
+    let (output, _, _) = eval_with_modules(script, &modules).unwrap();
+    assert_eq!(output, "loading base\n42\t41\ttrue\n");
+}
+
+#[test]
+fn scripts_cannot_reach_the_module_cache() {
+    let modules = modules(&[("base", "return 41")]);
+    let script = "__loaded = {base = 0}\nprint(require('base'))";
+    let (output, _, _) = eval_with_modules(script, &modules).unwrap();
+    assert_eq!(output, "41\n");
+
+    // The evaluator's own chunk names are not module names.
+    let reserved = self::modules(&[("script", "return 1"), ("prelude", "return 2")]);
+    for name in ["script", "prelude"] {
+        let d = diagnose_with_modules(&format!("require('{name}')"), &reserved);
+        assert_eq!(d.message, format!("module name '{name}' is reserved"));
+    }
+}
+
+#[test]
+fn required_modules_get_their_name() {
+    let modules = modules(&[("named", "local name = ...\nreturn name")]);
+    let (output, _, _) = eval_with_modules("print(require('named'))", &modules).unwrap();
+    assert_eq!(output, "named\n");
+}
+
+fn part(call: &str) -> Box<dyn Primitive> {
+    let script = format!("local parts = require('truescad.parts')\nbuild({call})");
+    let bodies = eval(&script).unwrap_or_else(|e| panic!("{call}: {e}")).1;
+    merge_bodies(&bodies).expect("no object built")
+}
+
+#[test]
+fn bundled_parts_are_bounded() {
+    let calls = [
+        "parts.bolt{}",
+        "parts.bolt{size='M8', head='hex', length=20}",
+        "parts.bolt{size='M2.5', head='button'}",
+        "parts.bolt{size='M4', head='countersunk'}",
+        "parts.nut{size='M5'}",
+        "parts.washer{size='M10'}",
+        "parts.bolt_hole{fit='loose'}",
+        "parts.bolt_hole{head='socket', sink=1}",
+        "parts.countersink{size='M6', length=12}",
+        "parts.nut_trap{slot=10}",
+        "parts.insert_hole{size='M2'}",
+        "parts.standoff{}",
+        "parts.standoff{size='M10', hole='tap'}",
+        "parts.bearing{bearing='625'}",
+        "parts.bearing_seat{relief=3}",
+        "parts.snap_clip{}",
+        "Union({parts.hinge{}})",
+    ];
+    for call in calls {
+        let bb = part(call)
+            .bbox()
+            .finite()
+            .unwrap_or_else(|e| panic!("{call}: {e}"));
+        assert!(bb.width() > 0.0, "{call} is empty");
+    }
+}
+
+#[test]
+fn bundled_parts_have_their_sizes() {
+    let nut = part("parts.nut{size='M8'}");
+    assert_bbox_near(
+        nut.bbox(),
+        [-13.0 / 3f32.sqrt(), -6.5, 0.0],
+        [13.0 / 3f32.sqrt(), 6.5, 6.8],
+    );
+    assert!(nut.eval([0.0, 0.0, 3.0]) > 0.0, "nut has a hole");
+
+    let bolt = part("parts.bolt{size='M3', length=12}");
+    assert_bbox_near(bolt.bbox(), [-2.75, -2.75, -12.0], [2.75, 2.75, 3.0]);
+
+    let hole = part("parts.bolt_hole{size='M3', length=5}");
+    assert!(hole.eval([1.65, 0.0, -1.0]) < 0.0 && hole.eval([1.75, 0.0, -1.0]) > 0.0);
+}
+
+#[test]
+fn hinge_halves_interleave() {
+    let script = "local parts = require('truescad.parts')\nlocal a, b = parts.hinge{}\nbuild(HALF)";
+    let a = merge_bodies(&eval(&script.replace("HALF", "a")).unwrap().1).unwrap();
+    let b = merge_bodies(&eval(&script.replace("HALF", "b")).unwrap().1).unwrap();
+    // the first knuckle belongs to a, the second to b; a's pin runs through both
+    for (p, in_a, in_b) in [
+        ([-12.2, 0.0, 2.5], true, false),
+        ([-6.4, 0.0, 2.5], false, true),
+        ([-6.4, 0.0, 0.0], true, false),
+    ] {
+        assert_eq!((a.eval(p) < 0.0, b.eval(p) < 0.0), (in_a, in_b), "at {p:?}");
+    }
+}
+
+#[test]
+fn bundled_part_errors_point_at_the_script() {
+    let d = diagnose("local parts = require('truescad.parts')\nlocal b = parts.bolt{size='M7'}");
+    assert_eq!(
+        d.message,
+        "size must be M2, M2.5, M3, M4, M5, M6, M8 or M10"
+    );
+    assert_eq!(
+        (d.chunk.as_deref(), d.line, d.column),
+        (Some("script"), Some(2), Some(17))
+    );
+    assert_eq!((d.function.as_deref(), d.argument), (Some("bolt"), Some(1)));
+}
+
+#[test]
+fn require_reports_missing_modules() {
+    let d = diagnose("require('nope')");
+    assert_eq!(d.message, "module 'nope' not found");
+    assert_eq!((d.chunk.as_deref(), d.line), (Some("script"), Some(1)));
+}
+
+#[test]
+fn require_detects_cycles() {
+    let modules = modules(&[("a", "return require('b')"), ("b", "return require('a')")]);
+    assert_eq!(
+        diagnose_with_modules("require('a')", &modules).message,
+        "module 'a' requires itself: a -> b -> a"
+    );
+}
+
+#[test]
+fn module_errors_point_into_the_module() {
+    let modules = modules(&[("broken", "local s = Sphere(1)\nlocal b = Box('q', 1, 1)")]);
+    let d = diagnose_with_modules("require('broken')", &modules);
+    assert_eq!(
+        (d.chunk.as_deref(), d.line, d.column),
+        (Some("broken"), Some(2), Some(11))
+    );
+    assert_eq!(d.traceback.last().unwrap(), "script:1: in main chunk");
+
+    let modules = self::modules(&[("typo", "x = = 1")]);
+    let d = diagnose_with_modules("local ok = 1\nrequire('typo')", &modules);
+    assert_eq!((d.chunk.as_deref(), d.line), (Some("typo"), Some(1)));
+}
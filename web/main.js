@@ -1,12 +1,14 @@
 import { EditorView, basicSetup } from "codemirror";
 import { StreamLanguage } from "@codemirror/language";
+import { setDiagnostics } from "@codemirror/lint";
 import { lua } from "@codemirror/legacy-modes/mode/lua";
 import { oneDark } from "@codemirror/theme-one-dark";
 import * as THREE from "three";
 import { STLLoader } from "three/addons/loaders/STLLoader.js";
 import { OrbitControls } from "three/addons/controls/OrbitControls.js";
-import init, { run_script, get_shader_source, get_world_transform, get_object_width,
-               rotate, pan, tessellate } from "./truescad.js";
+import init, { start_script, step_script, get_shader_source, get_world_transform, get_object_width,
+               rotate, pan, tessellate, tessellate_bodies, get_bodies, get_params,
+               set_param, get_shader_data } from "./truescad.js";
 
 const INITIAL_SCRIPT =
 `-- Left: hollow cube (sphere carved out of a box)
@@ -59,7 +61,9 @@ in vec2 aPos;
 void main() { gl_Position = vec4(aPos, 0.0, 1.0); }`;
 
   let glProgram = null;
+  let glSource = null;
   let uResolution, uTransform, uCameraZ;
+  let glTextures = [];
   let rafId = null;
 
   function compileProgram(fragSrc) {
@@ -109,9 +113,52 @@ void main() { gl_Position = vec4(aPos, 0.0, 1.0); }`;
     rafId = requestAnimationFrame(frame);
   }
 
+  // Upload every parameter value to its uniform in the current program.
+  function setParamUniforms() {
+    gl.useProgram(glProgram);
+    for (const p of get_params()) {
+      const loc = gl.getUniformLocation(glProgram, p.uniform);
+      if (loc) gl.uniform1f(loc, p.value);
+    }
+  }
+
+  // Upload the arrays and 3D textures the shader reads bulk data from, such
+  // as blob centres and voxel grids.
+  function setShaderData() {
+    gl.useProgram(glProgram);
+    for (const t of glTextures) gl.deleteTexture(t);
+    glTextures = [];
+    for (const d of get_shader_data()) {
+      const loc = gl.getUniformLocation(glProgram, d.name);
+      if (!loc) continue;
+      if (!d.dims) {
+        gl.uniform4fv(loc, d.values);
+        continue;
+      }
+      // R32F cannot be filtered; the shader interpolates with texelFetch.
+      const unit = glTextures.length;
+      const tex = gl.createTexture();
+      gl.activeTexture(gl.TEXTURE0 + unit);
+      gl.bindTexture(gl.TEXTURE_3D, tex);
+      gl.texParameteri(gl.TEXTURE_3D, gl.TEXTURE_MIN_FILTER, gl.NEAREST);
+      gl.texParameteri(gl.TEXTURE_3D, gl.TEXTURE_MAG_FILTER, gl.NEAREST);
+      gl.pixelStorei(gl.UNPACK_ALIGNMENT, 1);
+      const [x, y, z] = d.dims;
+      gl.texImage3D(gl.TEXTURE_3D, 0, gl.R32F, x, y, z, 0, gl.RED, gl.FLOAT, d.values);
+      gl.uniform1i(loc, unit);
+      glTextures.push(tex);
+    }
+  }
+
   function onNewObject() {
     const src = get_shader_source();
     if (!src || !gl) return;
+    // Same shader, e.g. after moving a parameter: keep the compiled program.
+    if (glProgram && src === glSource) {
+      setParamUniforms();
+      setShaderData();
+      return;
+    }
 
     if (glProgram) gl.deleteProgram(glProgram);
     try {
@@ -119,6 +166,7 @@ void main() { gl_Position = vec4(aPos, 0.0, 1.0); }`;
       const fragSrc = `#version 300 es\nprecision highp float;\nout vec4 fragColor;\n` +
         src.replace("gl_FragColor", "fragColor");
       glProgram = compileProgram(fragSrc);
+      glSource = src;
     } catch (e) {
       console.error(e);
       setLog("Shader compile error: " + e.message, true);
@@ -128,6 +176,8 @@ void main() { gl_Position = vec4(aPos, 0.0, 1.0); }`;
     uResolution = gl.getUniformLocation(glProgram, "iResolution");
     uTransform  = gl.getUniformLocation(glProgram, "iWorldTransform");
     uCameraZ    = gl.getUniformLocation(glProgram, "iCameraZ");
+    setParamUniforms();
+    setShaderData();
 
     startRenderLoop();
   }
@@ -175,15 +225,142 @@ void main() { gl_Position = vec4(aPos, 0.0, 1.0); }`;
 
   // ── Run ──────────────────────────────────────────────────────────────────
 
-  document.getElementById("btn-run").addEventListener("click", () => {
-    const result = run_script(editor.state.doc.toString());
-    if (result.error) {
-      setLog(result.error, true);
+  // Scripts run in slices of this many Lua instructions, one per task, so
+  // that a slow script does not freeze the page.
+  const SCRIPT_SLICE = 200000;
+  let scriptTimer = null;
+
+  function runScript() {
+    clearTimeout(scriptTimer);
+    start_script(editor.state.doc.toString());
+    const slice = () => {
+      const result = step_script(SCRIPT_SLICE);
+      if (result === null) {
+        scriptTimer = setTimeout(slice, 0);
+        return;
+      }
+      showDiagnostics(result.diagnostics);
+      if (result.error) {
+        const trace = result.diagnostics.flatMap(d => d.traceback);
+        setLog([result.error, ...trace].join("\n  "), true);
+      } else {
+        setLog(result.output);
+        onNewObject();
+        buildParamPanel();
+      }
+    };
+    slice();
+  }
+
+  document.getElementById("btn-run").addEventListener("click", runScript);
+
+  // Underline what the diagnostics point at in the script: from the column
+  // to the end of the name there, or the whole line without a column.
+  function showDiagnostics(diagnostics) {
+    const doc = editor.state.doc;
+    const marks = diagnostics
+      .filter(d => d.chunk === "script" && d.line !== null && d.line <= doc.lines)
+      .map(d => {
+        const line = doc.line(d.line);
+        let from = line.from, to = line.to;
+        if (d.column !== null) {
+          from = Math.min(line.from + d.column - 1, line.to);
+          const name = /^[\w.:]*/.exec(line.text.slice(from - line.from))[0];
+          to = from + Math.max(name.length, 1);
+        }
+        const message = d.argument === null ? d.message : `argument ${d.argument}: ${d.message}`;
+        return { from, to: Math.min(to, line.to), severity: "error", message };
+      });
+    editor.dispatch(setDiagnostics(editor.state, marks));
+  }
+
+  // ── Parameter sliders ────────────────────────────────────────────────────
+
+  const paramPanel = document.getElementById("param-panel");
+
+  // One control per param(), under a heading per group. Live parameters only
+  // update their uniform; the others need the script to run again.
+  function buildParamPanel() {
+    const params = get_params();
+    paramPanel.replaceChildren();
+    paramPanel.hidden = params.length === 0;
+    const groups = new Map();
+    for (const p of params) {
+      const label = Object.assign(document.createElement("label"), {
+        textContent: p.name,
+        title: p.description ?? "",
+      });
+      label.append(...paramControl(p));
+      if (p.group === null) {
+        paramPanel.append(label);
+        continue;
+      }
+      if (!groups.has(p.group)) {
+        const fieldset = document.createElement("fieldset");
+        fieldset.append(Object.assign(document.createElement("legend"), { textContent: p.group }));
+        groups.set(p.group, fieldset);
+      }
+      groups.get(p.group).append(label);
+    }
+    paramPanel.append(...groups.values());
+  }
+
+  function setParam(p, v) {
+    if (set_param(p.name, v)) {
+      gl.useProgram(glProgram);
+      gl.uniform1f(gl.getUniformLocation(glProgram, p.uniform), v);
     } else {
-      setLog(result.output);
-      onNewObject();
+      runScript();
     }
-  });
+  }
+
+  // The input elements for one parameter, by type.
+  function paramControl(p) {
+    switch (p.type) {
+      case "boolean": {
+        const input = Object.assign(document.createElement("input"), {
+          type: "checkbox",
+          checked: p.value,
+        });
+        input.addEventListener("change", () => setParam(p, input.checked));
+        return [input];
+      }
+      case "enum": {
+        const select = document.createElement("select");
+        for (const [i, o] of p.options.entries()) {
+          select.append(Object.assign(document.createElement("option"), {
+            value: i,
+            textContent: o,
+            selected: o === p.value,
+          }));
+        }
+        select.addEventListener("change", () => setParam(p, p.options[select.value]));
+        return [select];
+      }
+      case "string": {
+        const input = Object.assign(document.createElement("input"), { type: "text", value: p.value });
+        input.addEventListener("change", () => setParam(p, input.value));
+        return [input];
+      }
+      default: {
+        const span = Math.max(Math.abs(p.default), 1);
+        const input = Object.assign(document.createElement("input"), {
+          type: "range",
+          min: p.min ?? p.default - span,
+          max: p.max ?? p.default + span,
+          step: p.step ?? "any",
+          value: p.value,
+        });
+        const value = Object.assign(document.createElement("span"), { textContent: p.value });
+        input.addEventListener("input", () => {
+          const v = Number(input.value);
+          value.textContent = v;
+          setParam(p, v);
+        });
+        return [input, value];
+      }
+    }
+  }
 
   // ── Tab toggle ───────────────────────────────────────────────────────────
 
@@ -207,9 +384,19 @@ void main() { gl_Position = vec4(aPos, 0.0, 1.0); }`;
 
   // ── Mesh button ──────────────────────────────────────────────────────────
 
+  // Returns STL bytes, or null after logging why the object cannot be meshed.
+  function tryTessellate() {
+    try {
+      return tessellate(undefined);
+    } catch (e) {
+      setLog(e.message ?? String(e), true);
+      return null;
+    }
+  }
+
   document.getElementById("btn-mesh").addEventListener("click", () => {
-    const stlBytes = tessellate();
-    if (!stlBytes) { setLog("No object — run the script first.", true); return; }
+    const stlBytes = tryTessellate();
+    if (!stlBytes) return;
 
     initThree();
     if (three.mesh) three.scene.remove(three.mesh);
@@ -239,15 +426,32 @@ void main() { gl_Position = vec4(aPos, 0.0, 1.0); }`;
 
   // ── Export STL ───────────────────────────────────────────────────────────
 
-  document.getElementById("btn-export").addEventListener("click", () => {
-    const stlBytes = tessellate();
-    if (!stlBytes) { setLog("No object — run the script first.", true); return; }
+  function download(stlBytes, filename) {
     const url = URL.createObjectURL(new Blob([stlBytes], { type: "application/octet-stream" }));
-    const a = Object.assign(document.createElement("a"), { href: url, download: "model.stl" });
+    const a = Object.assign(document.createElement("a"), { href: url, download: filename });
     document.body.appendChild(a);
     a.click();
     document.body.removeChild(a);
     URL.revokeObjectURL(url);
+  }
+
+  // A script with several named bodies exports one STL file per body.
+  document.getElementById("btn-export").addEventListener("click", () => {
+    if (get_bodies().length > 1) {
+      let meshes;
+      try {
+        meshes = tessellate_bodies();
+      } catch (e) {
+        const message = e.message ?? String(e);
+        setLog(e.body === undefined ? message : `${e.body}: ${message}`, true);
+        return;
+      }
+      for (const { name, stl } of meshes) download(stl, `${name}.stl`);
+      return;
+    }
+    const stlBytes = tryTessellate();
+    if (!stlBytes) return;
+    download(stlBytes, "model.stl");
   });
 
   // ── Drag to rotate (left) / pan (right) on preview canvas ────────────────